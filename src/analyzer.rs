@@ -6,16 +6,21 @@
 use anyhow::{Context, Result};
 use colored::Colorize;
 use image::{DynamicImage, GrayImage};
-use rxing::{
-    BinaryBitmap, DecodeHints, Exceptions, Luma8LuminanceSource, Reader, common::HybridBinarizer,
-    qrcode::QRCodeReader,
-};
+use rxing::{DecodeHints, Exceptions};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::path::Path;
 
-use crate::preprocessor::ImagePreprocessor;
+use crate::config::Config;
+use crate::engine::{
+    EngineRegistry, QrEngine, rqrr_segments, rxing_decode_multiple, rxing_qr_symbol,
+};
+use crate::metadata::{EccLevel, SymbolMetadata};
+use crate::payload::{Charset, Payload};
+use crate::preprocessor::{ImagePreprocessor, MAX_DIMENSION, Pipeline};
 
 /// Analysis result for a single engine
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct EngineAnalysis {
     pub engine_name: String,
     pub grids_detected: usize,
@@ -25,12 +30,14 @@ pub struct EngineAnalysis {
 }
 
 /// Analysis result for a single detected grid
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct GridAnalysis {
     pub grid_index: usize,
     #[allow(dead_code)]
+    #[serde(skip)]
     pub version: Option<u32>,
     #[allow(dead_code)]
+    #[serde(skip)]
     pub module_size: Option<(u32, u32)>,
     pub decode_success: bool,
     pub error_type: Option<String>,
@@ -39,7 +46,7 @@ pub struct GridAnalysis {
 }
 
 /// Detailed QR code analysis result
-#[derive(Debug)]
+#[derive(Debug, Serialize, JsonSchema)]
 pub struct AnalysisReport {
     pub file_path: String,
     pub image_size: (u32, u32),
@@ -50,11 +57,32 @@ pub struct AnalysisReport {
 }
 
 /// QR code debug analyzer
-pub struct QrAnalyzer;
+pub struct QrAnalyzer {
+    /// Engines to analyze, in order
+    registry: EngineRegistry,
+    /// Hints for the detailed rxing analysis, matching the `rxing` engine
+    rxing_hints: DecodeHints,
+    /// Character set for payloads that have no ECI and are not UTF-8
+    charset: Option<Charset>,
+    /// Preprocessed variants to generate
+    pipeline: Pipeline,
+}
 
 impl QrAnalyzer {
-    pub fn new() -> Self {
-        Self
+    /// Create an analyzer that runs the engines in `registry`
+    pub fn new(registry: EngineRegistry, config: &Config) -> Result<Self> {
+        Ok(Self {
+            registry,
+            rxing_hints: config.rxing.to_decode_hints(),
+            charset: config.charset()?,
+            pipeline: config.pipeline()?,
+        })
+    }
+
+    /// Displayable content of a payload, decoded the way the scanner does
+    fn content(&self, mut payload: Payload, metadata: &SymbolMetadata) -> String {
+        payload.decode_text(metadata, self.charset);
+        payload.display().into_owned()
     }
 
     /// Analyze a single image file and produce detailed debug report
@@ -68,15 +96,10 @@ impl QrAnalyzer {
         let height = img.height();
 
         // Resize large images
-        let working_img = if width.max(height) > 2000 {
-            let scale = 2000.0 / width.max(height) as f32;
-            ImagePreprocessor::resize(&img, scale)
-        } else {
-            img.clone()
-        };
+        let working_img = ImagePreprocessor::limit_size(&img, MAX_DIMENSION);
 
         // Generate variants
-        let variants = ImagePreprocessor::generate_variants(&working_img);
+        let variants = self.pipeline.run(&working_img);
         let variants_tested = variants.len();
 
         let mut engine_analyses = Vec::new();
@@ -84,26 +107,20 @@ impl QrAnalyzer {
 
         // Analyze with first variant only to avoid excessive output
         if let Some((variant_name, gray_img)) = variants.first() {
-            // rqrr analysis
-            let rqrr_analysis = self.analyze_with_rqrr(gray_img, variant_name);
-            if rqrr_analysis.success {
-                overall_success = true;
-            }
-            engine_analyses.push(rqrr_analysis);
-
-            // quircs analysis
-            let quircs_analysis = self.analyze_with_quircs(gray_img, variant_name);
-            if quircs_analysis.success {
-                overall_success = true;
-            }
-            engine_analyses.push(quircs_analysis);
-
-            // rxing analysis
-            let rxing_analysis = self.analyze_with_rxing(gray_img, &working_img, variant_name);
-            if rxing_analysis.success {
-                overall_success = true;
+            for engine in self.registry.engines() {
+                // Engines with a dedicated analysis report per-grid failure causes;
+                // any other engine is analyzed through its generic detect path
+                let analysis = match engine.name() {
+                    "rqrr" => self.analyze_with_rqrr(gray_img, variant_name),
+                    "quircs" => self.analyze_with_quircs(gray_img, variant_name),
+                    "rxing" => self.analyze_with_rxing(gray_img, &working_img, variant_name),
+                    _ => self.analyze_with_engine(engine, gray_img, variant_name),
+                };
+                if analysis.success {
+                    overall_success = true;
+                }
+                engine_analyses.push(analysis);
             }
-            engine_analyses.push(rxing_analysis);
         }
 
         // Generate recommendations based on failures
@@ -129,9 +146,26 @@ impl QrAnalyzer {
         let mut any_success = false;
 
         for (i, grid) in grids.iter().enumerate() {
-            let (decode_success, error_type, error_detail, content) = match grid.decode() {
-                Ok((meta, content)) => {
+            // Decode to raw bytes like the scanner does, and read the ECI and
+            // segment modes rqrr skips, so the text is decoded as it would be
+            // there
+            let mut bytes = Vec::new();
+            let decoded = grid.decode_to(&mut bytes);
+            let (decode_success, error_type, error_detail, content) = match decoded {
+                Ok(meta) => {
                     any_success = true;
+                    let version = meta.version.0 as u32;
+                    let mut metadata = SymbolMetadata {
+                        version: Some(version),
+                        ecc_level: EccLevel::from_format_bits(meta.ecc_level),
+                        mask: Some(meta.mask as u8),
+                        ..SymbolMetadata::default()
+                    };
+                    if let Some(segments) = rqrr_segments(&grid.grid, version) {
+                        metadata.modes = Some(segments.modes);
+                        metadata.eci = segments.eci;
+                        metadata.structured_append = segments.structured_append;
+                    }
                     (
                         true,
                         None,
@@ -141,7 +175,7 @@ impl QrAnalyzer {
                             meta.ecc_level,
                             meta.version.0 * 4 + 17
                         ),
-                        Some(content),
+                        Some(self.content(Payload::from_bytes(bytes), &metadata)),
                     )
                 }
                 Err(e) => {
@@ -231,8 +265,9 @@ impl QrAnalyzer {
             ),
             rqrr::DeQRError::EncodingError => (
                 "EncodingError".to_string(),
-                "编码错误: 解码后的数据不是有效的 UTF-8。\n\
-                 可能原因: QR 码使用了非 UTF-8 编码 (如 Shift-JIS)。"
+                "编码错误: rqrr 无法把数据转换为 UTF-8 文本。\n\
+                 本工具按原始字节读取数据，再依据 ECI、Kanji/Hanzi 段或 --charset 选择字符集，\
+                 因此不应出现此错误；出现时说明 rqrr 的行为有变。"
                     .to_string(),
             ),
             rqrr::DeQRError::IoError => (
@@ -261,9 +296,11 @@ impl QrAnalyzer {
                 Ok(code) => match code.decode() {
                     Ok(decoded) => {
                         any_success = true;
-                        let text = std::str::from_utf8(&decoded.payload)
-                            .unwrap_or("<binary data>")
-                            .to_string();
+                        let metadata = SymbolMetadata {
+                            eci: decoded.eci.map(|eci| eci as u32),
+                            ..SymbolMetadata::default()
+                        };
+                        let text = self.content(Payload::from_bytes(decoded.payload), &metadata);
                         (
                             true,
                             None,
@@ -375,28 +412,29 @@ impl QrAnalyzer {
         _img: &DynamicImage,
         variant_name: &str,
     ) -> EngineAnalysis {
-        let width = gray_img.width();
-        let height = gray_img.height();
-
-        let luminance_source = Luma8LuminanceSource::new(gray_img.as_raw().clone(), width, height);
-        let mut bitmap = BinaryBitmap::new(HybridBinarizer::new(luminance_source));
-        let hints = DecodeHints::default();
-        let mut reader = QRCodeReader::new();
-
         let mut decode_results = Vec::new();
         let (grids_detected, any_success, summary) =
-            match reader.decode_with_hints(&mut bitmap, &hints) {
-                Ok(result) => {
-                    decode_results.push(GridAnalysis {
-                        grid_index: 0,
-                        version: None,
-                        module_size: None,
-                        decode_success: true,
-                        error_type: None,
-                        error_detail: format!("Format: {:?}", result.getBarcodeFormat()),
-                        content: Some(result.getText().to_string()),
-                    });
-                    (1, true, "Successfully decoded 1 QR code".to_string())
+            match rxing_decode_multiple(gray_img, &self.rxing_hints) {
+                Ok(results) => {
+                    for (i, result) in results.iter().enumerate() {
+                        decode_results.push(GridAnalysis {
+                            grid_index: i,
+                            version: None,
+                            module_size: None,
+                            decode_success: true,
+                            error_type: None,
+                            error_detail: format!("Format: {:?}", result.getBarcodeFormat()),
+                            content: Some({
+                                let symbol = rxing_qr_symbol(result);
+                                self.content(symbol.payload, &symbol.metadata)
+                            }),
+                        });
+                    }
+                    (
+                        results.len(),
+                        true,
+                        format!("Successfully decoded {} QR code(s)", results.len()),
+                    )
                 }
                 Err(e) => {
                     let (err_type, detail) = self.analyze_rxing_error(&e);
@@ -409,7 +447,9 @@ impl QrAnalyzer {
                         error_detail: detail,
                         content: None,
                     });
-                    (0, false, format!("Detection failed: {}", err_type))
+                    // Anything past "not found" means a symbol was located but not decoded
+                    let located = usize::from(!matches!(e, Exceptions::NotFoundException(_)));
+                    (located, false, format!("Detection failed: {}", err_type))
                 }
             };
 
@@ -422,6 +462,71 @@ impl QrAnalyzer {
         }
     }
 
+    /// Generic analysis for engines without a dedicated error breakdown
+    fn analyze_with_engine(
+        &self,
+        engine: &dyn QrEngine,
+        gray_img: &GrayImage,
+        variant_name: &str,
+    ) -> EngineAnalysis {
+        let mut decode_results = Vec::new();
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| engine.detect(gray_img)));
+
+        let (any_success, summary) = match result {
+            Ok(Ok(codes)) if !codes.is_empty() => {
+                for (i, symbol) in codes.iter().enumerate() {
+                    decode_results.push(GridAnalysis {
+                        grid_index: i,
+                        version: None,
+                        module_size: None,
+                        decode_success: true,
+                        error_type: None,
+                        error_detail: String::new(),
+                        content: Some(self.content(symbol.payload.clone(), &symbol.metadata)),
+                    });
+                }
+                (
+                    true,
+                    format!("Successfully decoded {} QR code(s)", decode_results.len()),
+                )
+            }
+            Ok(Ok(_)) => (false, "No QR code decoded".to_string()),
+            Ok(Err(e)) => {
+                decode_results.push(GridAnalysis {
+                    grid_index: 0,
+                    version: None,
+                    module_size: None,
+                    decode_success: false,
+                    error_type: Some("EngineError".to_string()),
+                    error_detail: format!("{:#}", e),
+                    content: None,
+                });
+                (false, "Detection failed: EngineError".to_string())
+            }
+            Err(_) => {
+                decode_results.push(GridAnalysis {
+                    grid_index: 0,
+                    version: None,
+                    module_size: None,
+                    decode_success: false,
+                    error_type: Some("Panic".to_string()),
+                    error_detail: "引擎内部发生 panic (很可能是库的 bug)。".to_string(),
+                    content: None,
+                });
+                (false, "Detection failed: Panic".to_string())
+            }
+        };
+
+        EngineAnalysis {
+            engine_name: format!("{} ({})", engine.name(), variant_name),
+            grids_detected: decode_results.iter().filter(|r| r.decode_success).count(),
+            decode_results,
+            success: any_success,
+            summary,
+        }
+    }
+
     /// Analyze rxing error
     fn analyze_rxing_error(&self, error: &Exceptions) -> (String, String) {
         match error {
@@ -0,0 +1,554 @@
+//! Baselines and regression detection
+//!
+//! A baseline is a condensed record of a scan or benchmark run: per file and
+//! engine whether the file passed and how long the engine took, plus the
+//! recall per engine when the run had ground truth. Comparing a new run with
+//! a saved baseline shows what an engine upgrade changed.
+//!
+//! A file passes for an engine if the engine decoded exactly the expected
+//! payloads, or, without ground truth, if it found any code. Timing changes
+//! are tested for significance: with several samples per file (benchmarks),
+//! a Mann-Whitney U test on the per-iteration totals; with one (scans), a
+//! Wilcoxon signed-rank test pairing the files.
+
+use anyhow::{Context, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::bench::{BenchReport, Summary};
+use crate::ground_truth::AccuracyReport;
+use crate::scanner::ScanResult;
+use crate::timer::ScanStats;
+
+/// p-value below which a timing change counts as significant
+pub const SIGNIFICANCE_LEVEL: f64 = 0.05;
+
+/// A saved run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Baseline {
+    /// qr-tester version that wrote the baseline
+    pub version: String,
+    pub files: Vec<BaselineFile>,
+    /// Scores against the ground truth, if the run had any
+    pub accuracy: Option<AccuracyReport>,
+}
+
+/// One file of a saved run; `engines` is empty if the file failed to load
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineFile {
+    pub file_path: String,
+    pub engines: Vec<BaselineEngine>,
+}
+
+impl BaselineFile {
+    fn engine(&self, engine_name: &str) -> Option<&BaselineEngine> {
+        self.engines.iter().find(|e| e.engine_name == engine_name)
+    }
+}
+
+/// One engine on one file of a saved run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BaselineEngine {
+    pub engine_name: String,
+    /// Decoded exactly the expected payloads, or any code without ground truth
+    pub passed: bool,
+    pub qr_codes: usize,
+    /// Time over all variants, one sample per measured run
+    pub samples_ms: Vec<f64>,
+}
+
+impl Baseline {
+    /// Condense the results of a scan
+    pub fn from_scan(results: &[ScanResult], stats: &ScanStats) -> Self {
+        let files = results
+            .iter()
+            .map(|result| BaselineFile {
+                file_path: result.file_path.display().to_string(),
+                engines: result
+                    .engine_results
+                    .iter()
+                    .map(|engine| BaselineEngine {
+                        engine_name: engine.engine_name.clone(),
+                        passed: match &engine.accuracy {
+                            Some(accuracy) => accuracy.exact(),
+                            None => !engine.qr_codes.is_empty(),
+                        },
+                        qr_codes: engine.qr_codes.len(),
+                        samples_ms: vec![engine.duration_ms],
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            files,
+            accuracy: stats.accuracy.clone(),
+        }
+    }
+
+    /// Condense the results of a benchmark; benchmarks have no ground truth,
+    /// so a file passes if an engine found any code
+    pub fn from_bench(report: &BenchReport) -> Self {
+        let files = report
+            .files
+            .iter()
+            .map(|file| BaselineFile {
+                file_path: file.file_path.clone(),
+                engines: file
+                    .engines
+                    .iter()
+                    .map(|engine| BaselineEngine {
+                        engine_name: engine.engine_name.clone(),
+                        passed: engine.qr_codes > 0,
+                        qr_codes: engine.qr_codes,
+                        samples_ms: engine.samples_ms.clone(),
+                    })
+                    .collect(),
+            })
+            .chain(report.failed_files.iter().map(|failed| BaselineFile {
+                file_path: failed.file_path.clone(),
+                engines: Vec::new(),
+            }))
+            .collect();
+
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            files,
+            accuracy: None,
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read baseline: {}", path.display()))?;
+        serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse baseline: {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self).context("Failed to serialize baseline")?;
+        std::fs::write(path, json)
+            .with_context(|| format!("Failed to write baseline: {}", path.display()))
+    }
+
+    fn engine_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = Vec::new();
+        for engine in self.files.iter().flat_map(|f| &f.engines) {
+            if !names.contains(&engine.engine_name.as_str()) {
+                names.push(&engine.engine_name);
+            }
+        }
+        names
+    }
+
+    fn recall(&self, engine_name: &str) -> Option<f64> {
+        self.accuracy
+            .as_ref()?
+            .engines
+            .iter()
+            .find(|e| e.engine_name == engine_name)?
+            .accuracy
+            .recall
+    }
+}
+
+/// Changes of one engine between a baseline and a run
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct EngineComparison {
+    pub engine_name: String,
+    pub recall_before: Option<f64>,
+    pub recall_after: Option<f64>,
+    /// Files that passed in the baseline and fail now
+    pub newly_failing: Vec<String>,
+    /// Files that failed in the baseline and pass now
+    pub newly_passing: Vec<String>,
+    /// Files timed in both runs
+    pub files_timed: usize,
+    /// Median per-iteration time over the files timed in both runs
+    pub time_before_ms: Option<f64>,
+    pub time_after_ms: Option<f64>,
+    /// Relative time change in percent
+    pub time_change_pct: Option<f64>,
+    /// Two-sided p-value of the timing change; `None` if there are too few samples
+    pub p_value: Option<f64>,
+    /// Why this engine counts as regressed; empty if it does not
+    pub regressions: Vec<String>,
+}
+
+/// Result of comparing a run with a baseline
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct Comparison {
+    /// Largest tolerated recall drop, in percentage points
+    pub recall_threshold_points: f64,
+    /// Largest tolerated significant slowdown, in percent
+    pub slowdown_threshold_pct: f64,
+    pub engines: Vec<EngineComparison>,
+    /// Engines in the baseline that did not run
+    pub only_in_baseline: Vec<String>,
+    /// Engines that ran but are not in the baseline
+    pub only_in_run: Vec<String>,
+}
+
+impl Comparison {
+    pub fn regressed(&self) -> bool {
+        self.engines.iter().any(|e| !e.regressions.is_empty())
+    }
+}
+
+/// Compare `run` with `baseline`
+///
+/// An engine regresses if any file newly fails, if its recall drops by more
+/// than `recall_threshold_points` percentage points, or if it is
+/// significantly slower by more than `slowdown_threshold_pct` percent.
+pub fn compare(
+    baseline: &Baseline,
+    run: &Baseline,
+    recall_threshold_points: f64,
+    slowdown_threshold_pct: f64,
+) -> Comparison {
+    let before_names = baseline.engine_names();
+    let after_names = run.engine_names();
+
+    let engines = after_names
+        .iter()
+        .filter(|name| before_names.contains(name))
+        .map(|name| {
+            compare_engine(
+                baseline,
+                run,
+                name,
+                recall_threshold_points,
+                slowdown_threshold_pct,
+            )
+        })
+        .collect();
+
+    Comparison {
+        recall_threshold_points,
+        slowdown_threshold_pct,
+        engines,
+        only_in_baseline: before_names
+            .iter()
+            .filter(|name| !after_names.contains(name))
+            .map(|name| name.to_string())
+            .collect(),
+        only_in_run: after_names
+            .iter()
+            .filter(|name| !before_names.contains(name))
+            .map(|name| name.to_string())
+            .collect(),
+    }
+}
+
+fn compare_engine(
+    baseline: &Baseline,
+    run: &Baseline,
+    engine_name: &str,
+    recall_threshold_points: f64,
+    slowdown_threshold_pct: f64,
+) -> EngineComparison {
+    // Files in both runs, with this engine's result on each side (None if
+    // the file failed to load)
+    let pairs: Vec<(&str, Option<&BaselineEngine>, Option<&BaselineEngine>)> = baseline
+        .files
+        .iter()
+        .filter_map(|before| {
+            let after = run.files.iter().find(|f| f.file_path == before.file_path)?;
+            Some((
+                before.file_path.as_str(),
+                before.engine(engine_name),
+                after.engine(engine_name),
+            ))
+        })
+        .collect();
+
+    let passed = |engine: Option<&BaselineEngine>| engine.is_some_and(|e| e.passed);
+    let newly_failing: Vec<String> = pairs
+        .iter()
+        .filter(|(_, before, after)| passed(*before) && !passed(*after))
+        .map(|(path, _, _)| path.to_string())
+        .collect();
+    let newly_passing: Vec<String> = pairs
+        .iter()
+        .filter(|(_, before, after)| !passed(*before) && passed(*after))
+        .map(|(path, _, _)| path.to_string())
+        .collect();
+
+    let timed: Vec<(&BaselineEngine, &BaselineEngine)> = pairs
+        .iter()
+        .filter_map(|(_, before, after)| Some(((*before)?, (*after)?)))
+        .filter(|(before, after)| !before.samples_ms.is_empty() && !after.samples_ms.is_empty())
+        .collect();
+    let timing = compare_timing(&timed);
+
+    let recall_before = baseline.recall(engine_name);
+    let recall_after = run.recall(engine_name);
+
+    let mut regressions = Vec::new();
+    if !newly_failing.is_empty() {
+        regressions.push(format!("{} newly failing files", newly_failing.len()));
+    }
+    if let (Some(before), Some(after)) = (recall_before, recall_after)
+        && (before - after) * 100.0 > recall_threshold_points
+    {
+        regressions.push(format!(
+            "recall dropped {:.1} points",
+            (before - after) * 100.0
+        ));
+    }
+    if let Some(TimingChange {
+        change_pct,
+        p_value: Some(p),
+        ..
+    }) = timing
+        && change_pct > slowdown_threshold_pct
+        && p < SIGNIFICANCE_LEVEL
+    {
+        regressions.push(format!("{:.1}% slower (p = {:.3})", change_pct, p));
+    }
+
+    EngineComparison {
+        engine_name: engine_name.to_string(),
+        recall_before,
+        recall_after,
+        newly_failing,
+        newly_passing,
+        files_timed: timed.len(),
+        time_before_ms: timing.map(|t| t.before_ms),
+        time_after_ms: timing.map(|t| t.after_ms),
+        time_change_pct: timing.map(|t| t.change_pct),
+        p_value: timing.and_then(|t| t.p_value),
+        regressions,
+    }
+}
+
+/// Timing change of one engine over the files timed in both runs
+#[derive(Debug, Clone, Copy)]
+struct TimingChange {
+    /// Median per-iteration time, summed over the files
+    before_ms: f64,
+    after_ms: f64,
+    change_pct: f64,
+    p_value: Option<f64>,
+}
+
+fn compare_timing(timed: &[(&BaselineEngine, &BaselineEngine)]) -> Option<TimingChange> {
+    if timed.is_empty() {
+        return None;
+    }
+
+    let before: Vec<&[f64]> = timed.iter().map(|(b, _)| b.samples_ms.as_slice()).collect();
+    let after: Vec<&[f64]> = timed.iter().map(|(_, a)| a.samples_ms.as_slice()).collect();
+    let before_totals = iteration_totals(&before);
+    let after_totals = iteration_totals(&after);
+
+    let before_ms = Summary::of(&before_totals).median_ms;
+    let after_ms = Summary::of(&after_totals).median_ms;
+    let change_pct = if before_ms > 0.0 {
+        (after_ms - before_ms) / before_ms * 100.0
+    } else {
+        0.0
+    };
+
+    let p_value = if before_totals.len() >= 2 && after_totals.len() >= 2 {
+        mann_whitney(&before_totals, &after_totals)
+    } else {
+        let diffs: Vec<f64> = before
+            .iter()
+            .zip(&after)
+            .map(|(b, a)| Summary::of(a).median_ms - Summary::of(b).median_ms)
+            .collect();
+        wilcoxon_signed_rank(&diffs)
+    };
+
+    Some(TimingChange {
+        before_ms,
+        after_ms,
+        change_pct,
+        p_value,
+    })
+}
+
+/// Sample `i` of every file summed, for each iteration all files have
+fn iteration_totals(files: &[&[f64]]) -> Vec<f64> {
+    let iterations = files.iter().map(|s| s.len()).min().unwrap_or(0);
+    (0..iterations)
+        .map(|i| files.iter().map(|s| s[i]).sum())
+        .collect()
+}
+
+/// Two-sided p-value of the Mann-Whitney U test, by the normal approximation
+/// with tie and continuity corrections
+fn mann_whitney(a: &[f64], b: &[f64]) -> Option<f64> {
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+    let values: Vec<f64> = a.iter().chain(b).copied().collect();
+    let (ranks, ties) = rank(&values);
+
+    let rank_sum: f64 = ranks[..a.len()].iter().sum();
+    let u = rank_sum - n1 * (n1 + 1.0) / 2.0;
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12.0 * ((n + 1.0) - ties / (n * (n - 1.0)));
+    if variance <= 0.0 {
+        return None;
+    }
+    let z = ((u - n1 * n2 / 2.0).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(2.0 * (1.0 - normal_cdf(z)))
+}
+
+/// Two-sided p-value of the Wilcoxon signed-rank test on paired
+/// differences, by the normal approximation; zero differences are dropped
+fn wilcoxon_signed_rank(diffs: &[f64]) -> Option<f64> {
+    let nonzero: Vec<f64> = diffs.iter().copied().filter(|d| *d != 0.0).collect();
+    if nonzero.is_empty() {
+        return None;
+    }
+    let n = nonzero.len() as f64;
+    let magnitudes: Vec<f64> = nonzero.iter().map(|d| d.abs()).collect();
+    let (ranks, ties) = rank(&magnitudes);
+
+    let positive: f64 = ranks
+        .iter()
+        .zip(&nonzero)
+        .filter(|(_, d)| **d > 0.0)
+        .map(|(r, _)| r)
+        .sum();
+    let variance = n * (n + 1.0) * (2.0 * n + 1.0) / 24.0 - ties / 48.0;
+    if variance <= 0.0 {
+        return None;
+    }
+    let z = ((positive - n * (n + 1.0) / 4.0).abs() - 0.5).max(0.0) / variance.sqrt();
+    Some(2.0 * (1.0 - normal_cdf(z)))
+}
+
+/// Ranks from 1, ties sharing their average rank, and the tie correction
+/// term: the sum of t³ - t over groups of t tied values
+fn rank(values: &[f64]) -> (Vec<f64>, f64) {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut ties = 0.0;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let average = (start + end + 1) as f64 / 2.0;
+        for &idx in &order[start..end] {
+            ranks[idx] = average;
+        }
+        let t = (end - start) as f64;
+        ties += t * t * t - t;
+        start = end;
+    }
+    (ranks, ties)
+}
+
+/// Standard normal CDF, via the Abramowitz-Stegun erf approximation
+fn normal_cdf(z: f64) -> f64 {
+    let x = z.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * x);
+    let poly = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erf = 1.0 - poly * (-x * x).exp();
+    if z >= 0.0 {
+        0.5 * (1.0 + erf)
+    } else {
+        0.5 * (1.0 - erf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    /// A run of one file and engine with these samples
+    fn run(samples_ms: Vec<f64>) -> Baseline {
+        Baseline {
+            version: String::new(),
+            files: vec![BaselineFile {
+                file_path: "a.png".to_string(),
+                engines: vec![BaselineEngine {
+                    engine_name: "rqrr".to_string(),
+                    passed: true,
+                    qr_codes: 1,
+                    samples_ms,
+                }],
+            }],
+            accuracy: None,
+        }
+    }
+
+    #[test]
+    fn ranks_share_ties() {
+        let (ranks, ties) = rank(&[3.0, 1.0, 2.0, 2.0, 3.0, 3.0]);
+        assert_eq!(ranks, [5.0, 1.0, 2.5, 2.5, 5.0, 5.0]);
+        // One pair (2³ - 2) and one triple (3³ - 3)
+        assert_eq!(ties, 30.0);
+    }
+
+    #[test]
+    fn mann_whitney_without_ties() {
+        // Disjoint samples: U = 0, σ² = 25/12 · 11, z = 12/4.787
+        let a = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let b = [6.0, 7.0, 8.0, 9.0, 10.0];
+        assert_close(mann_whitney(&a, &b).unwrap(), 0.012186);
+        assert_close(mann_whitney(&b, &a).unwrap(), 0.012186);
+        assert_close(mann_whitney(&a, &a).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn mann_whitney_with_ties() {
+        // U = 5; a triple and three pairs of ties give σ² = 30/12 · (12 - 42/110)
+        let a = [1.0, 2.0, 2.0, 3.0, 4.0];
+        let b = [2.0, 3.0, 4.0, 5.0, 5.0, 6.0];
+        assert_close(mann_whitney(&a, &b).unwrap(), 0.077947);
+        // All values tied: no spread to test
+        assert_eq!(mann_whitney(&[1.0, 1.0], &[1.0, 1.0]), None);
+    }
+
+    #[test]
+    fn wilcoxon_without_ties() {
+        // All eight differences positive: W+ = 36, σ² = 8 · 9 · 17 / 24
+        let diffs = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0];
+        assert_close(wilcoxon_signed_rank(&diffs).unwrap(), 0.014266);
+    }
+
+    #[test]
+    fn wilcoxon_with_ties_and_zeros() {
+        // The ten pairs of Wikipedia's example: one zero difference is
+        // dropped, |−5| is tied twice, W+ = 18 and σ² = 9 · 10 · 19 / 24 - 6/48
+        let diffs = [-15.0, 7.0, -5.0, -20.0, 0.0, 9.0, -17.0, 12.0, -5.0, 10.0];
+        assert_close(wilcoxon_signed_rank(&diffs).unwrap(), 0.635289);
+        assert_eq!(wilcoxon_signed_rank(&[0.0, 0.0]), None);
+    }
+
+    #[test]
+    fn slowdown_threshold_is_separate() {
+        let before: Vec<f64> = (0..10).map(|i| 10.0 + i as f64 / 10.0).collect();
+        let after: Vec<f64> = before.iter().map(|ms| ms * 1.1).collect();
+        let (before, after) = (run(before), run(after));
+
+        let comparison = compare(&before, &after, 0.0, 15.0);
+        assert!(!comparison.regressed());
+        let engine = &comparison.engines[0];
+        assert_close(engine.time_change_pct.unwrap(), 10.0);
+        assert!(engine.p_value.unwrap() < SIGNIFICANCE_LEVEL);
+
+        let comparison = compare(&before, &after, 15.0, 5.0);
+        assert_eq!(comparison.engines[0].regressions.len(), 1);
+    }
+}
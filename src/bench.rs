@@ -0,0 +1,439 @@
+//! Repeated timing of detection engines
+//!
+//! Each image is loaded and preprocessed once; every engine then runs over
+//! all variants for a number of warmup runs, which are discarded, and a
+//! number of measured iterations. One sample is one pass over all variants,
+//! so samples compare directly with an engine's time in a scan.
+//!
+//! Absolute times only compare on the same machine. To compare across
+//! machines, the report records the build and host, and times a fixed
+//! reference workload; medians are also given relative to it.
+
+use anyhow::{Context, Result, bail};
+use image::{DynamicImage, GrayImage, Luma};
+use log::{debug, error, info};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::engine::{DecodedSymbol, EngineRegistry, QrEngine};
+use crate::preprocessor::{ImagePreprocessor, MAX_DIMENSION, Pipeline};
+use crate::scanner::collect_images;
+use crate::timer::Timer;
+
+/// Runs of the reference workload; the median is used
+const REFERENCE_RUNS: usize = 5;
+
+/// How many times each engine runs on each image
+#[derive(Debug, Clone, Copy)]
+pub struct BenchSettings {
+    /// Unmeasured runs before the first sample
+    pub warmup: usize,
+    /// Measured runs
+    pub iterations: usize,
+}
+
+/// Distribution of a set of samples, in milliseconds
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Summary {
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub mean_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Sample standard deviation
+    pub std_dev_ms: f64,
+    /// Indices of samples beyond Tukey's fences (1.5 IQR outside the quartiles)
+    pub outliers: Vec<usize>,
+}
+
+impl Summary {
+    /// Summarize `samples`, which must not be empty
+    pub fn of(samples: &[f64]) -> Self {
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(f64::total_cmp);
+
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let std_dev = if samples.len() > 1 {
+            (samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()
+        } else {
+            0.0
+        };
+
+        let (q1, q3) = (percentile(&sorted, 25.0), percentile(&sorted, 75.0));
+        let (low, high) = (q1 - 1.5 * (q3 - q1), q3 + 1.5 * (q3 - q1));
+        let outliers = samples
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s < low || **s > high)
+            .map(|(i, _)| i)
+            .collect();
+
+        Self {
+            min_ms: sorted[0],
+            median_ms: percentile(&sorted, 50.0),
+            mean_ms: mean,
+            p95_ms: percentile(&sorted, 95.0),
+            p99_ms: percentile(&sorted, 99.0),
+            std_dev_ms: std_dev,
+            outliers,
+        }
+    }
+}
+
+/// Percentile of sorted samples, interpolating between neighbours
+fn percentile(sorted: &[f64], p: f64) -> f64 {
+    let rank = p / 100.0 * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// Where the benchmark ran
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Environment {
+    /// qr-tester version
+    pub version: String,
+    /// `release` or `debug`; debug builds are many times slower
+    pub build: String,
+    pub os: String,
+    pub arch: String,
+    /// CPU model, where the OS reports it
+    pub cpu: Option<String>,
+    /// Logical CPUs
+    pub cpus: usize,
+    /// Median time to preprocess a fixed synthetic image on this machine
+    pub reference_ms: f64,
+}
+
+impl Environment {
+    fn detect() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            build: if cfg!(debug_assertions) {
+                "debug"
+            } else {
+                "release"
+            }
+            .to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            cpu: cpu_model(),
+            cpus: std::thread::available_parallelism().map_or(1, |n| n.get()),
+            reference_ms: reference_ms(),
+        }
+    }
+}
+
+/// CPU model from /proc/cpuinfo (Linux only)
+fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, model)| model.trim().to_string())
+}
+
+/// Time the reference workload: generating the default variants of a
+/// 512×512 image, whatever pipeline is being benchmarked
+fn reference_ms() -> f64 {
+    let pipeline = Pipeline::default();
+    let img = DynamicImage::ImageLuma8(GrayImage::from_fn(512, 512, |x, y| {
+        Luma([((x ^ y) & 0xff) as u8])
+    }));
+    let samples: Vec<f64> = (0..REFERENCE_RUNS)
+        .map(|_| {
+            let timer = Timer::start();
+            std::hint::black_box(pipeline.run(&img));
+            timer.elapsed().as_secs_f64() * 1000.0
+        })
+        .collect();
+    Summary::of(&samples).median_ms
+}
+
+/// Timings of one engine on one image, or summed over all images
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EngineBench {
+    pub engine_name: String,
+    /// Distinct symbols found in the first measured iteration
+    pub qr_codes: usize,
+    /// Measured iterations in which a variant errored or panicked
+    pub failures: usize,
+    /// Time of each measured iteration over all variants, in run order
+    pub samples_ms: Vec<f64>,
+    pub summary: Summary,
+    /// Median over the environment's `reference_ms`
+    pub relative_median: f64,
+}
+
+impl EngineBench {
+    fn new(
+        engine_name: &str,
+        qr_codes: usize,
+        failures: usize,
+        samples_ms: Vec<f64>,
+        reference_ms: f64,
+    ) -> Self {
+        let summary = Summary::of(&samples_ms);
+        Self {
+            engine_name: engine_name.to_string(),
+            qr_codes,
+            failures,
+            relative_median: summary.median_ms / reference_ms,
+            samples_ms,
+            summary,
+        }
+    }
+}
+
+/// Timings of all engines on one image
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FileBench {
+    pub file_path: String,
+    /// Preprocessed variants each sample runs over
+    pub variants: usize,
+    pub engines: Vec<EngineBench>,
+}
+
+/// A file left out of the benchmark
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FailedFile {
+    pub file_path: String,
+    pub error: String,
+}
+
+/// Result of a benchmark run
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct BenchReport {
+    pub environment: Environment,
+    pub warmup: usize,
+    pub iterations: usize,
+    pub files: Vec<FileBench>,
+    /// Files that could not be loaded
+    pub failed_files: Vec<FailedFile>,
+    /// Per engine over all files: sample `i` is iteration `i` summed over files
+    pub engines: Vec<EngineBench>,
+}
+
+/// Benchmark the engines in `registry` on the `pipeline` variants of a file
+/// or every image in a directory
+pub fn run(
+    registry: &EngineRegistry,
+    pipeline: &Pipeline,
+    input: &Path,
+    settings: BenchSettings,
+) -> Result<BenchReport> {
+    if settings.iterations == 0 {
+        bail!("Benchmark needs at least one measured iteration");
+    }
+
+    let paths = if input.is_dir() {
+        collect_images(input)
+    } else {
+        vec![input.to_path_buf()]
+    };
+
+    let environment = Environment::detect();
+    info!(
+        "Reference workload: {:.2}ms ({} build)",
+        environment.reference_ms, environment.build
+    );
+
+    let mut files = Vec::new();
+    let mut failed_files = Vec::new();
+    for path in &paths {
+        match bench_file(registry, pipeline, path, settings, environment.reference_ms) {
+            Ok(file) => files.push(file),
+            Err(e) => {
+                error!("Failed to benchmark file {}: {:#}", path.display(), e);
+                failed_files.push(FailedFile {
+                    file_path: path.display().to_string(),
+                    error: format!("{:#}", e),
+                });
+            }
+        }
+    }
+
+    let engines = if files.is_empty() {
+        Vec::new()
+    } else {
+        registry
+            .engines()
+            .enumerate()
+            .map(|(idx, engine)| {
+                let per_file: Vec<&EngineBench> = files.iter().map(|f| &f.engines[idx]).collect();
+                let samples = (0..settings.iterations)
+                    .map(|i| per_file.iter().map(|e| e.samples_ms[i]).sum())
+                    .collect();
+                EngineBench::new(
+                    engine.name(),
+                    per_file.iter().map(|e| e.qr_codes).sum(),
+                    per_file.iter().map(|e| e.failures).sum(),
+                    samples,
+                    environment.reference_ms,
+                )
+            })
+            .collect()
+    };
+
+    Ok(BenchReport {
+        environment,
+        warmup: settings.warmup,
+        iterations: settings.iterations,
+        files,
+        failed_files,
+        engines,
+    })
+}
+
+/// Benchmark every engine on one image
+fn bench_file(
+    registry: &EngineRegistry,
+    pipeline: &Pipeline,
+    path: &Path,
+    settings: BenchSettings,
+    reference_ms: f64,
+) -> Result<FileBench> {
+    debug!("Benchmarking file: {}", path.display());
+    let img =
+        image::open(path).with_context(|| format!("Failed to decode image: {}", path.display()))?;
+    let working_img = ImagePreprocessor::limit_size(&img, MAX_DIMENSION);
+    let variants = pipeline.run(&working_img);
+
+    let engines = registry
+        .engines()
+        .map(|engine| {
+            for _ in 0..settings.warmup {
+                run_variants(engine, &variants);
+            }
+
+            let mut samples = Vec::with_capacity(settings.iterations);
+            let mut failures = 0;
+            let mut qr_codes = None;
+            for _ in 0..settings.iterations {
+                let timer = Timer::start();
+                let (symbols, failed) = run_variants(engine, &variants);
+                samples.push(timer.elapsed().as_secs_f64() * 1000.0);
+
+                failures += failed as usize;
+                qr_codes.get_or_insert_with(|| count_distinct(&symbols));
+            }
+            debug!(
+                "{} on {}: {:?}",
+                engine.name(),
+                path.display(),
+                Summary::of(&samples)
+            );
+            EngineBench::new(
+                engine.name(),
+                qr_codes.unwrap_or(0),
+                failures,
+                samples,
+                reference_ms,
+            )
+        })
+        .collect();
+
+    Ok(FileBench {
+        file_path: path.display().to_string(),
+        variants: variants.len(),
+        engines,
+    })
+}
+
+/// Run `engine` over every variant; returns all symbols found and whether
+/// any call errored or panicked
+fn run_variants(
+    engine: &dyn QrEngine,
+    variants: &[(String, GrayImage)],
+) -> (Vec<DecodedSymbol>, bool) {
+    let mut symbols = Vec::new();
+    let mut failed = false;
+    for (_, gray_img) in variants {
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| engine.detect(gray_img))) {
+            Ok(Ok(found)) => symbols.extend(found),
+            _ => failed = true,
+        }
+    }
+    (symbols, failed)
+}
+
+/// Number of distinct symbols among `symbols`
+fn count_distinct(symbols: &[DecodedSymbol]) -> usize {
+    let mut distinct: Vec<&DecodedSymbol> = Vec::new();
+    for symbol in symbols {
+        if !distinct.iter().any(|known| known.same_symbol(symbol)) {
+            distinct.push(symbol);
+        }
+    }
+    distinct.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn single_sample() {
+        let summary = Summary::of(&[5.0]);
+        for value in [
+            summary.min_ms,
+            summary.median_ms,
+            summary.mean_ms,
+            summary.p95_ms,
+            summary.p99_ms,
+        ] {
+            assert_eq!(value, 5.0);
+        }
+        assert_eq!(summary.std_dev_ms, 0.0);
+        assert!(summary.outliers.is_empty());
+    }
+
+    #[test]
+    fn odd_and_even_counts() {
+        let odd = Summary::of(&[3.0, 1.0, 2.0]);
+        assert_eq!((odd.min_ms, odd.median_ms, odd.mean_ms), (1.0, 2.0, 2.0));
+        assert_close(odd.std_dev_ms, 1.0);
+
+        // The median of an even count lies between the middle samples
+        let even = Summary::of(&[4.0, 1.0, 3.0, 2.0]);
+        assert_eq!((even.min_ms, even.median_ms, even.mean_ms), (1.0, 2.5, 2.5));
+        assert_close(even.std_dev_ms, (5.0f64 / 3.0).sqrt());
+    }
+
+    #[test]
+    fn percentiles_interpolate() {
+        let sorted: Vec<f64> = (1..=11).map(f64::from).collect();
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&sorted, 50.0), 6.0);
+        assert_eq!(percentile(&sorted, 100.0), 11.0);
+        // Rank 0.95 * 10 = 9.5, halfway between 10 and 11
+        assert_close(percentile(&sorted, 95.0), 10.5);
+        assert_close(percentile(&sorted, 99.0), 10.9);
+
+        let summary = Summary::of(&sorted);
+        assert_close(summary.p95_ms, 10.5);
+        assert_close(summary.p99_ms, 10.9);
+    }
+
+    #[test]
+    fn tukey_outliers() {
+        // Quartiles 11.25 and 15.75 put the fences at 4.5 and 22.5
+        let samples = [10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0, 17.0, 100.0, 1.0];
+        assert_eq!(Summary::of(&samples).outliers, [8, 9]);
+
+        // Samples on the fences are not outliers
+        let samples = [10.0, 11.0, 12.0, 13.0, 14.0, 15.0, 16.0, 17.0, 22.5, 4.5];
+        assert!(Summary::of(&samples).outliers.is_empty());
+    }
+}
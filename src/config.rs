@@ -0,0 +1,311 @@
+//! Run configuration shared by the command line and `--config` files
+//!
+//! A config file is JSON, for example:
+//!
+//! ```json
+//! {
+//!   "symbologies": ["qr", "data-matrix"],
+//!   "charset": "GBK",
+//!   "rxing": { "try_harder": true },
+//!   "rxing_profiles": {
+//!     "rxing-pure": { "pure_barcode": true },
+//!     "rxing-sjis": { "try_harder": true, "character_set": "Shift_JIS" }
+//!   },
+//!   "variants": ["original=", "denoised=contrast>gaussian(1.0)>sauvola(31,0.2)"]
+//! }
+//! ```
+//!
+//! Command line flags are applied on top of the file.
+
+use anyhow::{Context, Result, bail};
+use rxing::DecodeHints;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::engine::Symbology;
+use crate::payload::Charset;
+use crate::preprocessor::Pipeline;
+
+/// Settings that shape how engines are built
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Symbologies to decode; engines supporting none of them are not run
+    pub symbologies: Vec<Symbology>,
+    /// Character set for payloads that have no ECI and are not UTF-8
+    pub charset: Option<String>,
+    /// Hints for the built-in `rxing` engine
+    pub rxing: RxingHints,
+    /// Extra rxing engines with their hints, in the order given; a JSON
+    /// object keyed by engine name
+    #[serde(with = "profiles")]
+    pub rxing_profiles: Vec<(String, RxingHints)>,
+    /// Preprocessed variants to scan, as `NAME=STEP>STEP` chains; empty for
+    /// the built-in ones
+    pub variants: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            symbologies: vec![Symbology::Qr],
+            charset: None,
+            rxing: RxingHints::default(),
+            rxing_profiles: Vec::new(),
+            variants: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Load a JSON config file
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))
+    }
+
+    /// The fallback character set, if one is configured
+    pub fn charset(&self) -> Result<Option<Charset>> {
+        self.charset.as_deref().map(str::parse).transpose()
+    }
+
+    /// The preprocessing pipeline, the built-in one unless variants are
+    /// configured
+    pub fn pipeline(&self) -> Result<Pipeline> {
+        if self.variants.is_empty() {
+            Ok(Pipeline::default())
+        } else {
+            Pipeline::parse(&self.variants)
+        }
+    }
+
+    /// Add an rxing profile from a `NAME=HINTS` command line spec
+    pub fn add_rxing_profile(&mut self, spec: &str) -> Result<()> {
+        let (name, hints) = spec.split_once('=').unwrap_or((spec, ""));
+        if name.is_empty() {
+            bail!("rxing profile needs a name: {}", spec);
+        }
+        if self.rxing_profiles.iter().any(|(known, _)| known == name) {
+            bail!("rxing profile '{}' is given twice", name);
+        }
+        self.rxing_profiles.push((name.to_string(), hints.parse()?));
+        Ok(())
+    }
+}
+
+/// Serde format of rxing profiles: a JSON object, read in key order, that
+/// names no engine twice
+mod profiles {
+    use super::RxingHints;
+    use serde::de::{Error, MapAccess, Visitor};
+    use serde::ser::SerializeMap;
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(
+        profiles: &[(String, RxingHints)],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(profiles.len()))?;
+        for (name, hints) in profiles {
+            map.serialize_entry(name, hints)?;
+        }
+        map.end()
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(String, RxingHints)>, D::Error> {
+        struct Profiles;
+
+        impl<'de> Visitor<'de> for Profiles {
+            type Value = Vec<(String, RxingHints)>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object of rxing hints keyed by engine name")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut profiles: Vec<(String, RxingHints)> = Vec::new();
+                while let Some((name, hints)) = map.next_entry::<String, RxingHints>()? {
+                    if profiles.iter().any(|(known, _)| *known == name) {
+                        return Err(A::Error::custom(format!(
+                            "rxing profile '{}' is given twice",
+                            name
+                        )));
+                    }
+                    profiles.push((name, hints));
+                }
+                Ok(profiles)
+            }
+        }
+
+        deserializer.deserialize_map(Profiles)
+    }
+}
+
+/// rxing decode hints that can be set per engine
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RxingHints {
+    /// Spend more time looking for codes
+    pub try_harder: bool,
+    /// Image is a clean, unrotated code with nothing else in it
+    pub pure_barcode: bool,
+    /// Character set for byte segments without an ECI (e.g. `Shift_JIS`)
+    pub character_set: Option<String>,
+    /// Also try the inverted image (light code on dark background)
+    pub also_inverted: bool,
+}
+
+impl RxingHints {
+    /// Convert into rxing's hint set
+    pub fn to_decode_hints(&self) -> DecodeHints {
+        let mut hints = DecodeHints::default();
+        if self.try_harder {
+            hints.TryHarder = Some(true);
+        }
+        if self.pure_barcode {
+            hints.PureBarcode = Some(true);
+        }
+        if let Some(charset) = &self.character_set {
+            hints.CharacterSet = Some(charset.clone());
+        }
+        if self.also_inverted {
+            hints.AlsoInverted = Some(true);
+        }
+        hints
+    }
+}
+
+/// Parse a comma-separated hint list: `try-harder,pure-barcode,charset=GBK,also-inverted`
+impl FromStr for RxingHints {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut hints = RxingHints::default();
+        for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match item.split_once('=') {
+                None if item == "try-harder" => hints.try_harder = true,
+                None if item == "pure-barcode" => hints.pure_barcode = true,
+                None if item == "also-inverted" => hints.also_inverted = true,
+                Some(("charset", charset)) if !charset.is_empty() => {
+                    hints.character_set = Some(charset.to_string())
+                }
+                _ => bail!(
+                    "Unknown rxing hint '{}' (expected try-harder, pure-barcode, also-inverted or charset=NAME)",
+                    item
+                ),
+            }
+        }
+        Ok(hints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::EngineRegistry;
+
+    #[test]
+    fn hints() {
+        assert_eq!("".parse::<RxingHints>().unwrap(), RxingHints::default());
+        assert_eq!(
+            " try-harder, pure-barcode,also-inverted , charset=GBK"
+                .parse::<RxingHints>()
+                .unwrap(),
+            RxingHints {
+                try_harder: true,
+                pure_barcode: true,
+                character_set: Some("GBK".to_string()),
+                also_inverted: true,
+            }
+        );
+        for bad in [
+            "harder",
+            "try_harder",
+            "try-harder=true",
+            "try-harder=yes",
+            "charset",
+            "charset=",
+            "encoding=GBK",
+        ] {
+            let error = bad.parse::<RxingHints>().unwrap_err().to_string();
+            assert!(
+                error.starts_with("Unknown rxing hint"),
+                "{}: {}",
+                bad,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn hints_in_files() {
+        let config: Config =
+            serde_json::from_str(r#"{"rxing": {"try_harder": true, "character_set": "GBK"}}"#)
+                .unwrap();
+        assert!(config.rxing.try_harder && !config.rxing.pure_barcode);
+        assert_eq!(config.rxing.character_set.as_deref(), Some("GBK"));
+        for bad in [
+            r#"{"rxing": {"try_harder": "yes"}}"#,
+            r#"{"rxing": {"try_harder": 1}}"#,
+            r#"{"rxing": {"try-harder": true}}"#,
+        ] {
+            assert!(serde_json::from_str::<Config>(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn profiles_keep_their_order() {
+        let mut config: Config = serde_json::from_str(
+            r#"{"rxing_profiles": {"rxing-z": {"try_harder": true}, "rxing-a": {}}}"#,
+        )
+        .unwrap();
+        config.add_rxing_profile("rxing-m=pure-barcode").unwrap();
+        let names: Vec<&str> = config
+            .rxing_profiles
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(names, ["rxing-z", "rxing-a", "rxing-m"]);
+        assert!(config.rxing_profiles[0].1.try_harder);
+
+        // Workers get the config as JSON
+        let json = serde_json::to_string(&config).unwrap();
+        let copy: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy.rxing_profiles, config.rxing_profiles);
+
+        let registry = EngineRegistry::from_config(&config).unwrap();
+        assert_eq!(
+            registry.names()[..5],
+            ["rqrr", "rxing", "rxing-z", "rxing-a", "rxing-m"]
+        );
+    }
+
+    #[test]
+    fn repeated_profiles_are_rejected() {
+        let error = serde_json::from_str::<Config>(
+            r#"{"rxing_profiles": {"rxing-a": {}, "rxing-b": {}, "rxing-a": {"try_harder": true}}}"#,
+        )
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .starts_with("rxing profile 'rxing-a' is given twice")
+        );
+
+        let mut config = Config::default();
+        config.add_rxing_profile("rxing-a=try-harder").unwrap();
+        assert_eq!(
+            config.add_rxing_profile("rxing-a").unwrap_err().to_string(),
+            "rxing profile 'rxing-a' is given twice"
+        );
+        assert!(config.add_rxing_profile("=try-harder").is_err());
+        assert!(config.add_rxing_profile("rxing-b=fast").is_err());
+        assert_eq!(config.rxing_profiles.len(), 1);
+    }
+}
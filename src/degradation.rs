@@ -0,0 +1,510 @@
+//! Controlled image degradations
+//!
+//! A [`DegradationChain`] applies a list of degradations to an image before
+//! it is scanned, e.g. `blur:2,jpeg:30` blurs it and then recompresses it as
+//! a quality 30 JPEG. Images are converted to grayscale first, as every
+//! engine scans grayscale variants anyway.
+//!
+//! Randomness (noise, the perspective corners, the light direction and the
+//! occluder) comes from a ChaCha generator seeded anew for each image, so a
+//! chain with the same seed degrades an image the same way on every run.
+//! Random draws do not depend on the strength, so raising one parameter
+//! makes the same degradation stronger instead of a different one.
+
+use anyhow::{Context, Result, bail};
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage, Luma};
+use imageproc::filter::gaussian_blur_f32;
+use imageproc::geometric_transformations::{Interpolation, Projection, rotate_about_center, warp};
+use imageproc::morphology::{Mask, grayscale_erode};
+use imageproc::noise::gaussian_noise;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::fmt;
+use std::str::FromStr;
+
+/// Names accepted in a chain, for error messages
+const NAMES: &str = "blur, motion, defocus, noise, jpeg, perspective, rotate, lighting, contrast, occlude, dotgain, downscale";
+
+/// One degradation with its strength
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Degradation {
+    /// Gaussian blur with this sigma, in pixels (`blur:SIGMA`)
+    Blur(f32),
+    /// Linear motion blur over `length` pixels at `angle` degrees from the
+    /// x axis (`motion:LENGTH[:ANGLE]`)
+    MotionBlur { length: u32, angle: f32 },
+    /// Out-of-focus blur: the average over a disk of this radius, in pixels
+    /// (`defocus:RADIUS`)
+    Defocus(u32),
+    /// Gaussian sensor noise with this standard deviation, in gray levels
+    /// (`noise:SIGMA`)
+    Noise(f64),
+    /// JPEG recompression at this quality, 1-100 (`jpeg:QUALITY`)
+    Jpeg(u8),
+    /// Perspective warp moving each corner inward by up to this fraction of
+    /// the image size, 0-0.45 (`perspective:AMOUNT`)
+    Perspective(f32),
+    /// Clockwise rotation in degrees; the canvas grows to keep the whole
+    /// image (`rotate:DEGREES`)
+    Rotate(f32),
+    /// Light falling off linearly across the image, down to this fraction
+    /// darker on the far side, 0-1 (`lighting:AMOUNT`)
+    Lighting(f32),
+    /// Contrast scaled around mid-gray by this factor, 0-1 (`contrast:FACTOR`)
+    Contrast(f32),
+    /// A black or white rectangle covering this percentage of the image
+    /// (`occlude:PERCENT`)
+    Occlude(f32),
+    /// Printing dot gain: dark areas spread by this many pixels
+    /// (`dotgain:RADIUS`)
+    DotGain(u8),
+    /// Downscaling by this factor, at least 1 (`downscale:FACTOR`)
+    Downscale(f32),
+}
+
+impl Degradation {
+    /// Apply to `gray`, drawing any randomness from `rng`
+    pub fn apply(&self, gray: &GrayImage, rng: &mut ChaCha8Rng) -> Result<GrayImage> {
+        let degraded = match *self {
+            Degradation::Blur(sigma) => gaussian_blur_f32(gray, sigma),
+            Degradation::MotionBlur { length, angle } => motion_blur(gray, length, angle),
+            Degradation::Defocus(radius) => defocus(gray, radius),
+            Degradation::Noise(sigma) => gaussian_noise(gray, 0.0, sigma, rng.next_u64()),
+            Degradation::Jpeg(quality) => {
+                let mut buffer = Vec::new();
+                JpegEncoder::new_with_quality(&mut buffer, quality)
+                    .encode_image(gray)
+                    .context("Failed to encode JPEG")?;
+                image::load_from_memory(&buffer)
+                    .context("Failed to decode JPEG")?
+                    .to_luma8()
+            }
+            Degradation::Perspective(amount) => perspective(gray, amount, rng)?,
+            Degradation::Rotate(degrees) => rotate(gray, degrees),
+            Degradation::Lighting(amount) => lighting(gray, amount, rng),
+            Degradation::Contrast(factor) => {
+                let mut scaled = gray.clone();
+                for pixel in scaled.pixels_mut() {
+                    let value = 128.0 + (pixel[0] as f32 - 128.0) * factor;
+                    pixel[0] = value.round().clamp(0.0, 255.0) as u8;
+                }
+                scaled
+            }
+            Degradation::Occlude(percent) => occlude(gray, percent, rng),
+            Degradation::DotGain(radius) => grayscale_erode(gray, &Mask::disk(radius)),
+            Degradation::Downscale(factor) => {
+                let width = ((gray.width() as f32 / factor).round() as u32).max(1);
+                let height = ((gray.height() as f32 / factor).round() as u32).max(1);
+                image::imageops::resize(gray, width, height, FilterType::Triangle)
+            }
+        };
+        Ok(degraded)
+    }
+}
+
+impl FromStr for Degradation {
+    type Err = anyhow::Error;
+
+    /// Parse `name:value[:value]`
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut parts = spec.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let values: Vec<&str> = parts.collect();
+        let value = |index: usize| -> Result<f64> {
+            let text = values
+                .get(index)
+                .with_context(|| format!("'{}' needs a value, e.g. {}:2", name, name))?;
+            text.trim()
+                .parse()
+                .with_context(|| format!("Invalid value '{}' in '{}'", text, spec))
+        };
+        let in_range = |index: usize, min: f64, max: f64| -> Result<f64> {
+            let v = value(index)?;
+            if !(min..=max).contains(&v) {
+                bail!("'{}' needs a value from {} to {}", name, min, max);
+            }
+            Ok(v)
+        };
+        let positive = |index: usize| -> Result<f64> {
+            let v = value(index)?;
+            if v.is_nan() || v <= 0.0 {
+                bail!("'{}' needs a value above 0", name);
+            }
+            Ok(v)
+        };
+
+        let max_values = if name == "motion" { 2 } else { 1 };
+        if values.len() > max_values {
+            bail!("Too many values in '{}'", spec);
+        }
+
+        let degradation = match name {
+            "blur" => Degradation::Blur(positive(0)? as f32),
+            "motion" => Degradation::MotionBlur {
+                length: in_range(0, 1.0, 1000.0)?.round() as u32,
+                angle: if values.len() > 1 {
+                    value(1)? as f32
+                } else {
+                    0.0
+                },
+            },
+            "defocus" => Degradation::Defocus(in_range(0, 1.0, 1000.0)?.round() as u32),
+            "noise" => Degradation::Noise(in_range(0, 0.0, 255.0)?),
+            "jpeg" => Degradation::Jpeg(in_range(0, 1.0, 100.0)?.round() as u8),
+            "perspective" => Degradation::Perspective(in_range(0, 0.0, 0.45)? as f32),
+            "rotate" => Degradation::Rotate(in_range(0, -360.0, 360.0)? as f32),
+            "lighting" => Degradation::Lighting(in_range(0, 0.0, 1.0)? as f32),
+            "contrast" => Degradation::Contrast(in_range(0, 0.0, 1.0)? as f32),
+            "occlude" => Degradation::Occlude(in_range(0, 0.0, 100.0)? as f32),
+            "dotgain" => Degradation::DotGain(in_range(0, 1.0, 255.0)?.round() as u8),
+            "downscale" => Degradation::Downscale(in_range(0, 1.0, 1000.0)? as f32),
+            _ => bail!("Unknown degradation '{}' (expected {})", name, NAMES),
+        };
+        Ok(degradation)
+    }
+}
+
+impl fmt::Display for Degradation {
+    /// The spec this parses from
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Degradation::Blur(sigma) => write!(f, "blur:{}", sigma),
+            Degradation::MotionBlur { length, angle } => write!(f, "motion:{}:{}", length, angle),
+            Degradation::Defocus(radius) => write!(f, "defocus:{}", radius),
+            Degradation::Noise(sigma) => write!(f, "noise:{}", sigma),
+            Degradation::Jpeg(quality) => write!(f, "jpeg:{}", quality),
+            Degradation::Perspective(amount) => write!(f, "perspective:{}", amount),
+            Degradation::Rotate(degrees) => write!(f, "rotate:{}", degrees),
+            Degradation::Lighting(amount) => write!(f, "lighting:{}", amount),
+            Degradation::Contrast(factor) => write!(f, "contrast:{}", factor),
+            Degradation::Occlude(percent) => write!(f, "occlude:{}", percent),
+            Degradation::DotGain(radius) => write!(f, "dotgain:{}", radius),
+            Degradation::Downscale(factor) => write!(f, "downscale:{}", factor),
+        }
+    }
+}
+
+/// Degradations applied in order, with the seed of their randomness
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegradationChain {
+    pub steps: Vec<Degradation>,
+    pub seed: u64,
+}
+
+impl DegradationChain {
+    /// Parse a comma-separated chain such as `blur:2,jpeg:30`
+    pub fn parse(spec: &str, seed: u64) -> Result<Self> {
+        let steps = spec
+            .split(',')
+            .filter(|step| !step.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()?;
+        if steps.is_empty() {
+            bail!("No degradations given");
+        }
+        Ok(Self { steps, seed })
+    }
+
+    /// Degrade a grayscale copy of `img`
+    pub fn apply(&self, img: &DynamicImage) -> Result<DynamicImage> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut gray = img.to_luma8();
+        for step in &self.steps {
+            gray = step
+                .apply(&gray, &mut rng)
+                .with_context(|| format!("Failed to apply {}", step))?;
+        }
+        Ok(DynamicImage::ImageLuma8(gray))
+    }
+}
+
+impl fmt::Display for DegradationChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: Vec<String> = self.steps.iter().map(ToString::to_string).collect();
+        write!(f, "{}", steps.join(","))
+    }
+}
+
+/// Average `length` samples along a line at `angle` degrees through each pixel
+fn motion_blur(gray: &GrayImage, length: u32, angle: f32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let offsets: Vec<(f32, f32)> = (0..length)
+        .map(|i| {
+            let t = i as f32 - (length - 1) as f32 / 2.0;
+            (t * cos, t * sin)
+        })
+        .collect();
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let sum: u32 = offsets
+            .iter()
+            .map(|(dx, dy)| {
+                let sx = (x as f32 + dx).round().clamp(0.0, (width - 1) as f32) as u32;
+                let sy = (y as f32 + dy).round().clamp(0.0, (height - 1) as f32) as u32;
+                gray.get_pixel(sx, sy)[0] as u32
+            })
+            .sum();
+        Luma([(sum as f32 / length as f32).round() as u8])
+    })
+}
+
+/// Average over a disk of `radius` pixels around each pixel
+fn defocus(gray: &GrayImage, radius: u32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (w, h) = (width as i64, height as i64);
+
+    // Prefix sums per row, so each row of the disk is one subtraction
+    let stride = width as usize + 1;
+    let mut sums = vec![0u64; stride * height as usize];
+    for (y, row) in gray.rows().enumerate() {
+        for (x, pixel) in row.enumerate() {
+            sums[y * stride + x + 1] = sums[y * stride + x] + pixel[0] as u64;
+        }
+    }
+
+    let r = radius as i64;
+    let half_widths: Vec<(i64, i64)> = (-r..=r)
+        .map(|dy| (dy, ((r * r - dy * dy) as f64).sqrt() as i64))
+        .collect();
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (mut sum, mut count) = (0u64, 0u64);
+        for &(dy, half) in &half_widths {
+            let row = (y as i64 + dy).clamp(0, h - 1) as usize * stride;
+            let left = (x as i64 - half).max(0) as usize;
+            let right = (x as i64 + half).min(w - 1) as usize + 1;
+            sum += sums[row + right] - sums[row + left];
+            count += (right - left) as u64;
+        }
+        Luma([(sum as f64 / count as f64).round() as u8])
+    })
+}
+
+/// Warp so each corner moves inward by a random share of `amount`
+fn perspective(gray: &GrayImage, amount: f32, rng: &mut ChaCha8Rng) -> Result<GrayImage> {
+    let (w, h) = (gray.width() as f32, gray.height() as f32);
+    let mut inset = [0.0f32; 8];
+    for value in &mut inset {
+        *value = rng.gen_range(0.0..1.0) * amount;
+    }
+    let from = [(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)];
+    let to = [
+        (inset[0] * w, inset[1] * h),
+        (w - inset[2] * w, inset[3] * h),
+        (w - inset[4] * w, h - inset[5] * h),
+        (inset[6] * w, h - inset[7] * h),
+    ];
+    let projection =
+        Projection::from_control_points(from, to).context("Degenerate perspective warp")?;
+    Ok(warp(
+        gray,
+        &projection,
+        Interpolation::Bilinear,
+        Luma([255]),
+    ))
+}
+
+/// Rotate clockwise on a white canvas large enough for the rotated image
+fn rotate(gray: &GrayImage, degrees: f32) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (w, h) = (width as f32, height as f32);
+    let canvas_width = ((w * cos.abs() + h * sin.abs()).ceil() as u32).max(width);
+    let canvas_height = ((w * sin.abs() + h * cos.abs()).ceil() as u32).max(height);
+
+    let mut canvas = GrayImage::from_pixel(canvas_width, canvas_height, Luma([255]));
+    image::imageops::overlay(
+        &mut canvas,
+        gray,
+        ((canvas_width - width) / 2) as i64,
+        ((canvas_height - height) / 2) as i64,
+    );
+    rotate_about_center(
+        &canvas,
+        degrees.to_radians(),
+        Interpolation::Bilinear,
+        Luma([255]),
+    )
+}
+
+/// Darken linearly across the image in a random direction
+fn lighting(gray: &GrayImage, amount: f32, rng: &mut ChaCha8Rng) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let direction = rng.gen_range(0.0..std::f32::consts::TAU);
+    let (sin, cos) = direction.sin_cos();
+    // Largest distance from the center along the direction, in unit coordinates
+    let extent = 0.5 * (cos.abs() + sin.abs());
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (u, v) = (
+            x as f32 / width as f32 - 0.5,
+            y as f32 / height as f32 - 0.5,
+        );
+        let position = ((u * cos + v * sin) / extent + 1.0) / 2.0;
+        let light = 1.0 - amount * position.clamp(0.0, 1.0);
+        Luma([(gray.get_pixel(x, y)[0] as f32 * light).round() as u8])
+    })
+}
+
+/// Cover `percent` of the image with a black or white rectangle of the
+/// image's aspect ratio at a random position
+fn occlude(gray: &GrayImage, percent: f32, rng: &mut ChaCha8Rng) -> GrayImage {
+    let (width, height) = gray.dimensions();
+    let (fx, fy): (f32, f32) = (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0));
+    let fill = if rng.gen_bool(0.5) { 0 } else { 255 };
+
+    let side = (percent / 100.0).sqrt();
+    let (w, h) = (
+        (width as f32 * side).round() as u32,
+        (height as f32 * side).round() as u32,
+    );
+    let (left, top) = (
+        ((width - w) as f32 * fx) as u32,
+        ((height - h) as f32 * fy) as u32,
+    );
+
+    let mut occluded = gray.clone();
+    for y in top..top + h {
+        for x in left..left + w {
+            occluded.put_pixel(x, y, Luma([fill]));
+        }
+    }
+    occluded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A gray gradient with a dark square, so every degradation changes it
+    fn sample() -> DynamicImage {
+        let gray = GrayImage::from_fn(48, 40, |x, y| {
+            if (12..28).contains(&x) && (10..26).contains(&y) {
+                Luma([20])
+            } else {
+                Luma([(100 + x * 3) as u8])
+            }
+        });
+        DynamicImage::ImageLuma8(gray)
+    }
+
+    #[test]
+    fn specs() {
+        let cases = [
+            ("blur:2", Degradation::Blur(2.0)),
+            (
+                "motion:9",
+                Degradation::MotionBlur {
+                    length: 9,
+                    angle: 0.0,
+                },
+            ),
+            (
+                "motion:9:45",
+                Degradation::MotionBlur {
+                    length: 9,
+                    angle: 45.0,
+                },
+            ),
+            ("defocus:3", Degradation::Defocus(3)),
+            ("noise:12.5", Degradation::Noise(12.5)),
+            ("jpeg:30", Degradation::Jpeg(30)),
+            ("perspective:0.2", Degradation::Perspective(0.2)),
+            ("rotate:-15", Degradation::Rotate(-15.0)),
+            ("lighting:0.5", Degradation::Lighting(0.5)),
+            ("contrast:0.25", Degradation::Contrast(0.25)),
+            ("occlude:10", Degradation::Occlude(10.0)),
+            ("dotgain:2", Degradation::DotGain(2)),
+            ("downscale:1.5", Degradation::Downscale(1.5)),
+        ];
+        for (spec, expected) in cases {
+            let parsed: Degradation = spec.parse().unwrap();
+            assert_eq!(parsed, expected, "{}", spec);
+            // Display gives back a spec that parses to the same degradation
+            assert_eq!(parsed.to_string().parse::<Degradation>().unwrap(), expected);
+        }
+        assert_eq!(
+            " jpeg:30 ".parse::<Degradation>().unwrap(),
+            Degradation::Jpeg(30)
+        );
+    }
+
+    #[test]
+    fn spec_errors() {
+        let error = |spec: &str| spec.parse::<Degradation>().unwrap_err().to_string();
+        assert_eq!(error("jpeg:0"), "'jpeg' needs a value from 1 to 100");
+        assert_eq!(error("jpeg:101"), "'jpeg' needs a value from 1 to 100");
+        assert_eq!(
+            error("perspective:0.5"),
+            "'perspective' needs a value from 0 to 0.45"
+        );
+        assert_eq!(
+            error("contrast:-0.1"),
+            "'contrast' needs a value from 0 to 1"
+        );
+        assert_eq!(error("blur:0"), "'blur' needs a value above 0");
+        assert_eq!(error("blur:NaN"), "'blur' needs a value above 0");
+        assert_eq!(error("blur"), "'blur' needs a value, e.g. blur:2");
+        assert_eq!(error("blur:x"), "Invalid value 'x' in 'blur:x'");
+        assert_eq!(error("blur:1:2"), "Too many values in 'blur:1:2'");
+        assert_eq!(error("motion:1:2:3"), "Too many values in 'motion:1:2:3'");
+        assert!(error("sharpen:2").starts_with("Unknown degradation 'sharpen' (expected blur, "));
+    }
+
+    #[test]
+    fn chains() {
+        let chain = DegradationChain::parse("blur:2, jpeg:30,", 7).unwrap();
+        assert_eq!(chain.steps, [Degradation::Blur(2.0), Degradation::Jpeg(30)]);
+        assert_eq!(chain.to_string(), "blur:2,jpeg:30");
+        assert_eq!(
+            DegradationChain::parse(" , ", 7).unwrap_err().to_string(),
+            "No degradations given"
+        );
+        assert!(DegradationChain::parse("blur:2,fog:1", 7).is_err());
+    }
+
+    #[test]
+    fn seeded_degradations_repeat() {
+        let image = sample();
+        for spec in [
+            "noise:30",
+            "perspective:0.3",
+            "lighting:0.8",
+            "occlude:20",
+            "noise:20,rotate:10,jpeg:50",
+        ] {
+            let degrade = |seed| {
+                let chain = DegradationChain::parse(spec, seed).unwrap();
+                chain.apply(&image).unwrap().into_luma8().into_raw()
+            };
+            assert_eq!(degrade(1), degrade(1), "{}", spec);
+            assert_ne!(degrade(1), degrade(2), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn deterministic_degradations_ignore_the_seed() {
+        let image = sample();
+        for spec in [
+            "blur:1.5",
+            "motion:5:30",
+            "defocus:2",
+            "jpeg:20",
+            "rotate:30",
+            "contrast:0.5",
+            "dotgain:1",
+            "downscale:2",
+        ] {
+            let degrade = |seed| {
+                let chain = DegradationChain::parse(spec, seed).unwrap();
+                chain.apply(&image).unwrap().into_luma8()
+            };
+            let degraded = degrade(1);
+            assert_eq!(degraded, degrade(2), "{}", spec);
+            assert_ne!(degraded, image.to_luma8(), "{}", spec);
+        }
+    }
+}
@@ -0,0 +1,1449 @@
+//! QR detection engines
+//!
+//! Engines decode QR codes and, where the backend supports them, the other
+//! 2D symbologies in [`Symbology`]. Every decoding backend implements
+//! [`QrEngine`] and is registered in an [`EngineRegistry`]. The scanner and
+//! analyzer drive whatever engines the registry holds, so new backends plug
+//! in without touching the scan loop.
+
+use anyhow::{Context, Result, bail};
+use bardecoder::decode::{Decode, QRDecoder};
+use bardecoder::detect::{Detect, LineScan, Location};
+use bardecoder::extract::{Extract, QRExtractor};
+use bardecoder::prepare::{BlockedMean, Prepare};
+use image::GrayImage;
+use log::debug;
+use rqrr::BitGrid;
+use rxing::{
+    BarcodeFormat, BinaryBitmap, DecodeHints, Exceptions, Luma8LuminanceSource, RXingResult,
+    RXingResultMetadataType, RXingResultMetadataValue, Reader,
+    aztec::AztecReader,
+    common::{BitMatrix, DetectorRXingResult, HybridBinarizer},
+    datamatrix::DataMatrixReader,
+    multi::{GenericMultipleBarcodeReader, MultipleBarcodeReader, qrcode::detector::MultiDetector},
+    qrcode::{
+        QRCodeReader,
+        cpp_port::QrReader,
+        decoder::{
+            ErrorCorrectionLevel, QRCodeDecoderMetaData, Version,
+            qrcode_decoder::decode_bitmatrix_with_hints,
+        },
+    },
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::config::{Config, RxingHints};
+use crate::memory::{self, MemoryUsage};
+use crate::metadata::{EccLevel, Segments, SymbolMetadata, read_segments};
+use crate::payload::{Charset, Payload};
+use crate::preprocessor::ImagePreprocessor;
+use crate::timer::Timer;
+use crate::zbar;
+
+/// Static description of what an engine can do
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct EngineCapabilities {
+    /// Can return more than one QR code from a single image
+    pub multiple_codes: bool,
+    /// Backed by native (C) code rather than pure Rust
+    pub native_code: bool,
+    /// Reports the raw payload bytes rather than only a text rendering
+    pub raw_bytes: bool,
+    /// Reports where each symbol is in the image
+    pub corners: bool,
+    /// Symbologies the engine can decode
+    pub symbologies: &'static [Symbology],
+}
+
+/// A 2D symbology
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symbology {
+    Qr,
+    MicroQr,
+    Rmqr,
+    DataMatrix,
+    Aztec,
+}
+
+impl Symbology {
+    pub const ALL: [Symbology; 5] = [
+        Symbology::Qr,
+        Symbology::MicroQr,
+        Symbology::Rmqr,
+        Symbology::DataMatrix,
+        Symbology::Aztec,
+    ];
+
+    /// Name used on the command line and in reports
+    pub fn name(self) -> &'static str {
+        match self {
+            Symbology::Qr => "qr",
+            Symbology::MicroQr => "micro-qr",
+            Symbology::Rmqr => "rmqr",
+            Symbology::DataMatrix => "data-matrix",
+            Symbology::Aztec => "aztec",
+        }
+    }
+
+    fn from_rxing(format: &BarcodeFormat) -> Option<Self> {
+        match format {
+            BarcodeFormat::QR_CODE => Some(Symbology::Qr),
+            BarcodeFormat::MICRO_QR_CODE => Some(Symbology::MicroQr),
+            BarcodeFormat::RECTANGULAR_MICRO_QR_CODE => Some(Symbology::Rmqr),
+            BarcodeFormat::DATA_MATRIX => Some(Symbology::DataMatrix),
+            BarcodeFormat::AZTEC => Some(Symbology::Aztec),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Symbology {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Symbology {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Symbology::ALL
+            .into_iter()
+            .find(|s| s.name() == name)
+            .with_context(|| {
+                let names: Vec<_> = Symbology::ALL.iter().map(|s| s.name()).collect();
+                format!(
+                    "Unknown symbology '{}' (expected {})",
+                    name,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Symbologies of engines that only read QR codes
+const QR_ONLY: &[Symbology] = &[Symbology::Qr];
+
+/// A position in image pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Point {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+}
+
+/// One symbol decoded by an engine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct DecodedSymbol {
+    /// Kind of symbol
+    pub symbology: Symbology,
+    /// Decoded content
+    pub payload: Payload,
+    /// Corners of the symbol: top-left, top-right, bottom-right, bottom-left
+    /// as seen in the symbol's own orientation (for QR codes; other
+    /// symbologies start at the corner nearest the image's top-left and go
+    /// clockwise). `None` if the engine could not place the symbol by four
+    /// points.
+    pub corners: Option<[Point; 4]>,
+    /// Version, ECC level and other symbol parameters, as far as known
+    pub metadata: SymbolMetadata,
+    /// Whether `payload` holds the symbol's raw bytes rather than text the
+    /// engine transcoded; set by the scanner from the engine's capabilities
+    #[serde(skip)]
+    pub raw_bytes: bool,
+}
+
+impl DecodedSymbol {
+    /// A QR code symbol
+    pub fn new(payload: Payload) -> Self {
+        Self {
+            symbology: Symbology::Qr,
+            payload,
+            corners: None,
+            metadata: SymbolMetadata::default(),
+            raw_bytes: true,
+        }
+    }
+
+    /// Set the kind of symbol
+    pub fn with_symbology(mut self, symbology: Symbology) -> Self {
+        self.symbology = symbology;
+        self
+    }
+
+    /// Attach the symbol's parameters
+    pub fn with_metadata(mut self, metadata: SymbolMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Attach the symbol's corner points
+    pub fn with_corners(mut self, corners: [Point; 4]) -> Self {
+        self.corners = Some(corners);
+        self
+    }
+
+    /// Whether `other` is the same physical symbol: same symbology and
+    /// payload bytes, and at the same place when both locations are known
+    ///
+    /// Different variants and engines place corners a few pixels apart, so
+    /// symbols whose centres are within a quarter diagonal count as one.
+    /// Engines that transcode payloads (see [`raw_bytes`](Self::raw_bytes))
+    /// report other bytes for ECI and non-UTF-8 codes, so against those the
+    /// decoded texts are compared, and failing that the locations alone.
+    pub fn same_symbol(&self, other: &DecodedSymbol) -> bool {
+        if self.symbology != other.symbology {
+            return false;
+        }
+        if let (Some(a), Some(b)) = (
+            self.metadata.structured_append,
+            other.metadata.structured_append,
+        ) && a != b
+        {
+            return false;
+        }
+        let same_place = match (&self.corners, &other.corners) {
+            (Some(a), Some(b)) => {
+                let (ca, cb) = (centre(a), centre(b));
+                let diagonal = distance(a[0], a[2]).min(distance(b[0], b[2]));
+                Some(distance(ca, cb) < diagonal / 4.0)
+            }
+            _ => None,
+        };
+
+        if self.payload.bytes == other.payload.bytes {
+            return same_place.unwrap_or(true);
+        }
+        if self.raw_bytes && other.raw_bytes {
+            return false;
+        }
+        match (self.text(), other.text()) {
+            (Some(a), Some(b)) if a == b => same_place.unwrap_or(true),
+            _ => same_place == Some(true),
+        }
+    }
+
+    /// Decode the payload's text with `metadata` (see
+    /// [`Payload::decode_text`]); text an engine transcoded is kept as is
+    pub fn decode_text(&mut self, metadata: &SymbolMetadata, fallback: Option<Charset>) {
+        if self.raw_bytes {
+            self.payload.decode_text(metadata, fallback);
+        }
+    }
+
+    /// Text of the payload as far as this symbol's own metadata tells
+    fn text(&self) -> Option<String> {
+        if !self.raw_bytes {
+            return self.payload.text.clone();
+        }
+        let mut payload = self.payload.clone();
+        payload.decode_text(&self.metadata, None);
+        payload.text
+    }
+
+    /// Scale the corner points, e.g. back to the size of the original image
+    pub fn scale(&mut self, scale_x: f32, scale_y: f32) {
+        for corner in self.corners.iter_mut().flatten() {
+            corner.x *= scale_x;
+            corner.y *= scale_y;
+        }
+    }
+}
+
+fn centre(corners: &[Point; 4]) -> Point {
+    Point::new(
+        corners.iter().map(|p| p.x).sum::<f32>() / 4.0,
+        corners.iter().map(|p| p.y).sum::<f32>() / 4.0,
+    )
+}
+
+fn distance(a: Point, b: Point) -> f32 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+/// A QR code detection backend
+pub trait QrEngine: Send + Sync {
+    /// Unique engine name used in reports and on the command line
+    fn name(&self) -> &str;
+
+    /// What this engine supports
+    fn capabilities(&self) -> EngineCapabilities;
+
+    /// Detect and decode all symbols in a grayscale image
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>>;
+
+    /// Like [`detect`](Self::detect), also reporting how long locating and
+    /// decoding the symbols took where the engine's API separates the two
+    fn detect_staged(
+        &self,
+        gray_img: &GrayImage,
+    ) -> Result<(Vec<DecodedSymbol>, Option<StageTiming>)> {
+        Ok((self.detect(gray_img)?, None))
+    }
+
+    /// Like [`detect_staged`](Self::detect_staged), also measuring the heap
+    /// the call used if memory counting is enabled
+    fn detect_measured(&self, gray_img: &GrayImage) -> Result<Detection> {
+        let (result, memory) = memory::measure(|| self.detect_staged(gray_img));
+        let (symbols, stages) = result?;
+        Ok(Detection {
+            symbols,
+            stages,
+            memory,
+        })
+    }
+}
+
+/// Symbols found by one engine call, with what the call cost
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Detection {
+    pub symbols: Vec<DecodedSymbol>,
+    pub stages: Option<StageTiming>,
+    pub memory: Option<MemoryUsage>,
+}
+
+/// Time one engine call spent locating symbols and decoding them
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct StageTiming {
+    /// Finding and sampling symbol grids
+    pub detect: Duration,
+    /// Error correction and payload decoding
+    pub decode: Duration,
+}
+
+/// Extract a readable message from a panic payload
+pub fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic payload".to_string()
+    }
+}
+
+/// Ordered collection of detection engines
+pub struct EngineRegistry {
+    engines: Vec<Box<dyn QrEngine>>,
+}
+
+impl EngineRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            engines: Vec::new(),
+        }
+    }
+
+    /// Create a registry with all built-in engines, fastest first
+    pub fn with_default_engines() -> Self {
+        Self::from_config(&Config::default()).expect("built-in engine names are unique")
+    }
+
+    /// Create a registry with all built-in engines configured by `config`,
+    /// plus one extra rxing engine per configured rxing profile
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut registry = Self::new();
+        registry.register(Box::new(RqrrEngine))?;
+        registry.register(Box::new(RxingEngine::new(
+            "rxing",
+            &config.rxing,
+            &config.symbologies,
+        )))?;
+        for (name, hints) in &config.rxing_profiles {
+            registry.register(Box::new(RxingEngine::new(name, hints, &config.symbologies)))?;
+        }
+        registry.register(Box::new(QuircsEngine))?;
+        registry.register(Box::new(BardecoderEngine))?;
+        registry.register(Box::new(ZbarPackEngine))?;
+        Ok(registry)
+    }
+
+    /// Add an engine; engines run in registration order
+    pub fn register(&mut self, engine: Box<dyn QrEngine>) -> Result<()> {
+        if self.get(engine.name()).is_some() {
+            bail!("Duplicate engine name: {}", engine.name());
+        }
+        self.engines.push(engine);
+        Ok(())
+    }
+
+    /// Iterate over registered engines in order
+    pub fn engines(&self) -> impl Iterator<Item = &dyn QrEngine> {
+        self.engines.iter().map(|e| e.as_ref())
+    }
+
+    /// Look up an engine by name
+    pub fn get(&self, name: &str) -> Option<&dyn QrEngine> {
+        self.engines().find(|e| e.name() == name)
+    }
+
+    /// Names of all registered engines, in order
+    pub fn names(&self) -> Vec<&str> {
+        self.engines().map(|e| e.name()).collect()
+    }
+
+    /// Drop the engines that support none of `symbologies`
+    pub fn supporting(mut self, symbologies: &[Symbology]) -> Result<Self> {
+        self.engines.retain(|e| {
+            let supported = e.capabilities().symbologies;
+            let keep = symbologies.iter().any(|s| supported.contains(s));
+            if !keep {
+                debug!("Skipping {}: no requested symbology", e.name());
+            }
+            keep
+        });
+
+        if self.engines.is_empty() {
+            let names: Vec<_> = symbologies.iter().map(|s| s.name()).collect();
+            bail!("No selected engine supports {}", names.join(", "));
+        }
+
+        Ok(self)
+    }
+
+    /// Keep only the engines named in `only` (in that order, or all engines
+    /// if empty), then drop the ones named in `skip`
+    pub fn select(mut self, only: &[String], skip: &[String]) -> Result<Self> {
+        for name in only.iter().chain(skip) {
+            if self.get(name).is_none() {
+                bail!(
+                    "Unknown engine '{}' (available: {})",
+                    name,
+                    self.names().join(", ")
+                );
+            }
+        }
+
+        if !only.is_empty() {
+            let mut selected = Vec::with_capacity(only.len());
+            for name in only {
+                // Duplicates are already moved out and simply not found again
+                if let Some(pos) = self.engines.iter().position(|e| e.name() == name) {
+                    selected.push(self.engines.remove(pos));
+                }
+            }
+            self.engines = selected;
+        }
+
+        self.engines
+            .retain(|e| !skip.iter().any(|name| name == e.name()));
+
+        if self.engines.is_empty() {
+            bail!("No detection engines left to run after applying engine selection");
+        }
+
+        Ok(self)
+    }
+}
+
+impl Default for EngineRegistry {
+    fn default() -> Self {
+        Self::with_default_engines()
+    }
+}
+
+/// rqrr (fast, good for standard QR codes)
+pub struct RqrrEngine;
+
+impl QrEngine for RqrrEngine {
+    fn name(&self) -> &str {
+        "rqrr"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            multiple_codes: true,
+            native_code: false,
+            raw_bytes: true,
+            corners: true,
+            symbologies: QR_ONLY,
+        }
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        self.detect_staged(gray_img).map(|(symbols, _)| symbols)
+    }
+
+    /// Grid detection (including image preparation) vs. `decode_to`
+    fn detect_staged(
+        &self,
+        gray_img: &GrayImage,
+    ) -> Result<(Vec<DecodedSymbol>, Option<StageTiming>)> {
+        let detect_timer = Timer::start();
+        let mut img_data = rqrr::PreparedImage::prepare(gray_img.clone());
+        let grids = img_data.detect_grids();
+        let detect = detect_timer.elapsed();
+
+        debug!("rqrr detected {} grids", grids.len());
+
+        let mut results = Vec::new();
+        let mut decode = Duration::ZERO;
+        for (i, grid) in grids.iter().enumerate() {
+            // decode_to yields the raw bytes; decode() would reject non-UTF-8 payloads
+            let mut bytes = Vec::new();
+            let (decoded, duration) = Timer::time(|| grid.decode_to(&mut bytes));
+            decode += duration;
+            match decoded {
+                Ok(meta) => {
+                    debug!("rqrr grid {} decoded successfully", i);
+                    let metadata = SymbolMetadata {
+                        version: Some(meta.version.0 as u32),
+                        ecc_level: EccLevel::from_format_bits(meta.ecc_level),
+                        mask: Some(meta.mask as u8),
+                        ..SymbolMetadata::default()
+                    };
+                    results.push(
+                        DecodedSymbol::new(Payload::from_bytes(bytes))
+                            .with_corners(rqrr_corners(&grid.bounds, grid.grid.size()))
+                            .with_metadata(metadata),
+                    );
+                }
+                Err(e) => {
+                    debug!("rqrr grid {} decode failed: {:?}", i, e);
+                }
+            }
+        }
+
+        if !results.is_empty() {
+            debug!(
+                "rqrr successfully decoded {}/{} grids",
+                results.len(),
+                grids.len()
+            );
+        }
+
+        Ok((results, Some(StageTiming { detect, decode })))
+    }
+}
+
+/// Outer corners of an rqrr grid
+///
+/// rqrr's bounds span `size + 1` modules from the top-left corner, one module
+/// too far right and down; pull them back towards the top-left corner.
+fn rqrr_corners(bounds: &[rqrr::Point; 4], size: usize) -> [Point; 4] {
+    let ratio = size as f32 / (size as f32 + 1.0);
+    let origin = bounds[0];
+    bounds.map(|p| {
+        Point::new(
+            origin.x as f32 + (p.x - origin.x) as f32 * ratio,
+            origin.y as f32 + (p.y - origin.y) as f32 * ratio,
+        )
+    })
+}
+
+/// Segments of a version `version` grid rqrr decoded
+///
+/// rqrr skips ECI designators and Structured Append headers without
+/// reporting them and keeps its data codewords to itself. rxing's decoder,
+/// run on the same sampled modules, yields the codewords for
+/// [`read_segments`]. This is too slow for the timed engine call, so only
+/// the analyzer uses it.
+pub fn rqrr_segments(grid: &dyn BitGrid, version: u32) -> Option<Segments> {
+    let size = grid.size() as u32;
+    let mut bits = BitMatrix::new(size, size).ok()?;
+    for y in 0..size {
+        for x in 0..size {
+            if grid.bit(y as usize, x as usize) {
+                bits.set(x, y);
+            }
+        }
+    }
+    let decoded = decode_bitmatrix_with_hints(&bits, &DecodeHints::default()).ok()?;
+    read_segments(decoded.getRawBytes(), version)
+}
+
+/// rxing (robust, handles deformed and multiple QR codes, and the other
+/// symbologies)
+///
+/// Several instances with different hints can be registered side by side
+/// under different names to compare hint profiles.
+pub struct RxingEngine {
+    name: String,
+    hints: DecodeHints,
+    /// Symbologies to look for
+    symbologies: Vec<Symbology>,
+}
+
+impl RxingEngine {
+    pub fn new(name: &str, hints: &RxingHints, symbologies: &[Symbology]) -> Self {
+        Self {
+            name: name.to_string(),
+            hints: hints.to_decode_hints(),
+            symbologies: symbologies.to_vec(),
+        }
+    }
+}
+
+impl QrEngine for RxingEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            multiple_codes: true,
+            native_code: false,
+            raw_bytes: true,
+            corners: true,
+            symbologies: &Symbology::ALL,
+        }
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        let mut symbols = Vec::new();
+
+        if self.symbologies.contains(&Symbology::Qr) {
+            match rxing_decode_multiple(gray_img, &self.hints) {
+                Ok(results) => symbols.extend(results.iter().map(rxing_qr_symbol)),
+                Err(Exceptions::NotFoundException(_)) => {}
+                Err(e) => debug!("{} decode failed: {:?}", self.name, e),
+            }
+        }
+
+        for result in rxing_decode_other(gray_img, &self.hints, &self.symbologies) {
+            let Some(symbology) = Symbology::from_rxing(result.getBarcodeFormat()) else {
+                continue;
+            };
+            let symbol = DecodedSymbol::new(rxing_payload(&result)).with_symbology(symbology);
+            symbols.push(match clockwise_corners(result.getPoints()) {
+                Some(corners) => symbol.with_corners(corners),
+                None => symbol,
+            });
+        }
+
+        Ok(symbols)
+    }
+}
+
+/// Symbol for an rxing QR result
+pub fn rxing_qr_symbol(result: &RXingResult) -> DecodedSymbol {
+    let (metadata, data) = rxing_metadata(result);
+    let payload = match data {
+        Some(data) => Payload::from_bytes(data),
+        None => rxing_payload(result),
+    };
+    let corners = metadata.version.and_then(|v| rxing_corners(result, v));
+    let symbol = DecodedSymbol::new(payload).with_metadata(metadata);
+    match corners {
+        Some(corners) => symbol.with_corners(corners),
+        None => symbol,
+    }
+}
+
+/// Metadata and payload bytes of an rxing QR result
+///
+/// rxing reports only the ECC level. Its raw bytes are the symbol's data
+/// codewords though, whose count is unique per version and ECC level, and
+/// whose segments give the modes, ECI, Structured Append header and the
+/// payload bytes undecoded by rxing's character set guess.
+fn rxing_metadata(result: &RXingResult) -> (SymbolMetadata, Option<Vec<u8>>) {
+    let mut metadata = SymbolMetadata::default();
+    let Some(RXingResultMetadataValue::ErrorCorrectionLevel(level)) = result
+        .getRXingResultMetadata()
+        .get(&RXingResultMetadataType::ERROR_CORRECTION_LEVEL)
+    else {
+        return (metadata, None);
+    };
+    let Ok(level) = level.parse::<ErrorCorrectionLevel>() else {
+        return (metadata, None);
+    };
+    metadata.ecc_level = match level {
+        ErrorCorrectionLevel::L => Some(EccLevel::L),
+        ErrorCorrectionLevel::M => Some(EccLevel::M),
+        ErrorCorrectionLevel::Q => Some(EccLevel::Q),
+        ErrorCorrectionLevel::H => Some(EccLevel::H),
+        ErrorCorrectionLevel::Invalid => None,
+    };
+
+    let data_codewords = result.getRawBytes();
+    metadata.version = (1..=40)
+        .filter_map(|n| Version::getVersionForNumber(n).ok())
+        .find(|version| {
+            version.getTotalCodewords() - version.getECBlocksForLevel(level).getTotalECCodewords()
+                == data_codewords.len() as u32
+        })
+        .map(|version| version.getVersionNumber());
+
+    let Some(segments) = metadata
+        .version
+        .and_then(|version| read_segments(data_codewords, version))
+    else {
+        return (metadata, None);
+    };
+    metadata.modes = Some(segments.modes);
+    metadata.eci = segments.eci;
+    metadata.structured_append = segments.structured_append;
+    (metadata, Some(segments.data))
+}
+
+/// Outer corners of an rxing QR result
+///
+/// rxing reports the centres of the bottom-left, top-left and top-right
+/// finder patterns.
+fn rxing_corners(result: &RXingResult, version: u32) -> Option<[Point; 4]> {
+    let [bottom_left, top_left, top_right, ..] = result.getPoints() else {
+        return None;
+    };
+    Some(finder_corners(
+        Point::new(top_left.x, top_left.y),
+        Point::new(top_right.x, top_right.y),
+        Point::new(bottom_left.x, bottom_left.y),
+        version,
+    ))
+}
+
+/// Outer corners of a QR code from the centres of its three finder patterns
+///
+/// Each centre sits 3.5 modules in from the symbol's edges, so with the
+/// module count known the corners follow, assuming no perspective
+/// distortion.
+fn finder_corners(
+    top_left: Point,
+    top_right: Point,
+    bottom_left: Point,
+    version: u32,
+) -> [Point; 4] {
+    let dimension = (17 + 4 * version) as f32;
+
+    // One module along the top edge (u) and along the left edge (v)
+    let span = dimension - 7.0;
+    let (ux, uy) = (
+        (top_right.x - top_left.x) / span,
+        (top_right.y - top_left.y) / span,
+    );
+    let (vx, vy) = (
+        (bottom_left.x - top_left.x) / span,
+        (bottom_left.y - top_left.y) / span,
+    );
+    let corner = |x: f32, y: f32, du: f32, dv: f32| {
+        Point::new(x + 3.5 * (du * ux + dv * vx), y + 3.5 * (du * uy + dv * vy))
+    };
+
+    [
+        corner(top_left.x, top_left.y, -1.0, -1.0),
+        corner(top_right.x, top_right.y, 1.0, -1.0),
+        corner(
+            top_right.x + bottom_left.x - top_left.x,
+            top_right.y + bottom_left.y - top_left.y,
+            1.0,
+            1.0,
+        ),
+        corner(bottom_left.x, bottom_left.y, -1.0, 1.0),
+    ]
+}
+
+/// Recover the payload bytes of an rxing result whose codewords cannot be read
+///
+/// rxing decodes byte segments to text with a guessed character set. When
+/// the symbol is all byte mode (every text character came from a byte), the
+/// byte segments are the exact payload; otherwise fall back to the text.
+pub fn rxing_payload(result: &RXingResult) -> Payload {
+    let text = result.getText();
+    if let Some(RXingResultMetadataValue::ByteSegments(segments)) = result
+        .getRXingResultMetadata()
+        .get(&RXingResultMetadataType::BYTE_SEGMENTS)
+    {
+        let bytes = segments.concat();
+        if !bytes.is_empty() && text.chars().count() <= bytes.len() {
+            return Payload::from_bytes(bytes);
+        }
+    }
+    Payload::from_text(text.to_string())
+}
+
+/// Decode every QR code rxing can find in `gray_img`
+///
+/// If no detected symbol decodes, fall back to the single-code reader rather
+/// than reporting nothing. rxing's QR readers ignore `AlsoInverted`, so that
+/// hint is applied here.
+pub fn rxing_decode_multiple(
+    gray_img: &GrayImage,
+    hints: &DecodeHints,
+) -> std::result::Result<Vec<RXingResult>, Exceptions> {
+    let result = rxing_decode_luma(gray_img.as_raw().clone(), gray_img, hints);
+    match result {
+        Err(Exceptions::NotFoundException(_)) if hints.AlsoInverted == Some(true) => {
+            debug!("rxing found nothing, retrying inverted");
+            let inverted = ImagePreprocessor::invert(gray_img);
+            rxing_decode_luma(inverted.into_raw(), gray_img, hints)
+        }
+        result => result,
+    }
+}
+
+/// Decode the symbologies other than QR in `symbologies` with rxing
+///
+/// Failures of one symbology's reader do not affect the others; they are
+/// logged and yield no results.
+pub fn rxing_decode_other(
+    gray_img: &GrayImage,
+    hints: &DecodeHints,
+    symbologies: &[Symbology],
+) -> Vec<RXingResult> {
+    let mut results = Vec::new();
+    for &symbology in symbologies {
+        let decode = |pixels: Vec<u8>| {
+            let luminance_source =
+                Luma8LuminanceSource::new(pixels, gray_img.width(), gray_img.height());
+            let mut bitmap = BinaryBitmap::new(HybridBinarizer::new(luminance_source));
+            rxing_decode_symbology(&mut bitmap, hints, symbology)
+        };
+
+        let found = match decode(gray_img.as_raw().clone()) {
+            Err(Exceptions::NotFoundException(_)) if hints.AlsoInverted == Some(true) => {
+                debug!("rxing found no {}, retrying inverted", symbology);
+                decode(ImagePreprocessor::invert(gray_img).into_raw())
+            }
+            found => found,
+        };
+        match found {
+            Ok(found) => results.extend(found),
+            Err(Exceptions::NotFoundException(_)) => {}
+            Err(e) => debug!("rxing {} decode failed: {:?}", symbology, e),
+        }
+    }
+    results
+}
+
+/// Run the rxing reader for one non-QR symbology
+fn rxing_decode_symbology(
+    bitmap: &mut BinaryBitmap<HybridBinarizer<Luma8LuminanceSource>>,
+    hints: &DecodeHints,
+    symbology: Symbology,
+) -> std::result::Result<Vec<RXingResult>, Exceptions> {
+    let results = match symbology {
+        Symbology::Qr => return Ok(Vec::new()),
+        Symbology::MicroQr | Symbology::Rmqr => {
+            // The C++ port reader handles all QR variants; restrict it to this one
+            let format = if symbology == Symbology::MicroQr {
+                BarcodeFormat::MICRO_QR_CODE
+            } else {
+                BarcodeFormat::RECTANGULAR_MICRO_QR_CODE
+            };
+            let mut hints = hints.clone();
+            hints.PossibleFormats = Some(HashSet::from([format]));
+            QrReader.decode_multiple_with_hints(bitmap, &hints)?
+        }
+        Symbology::DataMatrix => GenericMultipleBarcodeReader::new(DataMatrixReader)
+            .decode_multiple_with_hints(bitmap, hints)?,
+        Symbology::Aztec => GenericMultipleBarcodeReader::new(AztecReader)
+            .decode_multiple_with_hints(bitmap, hints)?,
+    };
+
+    if results.is_empty() {
+        Err(Exceptions::NOT_FOUND)
+    } else {
+        Ok(results)
+    }
+}
+
+/// Order four corner points clockwise, starting nearest the image's top-left
+fn clockwise_corners(points: &[rxing::Point]) -> Option<[Point; 4]> {
+    let [a, b, c, d] = points else {
+        return None;
+    };
+    let mut corners = [a, b, c, d].map(|p| Point::new(p.x, p.y));
+    let centre = centre(&corners);
+    // Image y points down, so increasing angle runs clockwise on screen
+    corners.sort_by(|p, q| {
+        let angle = |p: &Point| (p.y - centre.y).atan2(p.x - centre.x);
+        angle(p).total_cmp(&angle(q))
+    });
+    let start = (0..4)
+        .min_by(|&i, &j| (corners[i].x + corners[i].y).total_cmp(&(corners[j].x + corners[j].y)))?;
+    corners.rotate_left(start);
+    Some(corners)
+}
+
+/// Run the rxing QR readers over raw luma pixels with the dimensions of `gray_img`
+fn rxing_decode_luma(
+    pixels: Vec<u8>,
+    gray_img: &GrayImage,
+    hints: &DecodeHints,
+) -> std::result::Result<Vec<RXingResult>, Exceptions> {
+    // Convert to rxing format
+    let luminance_source = Luma8LuminanceSource::new(pixels, gray_img.width(), gray_img.height());
+    let mut bitmap = BinaryBitmap::new(HybridBinarizer::new(luminance_source));
+
+    match rxing_decode_each(&bitmap, hints) {
+        Ok(results) if !results.is_empty() => Ok(results),
+        Ok(_) => Err(Exceptions::NOT_FOUND),
+        Err(Exceptions::NotFoundException(msg)) => Err(Exceptions::NotFoundException(msg)),
+        Err(e) => {
+            debug!("rxing multi decode failed ({:?}), retrying single", e);
+            QRCodeReader::new()
+                .decode_with_hints(&mut bitmap, hints)
+                .map(|result| vec![result])
+        }
+    }
+}
+
+/// Decode each QR symbol rxing's multi detector finds
+///
+/// This is `QRCodeMultiReader` without its two shortcuts: a symbol that fails
+/// to decode is skipped instead of failing the whole image, and Structured
+/// Append fragments are returned one by one instead of being merged (even
+/// when incomplete) into a result with no location.
+fn rxing_decode_each(
+    bitmap: &BinaryBitmap<HybridBinarizer<Luma8LuminanceSource>>,
+    hints: &DecodeHints,
+) -> std::result::Result<Vec<RXingResult>, Exceptions> {
+    let detections = MultiDetector::new(bitmap.get_black_matrix()).detectMulti(hints)?;
+
+    let mut results = Vec::new();
+    let mut failure = None;
+    for detection in detections {
+        let decoded = match decode_bitmatrix_with_hints(detection.getBits(), hints) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                debug!("rxing symbol decode failed: {:?}", e);
+                failure = Some(e);
+                continue;
+            }
+        };
+
+        let mut points = detection.getPoints().to_vec();
+        if let Some(meta) = decoded
+            .getOther()
+            .as_deref()
+            .and_then(|other| other.downcast_ref::<QRCodeDecoderMetaData>())
+        {
+            meta.applyMirroredCorrection(&mut points);
+        }
+
+        let mut result = RXingResult::new(
+            decoded.getText(),
+            decoded.getRawBytes().clone(),
+            points,
+            BarcodeFormat::QR_CODE,
+        );
+        result.putMetadata(
+            RXingResultMetadataType::BYTE_SEGMENTS,
+            RXingResultMetadataValue::ByteSegments(decoded.getByteSegments().clone()),
+        );
+        result.putMetadata(
+            RXingResultMetadataType::ERROR_CORRECTION_LEVEL,
+            RXingResultMetadataValue::ErrorCorrectionLevel(decoded.getECLevel().to_owned()),
+        );
+        if decoded.hasStructuredAppend() {
+            result.putMetadata(
+                RXingResultMetadataType::STRUCTURED_APPEND_SEQUENCE,
+                RXingResultMetadataValue::StructuredAppendSequence(
+                    decoded.getStructuredAppendSequenceNumber(),
+                ),
+            );
+            result.putMetadata(
+                RXingResultMetadataType::STRUCTURED_APPEND_PARITY,
+                RXingResultMetadataValue::StructuredAppendParity(
+                    decoded.getStructuredAppendParity(),
+                ),
+            );
+        }
+        results.push(result);
+    }
+
+    match failure {
+        Some(e) if results.is_empty() => Err(e),
+        _ => Ok(results),
+    }
+}
+
+/// quircs (pure Rust, based on quirc library)
+pub struct QuircsEngine;
+
+impl QrEngine for QuircsEngine {
+    fn name(&self) -> &str {
+        "quircs"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            multiple_codes: true,
+            native_code: false,
+            raw_bytes: true,
+            corners: true,
+            symbologies: QR_ONLY,
+        }
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        self.detect_staged(gray_img).map(|(symbols, _)| symbols)
+    }
+
+    /// `identify` and grid extraction vs. `decode`
+    fn detect_staged(
+        &self,
+        gray_img: &GrayImage,
+    ) -> Result<(Vec<DecodedSymbol>, Option<StageTiming>)> {
+        let width = gray_img.width() as usize;
+        let height = gray_img.height() as usize;
+
+        // Create quircs decoder
+        let mut decoder = quircs::Quirc::new();
+
+        // Identify QR codes in the image; each code's grid is extracted
+        // lazily as the iterator advances
+        let detect_timer = Timer::start();
+        let mut codes = decoder.identify(width, height, gray_img.as_raw());
+        let mut detect = detect_timer.elapsed();
+        let mut decode = Duration::ZERO;
+
+        let mut results = Vec::new();
+        let mut count = 0;
+
+        loop {
+            let (code, duration) = Timer::time(|| codes.next());
+            detect += duration;
+            let Some(code) = code else {
+                break;
+            };
+            count += 1;
+            match code {
+                Ok(code) => {
+                    let (decoded, duration) = Timer::time(|| code.decode());
+                    decode += duration;
+                    match decoded {
+                        Ok(decoded) => {
+                            debug!("quircs decoded QR code successfully");
+                            let corners = code.corners.map(|p| Point::new(p.x as f32, p.y as f32));
+                            let metadata = SymbolMetadata {
+                                version: Some(decoded.version as u32),
+                                ecc_level: EccLevel::from_format_bits(decoded.ecc_level as u16),
+                                mask: Some(decoded.mask as u8),
+                                eci: decoded.eci.map(|eci| eci as u32),
+                                ..SymbolMetadata::default()
+                            };
+                            results.push(
+                                DecodedSymbol::new(Payload::from_bytes(decoded.payload))
+                                    .with_corners(corners)
+                                    .with_metadata(metadata),
+                            );
+                        }
+                        Err(e) => {
+                            debug!("quircs decode failed: {:?}", e);
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!("quircs extract failed: {:?}", e);
+                }
+            }
+        }
+
+        if count > 0 {
+            debug!("quircs identified {} codes", count);
+        }
+
+        Ok((results, Some(StageTiming { detect, decode })))
+    }
+}
+
+/// bardecoder (image-based decoder)
+pub struct BardecoderEngine;
+
+impl QrEngine for BardecoderEngine {
+    fn name(&self) -> &str {
+        "bardecoder"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            multiple_codes: true,
+            native_code: false,
+            raw_bytes: false,
+            corners: true,
+            symbologies: QR_ONLY,
+        }
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        // bardecoder uses image 0.24, we use 0.25
+        // Convert via raw pixels to avoid slow PNG encode/decode
+        let width = gray_img.width();
+        let height = gray_img.height();
+        let pixels = gray_img.as_raw().clone();
+
+        // Create image 0.24 GrayImage from raw pixels
+        let gray_v24 = image_v24::GrayImage::from_raw(width, height, pixels)
+            .context("Failed to create image_v24::GrayImage")?;
+
+        // Convert to DynamicImage for bardecoder
+        let img_v24 = image_v24::DynamicImage::ImageLuma8(gray_v24);
+
+        // The stages of bardecoder's default decoder, run one by one because
+        // its `Decoder` drops the locations
+        let prepared = BlockedMean::new(5, 7).prepare(&img_v24);
+        let locations = LineScan::new().detect(&prepared);
+
+        debug!("bardecoder found {} results", locations.len());
+
+        let mut results = Vec::new();
+        for Location::QR(location) in locations {
+            let point = |p: bardecoder::util::Point| Point::new(p.x as f32, p.y as f32);
+            let corners = finder_corners(
+                point(location.top_left),
+                point(location.top_right),
+                point(location.bottom_left),
+                location.version,
+            );
+            let decoded = QRDecoder::new().decode(QRExtractor::new().extract(&prepared, location));
+            match decoded {
+                Ok(text) => {
+                    debug!("bardecoder decoded QR code successfully");
+                    results
+                        .push(DecodedSymbol::new(Payload::from_text(text)).with_corners(corners));
+                }
+                Err(e) => {
+                    debug!("bardecoder decode failed: {:?}", e);
+                }
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+/// zbar-pack (safe vendored ZBar bindings)
+pub struct ZbarPackEngine;
+
+impl QrEngine for ZbarPackEngine {
+    fn name(&self) -> &str {
+        "zbar-pack"
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        EngineCapabilities {
+            multiple_codes: true,
+            native_code: true,
+            // Binary mode keeps most payloads raw, but ZBar still converts
+            // those with an ECI to UTF-8
+            raw_bytes: false,
+            corners: true,
+            symbologies: QR_ONLY,
+        }
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        // Ensure verbosity is set to 0 (ZBar might reset it internally)
+        zbar_pack::set_verbosity(0);
+
+        let mut scanner = zbar::Scanner::new()?;
+
+        // Without an ECI, ZBar guesses between Shift JIS, Latin-1, Big5 and
+        // UTF-8; binary mode hands back the bytes for the scanner to decode
+        scanner.set_config(zbar::ZBAR_QRCODE, zbar::ZBAR_CFG_BINARY, 1)?;
+
+        let symbols = scanner.scan_gray(gray_img.as_raw(), gray_img.width(), gray_img.height())?;
+
+        let mut results = Vec::new();
+        // Only include QR codes
+        for symbol in symbols
+            .into_iter()
+            .filter(|s| s.symbol_type == zbar::ZBAR_QRCODE)
+        {
+            debug!("zbar-pack decoded QR code successfully");
+            // ZBar locates QR codes by their top-left, bottom-left,
+            // bottom-right and top-right corners
+            let corners = match symbol.location[..] {
+                [tl, bl, br, tr] => {
+                    Some([tl, tr, br, bl].map(|(x, y)| Point::new(x as f32, y as f32)))
+                }
+                _ => None,
+            };
+            // Raw bytes, or UTF-8 text if ZBar converted an ECI payload itself
+            let decoded = DecodedSymbol::new(Payload::from_bytes(symbol.data));
+            results.push(match corners {
+                Some(corners) => decoded.with_corners(corners),
+                None => decoded,
+            });
+        }
+
+        debug!("zbar-pack found {} QR codes", results.len());
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// "テスト" in Shift JIS
+    const SJIS_TEST: [u8; 6] = [0x83, 0x65, 0x83, 0x58, 0x83, 0x67];
+
+    fn square(x: f32, y: f32, side: f32) -> [Point; 4] {
+        [
+            Point::new(x, y),
+            Point::new(x + side, y),
+            Point::new(x + side, y + side),
+            Point::new(x, y + side),
+        ]
+    }
+
+    /// A Shift JIS symbol with an ECI as rqrr, quircs or rxing report it
+    fn raw_sjis() -> DecodedSymbol {
+        DecodedSymbol::new(Payload::from_bytes(SJIS_TEST.to_vec()))
+            .with_metadata(SymbolMetadata {
+                eci: Some(20),
+                ..SymbolMetadata::default()
+            })
+            .with_corners(square(10.0, 10.0, 100.0))
+    }
+
+    /// The same symbol as ZBar reports it: converted to UTF-8, no ECI
+    fn transcoded_sjis() -> DecodedSymbol {
+        DecodedSymbol {
+            raw_bytes: false,
+            ..DecodedSymbol::new(Payload::from_bytes("テスト".as_bytes().to_vec()))
+        }
+    }
+
+    #[test]
+    fn transcoded_symbols_match_on_text() {
+        let raw = raw_sjis();
+        let transcoded = transcoded_sjis();
+        assert!(raw.same_symbol(&transcoded));
+        assert!(transcoded.same_symbol(&raw));
+
+        // Located a few pixels apart, as another engine would
+        let located = transcoded.clone().with_corners(square(12.0, 9.0, 98.0));
+        assert!(raw.same_symbol(&located));
+        // The same text elsewhere in the image is another symbol
+        let elsewhere = transcoded.with_corners(square(300.0, 10.0, 100.0));
+        assert!(!raw.same_symbol(&elsewhere));
+    }
+
+    #[test]
+    fn transcoded_symbols_match_on_location_without_text() {
+        // Without its ECI the raw side has no text to compare
+        let raw = DecodedSymbol::new(Payload::from_bytes(SJIS_TEST.to_vec()))
+            .with_corners(square(10.0, 10.0, 100.0));
+        let transcoded = transcoded_sjis();
+        assert!(!raw.same_symbol(&transcoded));
+        assert!(raw.same_symbol(&transcoded.with_corners(square(11.0, 11.0, 100.0))));
+    }
+
+    #[test]
+    fn raw_symbols_match_on_bytes() {
+        let raw = raw_sjis();
+        let mut other = raw.clone();
+        other.payload = Payload::from_bytes("テスト".as_bytes().to_vec());
+        // Two engines that report raw bytes disagree on the payload
+        assert!(!raw.same_symbol(&other));
+        assert!(raw.same_symbol(&raw.clone().with_corners(square(12.0, 12.0, 100.0))));
+    }
+
+    #[test]
+    fn transcoded_text_is_not_decoded_again() {
+        let mut transcoded = transcoded_sjis();
+        transcoded.decode_text(&raw_sjis().metadata, None);
+        assert_eq!(transcoded.payload.text.as_deref(), Some("テスト"));
+
+        let mut raw = raw_sjis();
+        raw.decode_text(&raw_sjis().metadata, None);
+        assert_eq!(raw.payload.text, transcoded.payload.text);
+    }
+
+    fn assert_corners(actual: &[Point; 4], expected: &[Point; 4], tolerance: f32) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a.x - e.x).abs() <= tolerance && (a.y - e.y).abs() <= tolerance,
+                "{:?} is not {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    /// Rotation by `degrees` clockwise (on screen) about (150, 150)
+    fn rotate(degrees: f32) -> impl Fn(Point) -> Point {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        move |p| {
+            let (x, y) = (p.x - 150.0, p.y - 150.0);
+            Point::new(150.0 + x * cos - y * sin, 150.0 + x * sin + y * cos)
+        }
+    }
+
+    fn mirror(p: Point) -> Point {
+        Point::new(300.0 - p.x, p.y)
+    }
+
+    /// Twice the signed area; positive if the points run clockwise on screen
+    fn winding(corners: &[Point; 4]) -> f32 {
+        (0..4)
+            .map(|i| {
+                let (p, q) = (corners[i], corners[(i + 1) % 4]);
+                p.x * q.y - q.x * p.y
+            })
+            .sum()
+    }
+
+    #[test]
+    fn finder_corners_follow_the_symbol() {
+        // A version 1 symbol (21 modules) of 10-pixel modules at (40, 40):
+        // the finder centres lie 3.5 modules in from its edges
+        let (top_left, top_right, bottom_left) = (
+            Point::new(75.0, 75.0),
+            Point::new(215.0, 75.0),
+            Point::new(75.0, 215.0),
+        );
+        let expected = square(40.0, 40.0, 210.0);
+        assert_corners(
+            &finder_corners(top_left, top_right, bottom_left, 1),
+            &expected,
+            1e-3,
+        );
+
+        // Corners start at the symbol's own top-left and stay in its order,
+        // however the symbol lies in the image
+        let transforms: [Box<dyn Fn(Point) -> Point>; 4] = [
+            Box::new(rotate(90.0)),
+            Box::new(rotate(-30.0)),
+            Box::new(rotate(200.0)),
+            Box::new(mirror),
+        ];
+        for transform in transforms {
+            let corners = finder_corners(
+                transform(top_left),
+                transform(top_right),
+                transform(bottom_left),
+                1,
+            );
+            assert_corners(&corners, &expected.map(&transform), 1e-3);
+        }
+    }
+
+    #[test]
+    fn rqrr_corners_drop_the_extra_module() {
+        // rqrr's bounds of a 21-module symbol span 22 modules
+        let bounds = |corners: [Point; 4]| {
+            corners.map(|p| rqrr::Point {
+                x: p.x.round() as i32,
+                y: p.y.round() as i32,
+            })
+        };
+        let expected = square(40.0, 40.0, 210.0);
+        assert_corners(
+            &rqrr_corners(&bounds(square(40.0, 40.0, 220.0)), 21),
+            &expected,
+            1e-3,
+        );
+
+        let turned = rotate(90.0);
+        assert_corners(
+            &rqrr_corners(&bounds(square(40.0, 40.0, 220.0).map(&turned)), 21),
+            &expected.map(&turned),
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn corners_are_ordered_clockwise() {
+        let points = |corners: [Point; 4]| corners.map(|p| rxing::Point::new(p.x, p.y));
+        let [top_left, top_right, bottom_right, bottom_left] = square(40.0, 40.0, 210.0);
+        let expected = [top_left, top_right, bottom_right, bottom_left];
+        assert_corners(
+            &clockwise_corners(&points([bottom_left, top_right, top_left, bottom_right])).unwrap(),
+            &expected,
+            0.0,
+        );
+        // Counterclockwise, as for a mirrored symbol
+        assert_corners(
+            &clockwise_corners(&points([top_left, bottom_left, bottom_right, top_right])).unwrap(),
+            &expected,
+            0.0,
+        );
+
+        // Rotated, the corner nearest the image's top-left comes first
+        for degrees in [-30.0, 30.0, 100.0, 190.0] {
+            let turned = expected.map(rotate(degrees));
+            let shuffled = [turned[2], turned[0], turned[3], turned[1]];
+            let corners = clockwise_corners(&points(shuffled)).unwrap();
+            assert!(winding(&corners) > 0.0, "{}°: {:?}", degrees, corners);
+            let first = turned
+                .iter()
+                .min_by(|p, q| (p.x + p.y).total_cmp(&(q.x + q.y)))
+                .unwrap();
+            assert_eq!(
+                (corners[0].x, corners[0].y),
+                (first.x, first.y),
+                "{}°",
+                degrees
+            );
+            assert!(
+                turned
+                    .iter()
+                    .all(|p| corners.iter().any(|c| c.x == p.x && c.y == p.y))
+            );
+        }
+
+        assert!(clockwise_corners(&points(expected)[..3]).is_none());
+    }
+
+    #[test]
+    fn engines_agree_on_corners() {
+        // Version 2 (25 modules) of 8-pixel modules behind a 4-module quiet zone
+        let qr =
+            qrcodegen::QrCode::encode_text("corner test", qrcodegen::QrCodeEcc::Medium).unwrap();
+        let (module, quiet) = (8.0, 32.0);
+        let upright = crate::generator::render(&qr, 8, 4);
+        let expected = square(quiet, quiet, qr.size() as f32 * module);
+
+        // Turned a quarter clockwise, the symbol's top-left lies top right
+        let side = upright.width() as f32;
+        let turned = image::imageops::rotate90(&upright);
+        let expected_turned = expected.map(|p| Point::new(side - p.y, p.x));
+
+        let engines: [Box<dyn QrEngine>; 3] = [
+            Box::new(RqrrEngine),
+            Box::new(QuircsEngine),
+            Box::new(RxingEngine::new(
+                "rxing",
+                &RxingHints::default(),
+                &[Symbology::Qr],
+            )),
+        ];
+        for (img, expected) in [(&upright, expected), (&turned, expected_turned)] {
+            for engine in &engines {
+                let symbols = engine.detect(img).unwrap();
+                assert_eq!(symbols.len(), 1, "{}", engine.name());
+                let corners = symbols[0]
+                    .corners
+                    .unwrap_or_else(|| panic!("{} placed no corners", engine.name()));
+                // Within half a module of the true corners, so of each other
+                assert_corners(&corners, &expected, module / 2.0);
+            }
+        }
+    }
+
+    #[test]
+    fn rqrr_segments_read_what_rqrr_skips() {
+        // テスト in Shift JIS behind an ECI 20 designator
+        let sjis = [0x83, 0x65, 0x83, 0x58, 0x83, 0x67];
+        let segments = [
+            qrcodegen::QrSegment::make_eci(20),
+            qrcodegen::QrSegment::make_bytes(&sjis),
+        ];
+        let qr =
+            qrcodegen::QrCode::encode_segments(&segments, qrcodegen::QrCodeEcc::Medium).unwrap();
+        let img = crate::generator::render(&qr, 8, 4);
+        let mut prepared = rqrr::PreparedImage::prepare(img);
+        let grids = prepared.detect_grids();
+        assert_eq!(grids.len(), 1);
+        let segments = rqrr_segments(&grids[0].grid, qr.version().value() as u32).unwrap();
+        assert_eq!(segments.eci, Some(20));
+        assert_eq!(segments.modes, [crate::metadata::SegmentMode::Byte]);
+        assert_eq!(segments.data, sjis);
+    }
+}
@@ -0,0 +1,349 @@
+//! Synthetic test corpus
+//!
+//! Encodes every combination of the chosen payloads, versions, ECC levels
+//! and masks with qrcodegen, renders each symbol at every chosen module size
+//! and quiet zone, and writes the PNGs together with a CSV manifest that
+//! `--manifest` reads as ground truth. Random payloads come from a seeded
+//! ChaCha generator, so the same options always produce the same corpus.
+
+use anyhow::{Context, Result, bail};
+use image::{GrayImage, Luma};
+use log::debug;
+use qrcodegen::{Mask, QrCode, QrCodeEcc, QrSegment, Version};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::EccLevel;
+
+/// File name of the manifest written next to the images
+pub const MANIFEST_NAME: &str = "manifest.csv";
+
+/// Character sets random payloads are drawn from, one per payload; they
+/// cover the numeric, alphanumeric and byte modes
+const ALPHABETS: [&str; 4] = [
+    "0123456789",
+    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:",
+    "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz !\"#$%&'()*+,-./:;<=>?@[]_{}~",
+    "abcxyzäöüßéçñ€漢字かなカナ한글",
+];
+
+/// What to encode and how to render it; every combination is generated
+#[derive(Debug, Clone)]
+pub struct GenerateSettings {
+    pub payloads: Vec<String>,
+    /// Symbol versions; `None` picks the smallest that fits
+    pub versions: Vec<Option<u8>>,
+    pub ecc_levels: Vec<EccLevel>,
+    /// Mask patterns; `None` lets the encoder pick the best one
+    pub masks: Vec<Option<u8>>,
+    /// Pixels per module
+    pub module_sizes: Vec<u32>,
+    /// Light border around the symbol, in modules
+    pub quiet_zones: Vec<u32>,
+}
+
+/// Outcome of [`generate`]
+#[derive(Debug, Default)]
+pub struct GenerateSummary {
+    /// Images written
+    pub images: usize,
+    /// Payload, version, ECC level and mask combinations whose payload does
+    /// not fit the version
+    pub skipped: usize,
+    pub manifest: PathBuf,
+}
+
+/// Draw `count` payloads of `min..=max` characters from a generator seeded
+/// with `seed`
+pub fn random_payloads(count: usize, (min, max): (usize, usize), seed: u64) -> Vec<String> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| {
+            let alphabet: Vec<char> = ALPHABETS[rng.gen_range(0..ALPHABETS.len())]
+                .chars()
+                .collect();
+            let length = rng.gen_range(min..=max);
+            (0..length)
+                .map(|_| alphabet[rng.gen_range(0..alphabet.len())])
+                .collect()
+        })
+        .collect()
+}
+
+/// Write every combination of `settings` as a PNG into `dir`, plus the
+/// manifest
+pub fn generate(settings: &GenerateSettings, dir: &Path) -> Result<GenerateSummary> {
+    // An empty text cell in the manifest means an image without codes
+    if let Some(index) = settings.payloads.iter().position(String::is_empty) {
+        bail!(
+            "Payload {} is empty; the manifest cannot tell it from an image without codes",
+            index + 1
+        );
+    }
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let manifest = dir.join(MANIFEST_NAME);
+    let mut writer = csv::Writer::from_path(&manifest)
+        .with_context(|| format!("Failed to create manifest: {}", manifest.display()))?;
+    writer.write_record([
+        "file",
+        "text",
+        "version",
+        "ecc_level",
+        "mask",
+        "module_size",
+        "quiet_zone",
+    ])?;
+
+    let mut summary = GenerateSummary {
+        manifest,
+        ..GenerateSummary::default()
+    };
+    // "auto" may land on a version or mask that is also listed explicitly
+    let mut written = HashSet::new();
+
+    for (index, payload) in settings.payloads.iter().enumerate() {
+        let segments = QrSegment::make_segments(payload);
+        for &version in &settings.versions {
+            for &ecc in &settings.ecc_levels {
+                for &mask in &settings.masks {
+                    let Some(qr) = encode(&segments, version, ecc, mask) else {
+                        debug!(
+                            "Payload {} does not fit version {:?} at ECC level {:?}",
+                            index + 1,
+                            version,
+                            ecc
+                        );
+                        summary.skipped += 1;
+                        continue;
+                    };
+                    let version = qr.version().value();
+                    let mask = qr.mask().value();
+
+                    for &module_size in &settings.module_sizes {
+                        for &quiet_zone in &settings.quiet_zones {
+                            let name = format!(
+                                "qr-{:05}-v{}-{:?}-m{}-s{}-q{}.png",
+                                index + 1,
+                                version,
+                                ecc,
+                                mask,
+                                module_size,
+                                quiet_zone
+                            );
+                            if !written.insert(name.clone()) {
+                                continue;
+                            }
+
+                            let path = dir.join(&name);
+                            render(&qr, module_size, quiet_zone)
+                                .save(&path)
+                                .with_context(|| {
+                                    format!("Failed to write image: {}", path.display())
+                                })?;
+                            writer.write_record([
+                                name,
+                                payload.clone(),
+                                version.to_string(),
+                                format!("{:?}", ecc),
+                                mask.to_string(),
+                                module_size.to_string(),
+                                quiet_zone.to_string(),
+                            ])?;
+                            summary.images += 1;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    writer
+        .flush()
+        .with_context(|| format!("Failed to write manifest: {}", summary.manifest.display()))?;
+    Ok(summary)
+}
+
+/// Encode at exactly `version`, or the smallest version that fits; `None`
+/// if the payload does not fit
+fn encode(
+    segments: &[QrSegment],
+    version: Option<u8>,
+    ecc: EccLevel,
+    mask: Option<u8>,
+) -> Option<QrCode> {
+    let (min, max) = version.map_or((1, 40), |v| (v, v));
+    let ecc = match ecc {
+        EccLevel::L => QrCodeEcc::Low,
+        EccLevel::M => QrCodeEcc::Medium,
+        EccLevel::Q => QrCodeEcc::Quartile,
+        EccLevel::H => QrCodeEcc::High,
+    };
+    QrCode::encode_segments_advanced(
+        segments,
+        ecc,
+        Version::new(min),
+        Version::new(max),
+        mask.map(Mask::new),
+        false,
+    )
+    .ok()
+}
+
+/// Black modules on white, `module_size` pixels each, with a `quiet_zone`
+/// modules wide border
+pub fn render(qr: &QrCode, module_size: u32, quiet_zone: u32) -> GrayImage {
+    let side = (qr.size() as u32 + 2 * quiet_zone) * module_size;
+    GrayImage::from_fn(side, side, |x, y| {
+        let module_x = (x / module_size) as i32 - quiet_zone as i32;
+        let module_y = (y / module_size) as i32 - quiet_zone as i32;
+        // Coordinates outside the symbol read as light
+        if qr.get_module(module_x, module_y) {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+/// Parse a list such as `1-4,7` whose values lie in `min..=max`; if `auto`
+/// is allowed, `auto` entries become `None`
+pub fn parse_list(spec: &str, min: u32, max: u32, auto: bool) -> Result<Vec<Option<u32>>> {
+    let mut values = Vec::new();
+    for item in spec.split(',').map(str::trim) {
+        if auto && item.eq_ignore_ascii_case("auto") {
+            values.push(None);
+            continue;
+        }
+        let (start, end) = parse_range(item)?;
+        if start < min as usize || end > max as usize {
+            bail!("'{}' is outside {}-{}", item, min, max);
+        }
+        values.extend((start..=end).map(|value| Some(value as u32)));
+    }
+    if values.is_empty() {
+        bail!("Empty list");
+    }
+    Ok(values)
+}
+
+/// Parse `N` or `MIN-MAX`
+pub fn parse_range(spec: &str) -> Result<(usize, usize)> {
+    let number = |text: &str| {
+        text.trim()
+            .parse::<usize>()
+            .with_context(|| format!("Invalid number '{}'", text.trim()))
+    };
+    let (start, end) = match spec.split_once('-') {
+        Some((start, end)) => (number(start)?, number(end)?),
+        None => (number(spec)?, number(spec)?),
+    };
+    if start > end {
+        bail!("Range '{}' is reversed", spec);
+    }
+    Ok((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("7").unwrap(), (7, 7));
+        assert_eq!(parse_range(" 2 - 5 ").unwrap(), (2, 5));
+        assert!(parse_range("5-2").is_err());
+        for bad in ["", "x", "1-", "-3", "1-2-3", "1.5"] {
+            assert!(parse_range(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            parse_list("1-3,7", 1, 40, false).unwrap(),
+            [Some(1), Some(2), Some(3), Some(7)]
+        );
+        assert_eq!(parse_list("auto, 2", 1, 40, true).unwrap(), [None, Some(2)]);
+        // Versions run from 1 to 40, masks from 0 to 7
+        assert!(parse_list("0-3", 1, 40, true).is_err());
+        assert!(parse_list("41", 1, 40, true).is_err());
+        assert!(parse_list("8", 0, 7, true).is_err());
+        assert!(parse_list("auto", 1, 100, false).is_err());
+        assert!(parse_list("1,,2", 1, 40, false).is_err());
+        assert!(parse_list("3-1", 1, 40, false).is_err());
+        assert!(
+            "L,X"
+                .split(',')
+                .map(str::parse::<EccLevel>)
+                .any(|level| level.is_err())
+        );
+    }
+
+    #[test]
+    fn random_payloads_are_seeded() {
+        let payloads = random_payloads(20, (1, 8), 7);
+        assert_eq!(payloads, random_payloads(20, (1, 8), 7));
+        assert_ne!(payloads, random_payloads(20, (1, 8), 8));
+        assert!(
+            payloads
+                .iter()
+                .all(|p| (1..=8).contains(&p.chars().count()))
+        );
+    }
+
+    #[test]
+    fn rendered_symbols_decode() {
+        for (payload, version, ecc, mask) in [
+            ("0123456789", None, EccLevel::L, None),
+            ("HELLO WORLD", Some(3), EccLevel::H, Some(5)),
+            (
+                "mixed Case, ünïcödé and 漢字",
+                Some(10),
+                EccLevel::Q,
+                Some(0),
+            ),
+            ("https://example.com/?q=1", None, EccLevel::M, Some(7)),
+        ] {
+            let segments = QrSegment::make_segments(payload);
+            let qr = encode(&segments, version, ecc, mask).unwrap();
+            let img = render(&qr, 4, 4);
+            assert_eq!(img.width(), (qr.size() as u32 + 8) * 4);
+
+            let mut prepared = rqrr::PreparedImage::prepare(img);
+            let grids = prepared.detect_grids();
+            assert_eq!(grids.len(), 1, "{}", payload);
+            let (meta, content) = grids[0].decode().unwrap();
+            assert_eq!(content, payload);
+            assert_eq!(meta.version.0, qr.version().value() as usize);
+            if let Some(version) = version {
+                assert_eq!(meta.version.0, version as usize);
+            }
+            assert_eq!(EccLevel::from_format_bits(meta.ecc_level), Some(ecc));
+            if let Some(mask) = mask {
+                assert_eq!(meta.mask, mask as u16);
+            }
+        }
+        // Too long for version 1 at level H
+        let segments = QrSegment::make_segments(&"x".repeat(20));
+        assert!(encode(&segments, Some(1), EccLevel::H, None).is_none());
+    }
+
+    #[test]
+    fn empty_payloads_are_rejected() {
+        let settings = GenerateSettings {
+            payloads: vec!["a".to_string(), String::new()],
+            versions: vec![None],
+            ecc_levels: vec![EccLevel::M],
+            masks: vec![None],
+            module_sizes: vec![2],
+            quiet_zones: vec![4],
+        };
+        let dir = std::env::temp_dir().join(format!("qr-tester-generate-{}", std::process::id()));
+        let error = generate(&settings, &dir).unwrap_err();
+        assert!(error.to_string().contains("Payload 2 is empty"));
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,613 @@
+//! Ground-truth manifests and accuracy metrics
+//!
+//! A manifest maps images to the payloads they are expected to contain. It
+//! is either one file for a whole directory, CSV or JSON, or a sidecar
+//! `<image>.expected.json` next to a single image. Scoring an engine's
+//! symbols against the expected payloads tells whether it found the right
+//! codes, not just how many.
+//!
+//! Text payloads match symbols decoded to that text (see `--charset`); hex
+//! and base64 payloads match the raw bytes. JSON manifests map image paths,
+//! relative to the manifest, to payload lists; sidecars hold just the list:
+//!
+//! ```json
+//! {
+//!   "tickets/a.png": ["hello", { "hex": "00ff10fe" }],
+//!   "empty.png": []
+//! }
+//! ```
+//!
+//! CSV manifests have a `file` column and one of `text`, `hex` or `base64`
+//! per row; an image with several codes has several rows, and an image
+//! with none one row with the payload column left empty.
+
+use anyhow::{Context, Result, bail};
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::engine::DecodedSymbol;
+use crate::payload::{Payload, from_base64, from_hex};
+
+/// One payload an image is expected to contain
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expected {
+    /// Matches a payload decoded to this text, whatever its character set
+    Text(String),
+    /// Matches a payload with exactly these raw bytes
+    Bytes(Vec<u8>),
+}
+
+impl Expected {
+    pub fn matches(&self, payload: &Payload) -> bool {
+        match self {
+            Expected::Text(text) => payload.text.as_ref() == Some(text),
+            Expected::Bytes(bytes) => payload.bytes == *bytes,
+        }
+    }
+}
+
+/// Expected payloads per image
+#[derive(Debug, Default)]
+pub struct GroundTruth {
+    /// Manifest entries keyed by canonical image path
+    entries: HashMap<PathBuf, Vec<Expected>>,
+}
+
+impl GroundTruth {
+    /// Load a manifest; `.csv` files are read as CSV, anything else as JSON
+    pub fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest: {}", path.display()))?;
+        let entries = if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+        {
+            parse_csv(&data)
+        } else {
+            parse_json(&data)
+        }
+        .with_context(|| format!("Failed to parse manifest: {}", path.display()))?;
+
+        let base = path.parent().unwrap_or(Path::new(""));
+        let entries = entries
+            .into_iter()
+            .map(|(image, payloads)| (canonical(&base.join(image)), payloads))
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// Expected payloads of the image at `path`, from the manifest or else
+    /// from a sidecar file; `None` if the image has no ground truth
+    pub fn expected(&self, path: &Path) -> Result<Option<Vec<Expected>>> {
+        if let Some(payloads) = self.entries.get(&canonical(path)) {
+            return Ok(Some(payloads.clone()));
+        }
+
+        let mut sidecar = path.as_os_str().to_owned();
+        sidecar.push(".expected.json");
+        let sidecar = PathBuf::from(sidecar);
+        if !sidecar.is_file() {
+            return Ok(None);
+        }
+        let data = std::fs::read_to_string(&sidecar)
+            .with_context(|| format!("Failed to read sidecar: {}", sidecar.display()))?;
+        let payloads: Vec<ExpectedPayload> = serde_json::from_str(&data)
+            .with_context(|| format!("Failed to parse sidecar: {}", sidecar.display()))?;
+        payloads
+            .into_iter()
+            .map(ExpectedPayload::into_expected)
+            .collect::<Result<_>>()
+            .map(Some)
+            .with_context(|| format!("Invalid payload in sidecar: {}", sidecar.display()))
+    }
+}
+
+/// Canonical form of an image path, so manifest and scan paths compare equal
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// One expected payload in a JSON manifest: text, or raw bytes as hex or base64
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExpectedPayload {
+    Text(String),
+    Encoded {
+        text: Option<String>,
+        hex: Option<String>,
+        base64: Option<String>,
+    },
+}
+
+impl ExpectedPayload {
+    fn into_expected(self) -> Result<Expected> {
+        match self {
+            ExpectedPayload::Text(text)
+            | ExpectedPayload::Encoded {
+                text: Some(text), ..
+            } => Ok(Expected::Text(text)),
+            ExpectedPayload::Encoded { hex: Some(hex), .. } => from_hex(&hex)
+                .map(Expected::Bytes)
+                .map_err(anyhow::Error::msg),
+            ExpectedPayload::Encoded {
+                base64: Some(base64),
+                ..
+            } => from_base64(&base64)
+                .map(Expected::Bytes)
+                .map_err(anyhow::Error::msg),
+            ExpectedPayload::Encoded { .. } => bail!("payload needs text, hex or base64"),
+        }
+    }
+}
+
+fn parse_json(data: &str) -> Result<Vec<(String, Vec<Expected>)>> {
+    let manifest: HashMap<String, Vec<ExpectedPayload>> = serde_json::from_str(data)?;
+    manifest
+        .into_iter()
+        .map(|(image, payloads)| {
+            let payloads = payloads
+                .into_iter()
+                .map(ExpectedPayload::into_expected)
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid payload for {}", image))?;
+            Ok((image, payloads))
+        })
+        .collect()
+}
+
+fn parse_csv(data: &str) -> Result<Vec<(String, Vec<Expected>)>> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+    let file = column("file").context("CSV manifest needs a 'file' column")?;
+    let (text, hex, base64) = (column("text"), column("hex"), column("base64"));
+    if text.or(hex).or(base64).is_none() {
+        bail!("CSV manifest needs a 'text', 'hex' or 'base64' column");
+    }
+
+    let mut entries: Vec<(String, Vec<Expected>)> = Vec::new();
+    for (line, record) in reader.records().enumerate() {
+        let record = record?;
+        let field = |column: Option<usize>| {
+            column
+                .and_then(|i| record.get(i))
+                .filter(|value| !value.is_empty())
+        };
+        let image = field(Some(file)).with_context(|| format!("Row {} has no file", line + 1))?;
+        let payload = if let Some(value) = field(text) {
+            Some(Ok(Expected::Text(value.to_string())))
+        } else if let Some(value) = field(hex) {
+            Some(from_hex(value).map(Expected::Bytes))
+        } else {
+            field(base64).map(|value| from_base64(value).map(Expected::Bytes))
+        };
+        let payload = payload
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid payload in row {}", line + 1))?;
+
+        let pos = match entries.iter().position(|(known, _)| known == image) {
+            Some(pos) => pos,
+            None => {
+                entries.push((image.to_string(), Vec::new()));
+                entries.len() - 1
+            }
+        };
+        entries[pos].1.extend(payload);
+    }
+    Ok(entries)
+}
+
+/// Detection counts of one engine (or all engines together) against the
+/// ground truth
+///
+/// A decoded symbol whose payload is expected is a true positive. Left-over
+/// symbols and left-over expected payloads of the same image are paired up
+/// as misdecodes (a code was found but read wrong); symbols beyond that are
+/// false positives and expected payloads beyond that are misses.
+///
+/// Engines that transcode payloads (zbar-pack for ECI codes, bardecoder)
+/// do not report the bytes a hex or base64 payload names. Their left-over
+/// symbols are paired with left-over byte payloads first and counted as
+/// unverified rather than misdecoded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Accuracy {
+    /// Images with ground truth
+    pub files: usize,
+    /// Expected payloads
+    pub expected: usize,
+    pub true_positives: usize,
+    pub false_positives: usize,
+    pub misdecodes: usize,
+    pub misses: usize,
+    /// Expected byte payloads paired with a symbol whose raw bytes the
+    /// engine did not report, so the two could not be compared
+    pub unverified: usize,
+    /// True positives over decoded symbols other than unverified ones;
+    /// `None` if nothing was decoded
+    pub precision: Option<f64>,
+    /// True positives over expected payloads other than unverified ones;
+    /// `None` if nothing was expected
+    pub recall: Option<f64>,
+}
+
+impl Accuracy {
+    /// Whether exactly the expected payloads were decoded, nothing missed,
+    /// misread or extra
+    pub fn exact(&self) -> bool {
+        self.true_positives + self.unverified == self.expected
+            && self.false_positives == 0
+            && self.misdecodes == 0
+    }
+
+    /// Score the symbols decoded from one image against its expected payloads
+    pub fn score(expected: &[Expected], symbols: &[DecodedSymbol]) -> Self {
+        let mut unmatched: Vec<&Expected> = expected.iter().collect();
+        let mut true_positives = 0;
+        let mut left_over = Vec::new();
+        for symbol in symbols {
+            match unmatched
+                .iter()
+                .position(|expected| expected.matches(&symbol.payload))
+            {
+                Some(pos) => {
+                    unmatched.swap_remove(pos);
+                    true_positives += 1;
+                }
+                None => left_over.push(symbol),
+            }
+        }
+
+        let mut unverified = 0;
+        let mut wrong = 0;
+        for symbol in left_over {
+            let bytes = unmatched
+                .iter()
+                .position(|expected| matches!(expected, Expected::Bytes(_)));
+            match bytes {
+                Some(pos) if !symbol.raw_bytes => {
+                    unmatched.swap_remove(pos);
+                    unverified += 1;
+                }
+                _ => wrong += 1,
+            }
+        }
+        let misdecodes = wrong.min(unmatched.len());
+
+        let mut accuracy = Self {
+            files: 1,
+            expected: expected.len(),
+            true_positives,
+            false_positives: wrong - misdecodes,
+            misdecodes,
+            misses: unmatched.len() - misdecodes,
+            unverified,
+            ..Self::default()
+        };
+        accuracy.update_rates();
+        accuracy
+    }
+
+    /// Add the counts of another image
+    pub fn add(&mut self, other: &Accuracy) {
+        self.files += other.files;
+        self.expected += other.expected;
+        self.true_positives += other.true_positives;
+        self.false_positives += other.false_positives;
+        self.misdecodes += other.misdecodes;
+        self.misses += other.misses;
+        self.unverified += other.unverified;
+        self.update_rates();
+    }
+
+    fn update_rates(&mut self) {
+        let decoded = self.true_positives + self.false_positives + self.misdecodes;
+        self.precision = (decoded > 0).then(|| self.true_positives as f64 / decoded as f64);
+        let expected = self.expected - self.unverified;
+        self.recall = (expected > 0).then(|| self.true_positives as f64 / expected as f64);
+    }
+}
+
+/// Accuracy over all scanned images with ground truth
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AccuracyReport {
+    /// All engines together (the deduplicated codes of each file)
+    pub overall: Accuracy,
+    /// Per engine, in run order
+    pub engines: Vec<EngineAccuracy>,
+}
+
+/// Accuracy of one engine over all scanned images with ground truth
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EngineAccuracy {
+    pub engine_name: String,
+    #[serde(flatten)]
+    pub accuracy: Accuracy,
+}
+
+impl AccuracyReport {
+    /// Add the scores of one image
+    pub fn add(&mut self, overall: &Accuracy, engines: &[(&str, &Accuracy)]) {
+        self.overall.add(overall);
+        for (name, accuracy) in engines {
+            match self.engines.iter_mut().find(|e| e.engine_name == *name) {
+                Some(engine) => engine.accuracy.add(accuracy),
+                None => self.engines.push(EngineAccuracy {
+                    engine_name: name.to_string(),
+                    accuracy: (*accuracy).clone(),
+                }),
+            }
+        }
+    }
+}
+
+/// Warn about manifest entries that no scanned image matched
+pub fn warn_unmatched(ground_truth: &GroundTruth, scanned: &[PathBuf]) {
+    for image in unmatched(ground_truth, scanned) {
+        warn!("Manifest entry not scanned: {}", image.display());
+    }
+}
+
+/// Manifest entries that no scanned image matched, sorted
+fn unmatched<'a>(ground_truth: &'a GroundTruth, scanned: &[PathBuf]) -> Vec<&'a Path> {
+    let scanned: Vec<PathBuf> = scanned.iter().map(|p| canonical(p)).collect();
+    let mut images: Vec<&Path> = ground_truth
+        .entries
+        .keys()
+        .filter(|image| !scanned.contains(image))
+        .map(PathBuf::as_path)
+        .collect();
+    images.sort();
+    images
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::payload::Payload;
+
+    /// An empty scratch directory for one test
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "qr-tester-ground-truth-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn text(text: &str) -> Expected {
+        Expected::Text(text.to_string())
+    }
+
+    fn counts(accuracy: &Accuracy) -> (usize, usize, usize, usize) {
+        (
+            accuracy.true_positives,
+            accuracy.false_positives,
+            accuracy.misdecodes,
+            accuracy.misses,
+        )
+    }
+
+    #[test]
+    fn score_pairs_symbols_and_payloads() {
+        let expected = [text("a"), text("b")];
+
+        let accuracy = Accuracy::score(&expected, &[symbol(b"b"), symbol(b"a")]);
+        assert_eq!(counts(&accuracy), (2, 0, 0, 0));
+        assert!(accuracy.exact());
+        assert_eq!(
+            (accuracy.precision, accuracy.recall),
+            (Some(1.0), Some(1.0))
+        );
+
+        // One read wrong, one extra: the wrong read is paired with "b"
+        let accuracy = Accuracy::score(&expected, &[symbol(b"a"), symbol(b"x"), symbol(b"y")]);
+        assert_eq!(counts(&accuracy), (1, 1, 1, 0));
+        assert_eq!(accuracy.precision, Some(1.0 / 3.0));
+        assert_eq!(accuracy.recall, Some(0.5));
+
+        // Nothing found
+        let accuracy = Accuracy::score(&expected, &[]);
+        assert_eq!(counts(&accuracy), (0, 0, 0, 2));
+        assert_eq!((accuracy.precision, accuracy.recall), (None, Some(0.0)));
+
+        // Nothing expected
+        let accuracy = Accuracy::score(&[], &[symbol(b"a")]);
+        assert_eq!(counts(&accuracy), (0, 1, 0, 0));
+        assert!(!accuracy.exact());
+    }
+
+    #[test]
+    fn score_duplicate_payloads() {
+        // Two codes with the same payload are both expected
+        let expected = [text("a"), text("a")];
+        let accuracy = Accuracy::score(&expected, &[symbol(b"a")]);
+        assert_eq!(counts(&accuracy), (1, 0, 0, 1));
+        let accuracy = Accuracy::score(&expected, &[symbol(b"a"), symbol(b"a")]);
+        assert_eq!(counts(&accuracy), (2, 0, 0, 0));
+        // A third sighting has nothing left to match
+        let accuracy = Accuracy::score(&expected, &[symbol(b"a"), symbol(b"a"), symbol(b"a")]);
+        assert_eq!(counts(&accuracy), (2, 1, 0, 0));
+    }
+
+    #[test]
+    fn score_bytes() {
+        let expected = [Expected::Bytes(vec![0x00, 0xff])];
+        let accuracy = Accuracy::score(&expected, &[symbol(&[0x00, 0xff])]);
+        assert_eq!(counts(&accuracy), (1, 0, 0, 0));
+        let accuracy = Accuracy::score(&expected, &[symbol(&[0x00, 0xfe])]);
+        assert_eq!(counts(&accuracy), (0, 0, 1, 0));
+    }
+
+    #[test]
+    fn accuracy_adds_up() {
+        let mut total = Accuracy::score(&[text("a")], &[symbol(b"a")]);
+        total.add(&Accuracy::score(&[text("b")], &[]));
+        assert_eq!((total.files, total.expected), (2, 2));
+        assert_eq!(counts(&total), (1, 0, 0, 1));
+        assert_eq!(total.recall, Some(0.5));
+    }
+
+    #[test]
+    fn csv_manifest() {
+        let data = "file,text\n\
+                    a.png,plain\n\
+                    a.png,\"comma, \"\"quote\"\"\nand newline\"\n\
+                    empty.png,\n\
+                    b.png,b\n";
+        let entries = parse_csv(data).unwrap();
+        assert_eq!(
+            entries,
+            [
+                (
+                    "a.png".to_string(),
+                    vec![text("plain"), text("comma, \"quote\"\nand newline")]
+                ),
+                ("empty.png".to_string(), vec![]),
+                ("b.png".to_string(), vec![text("b")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn csv_manifest_with_bytes() {
+        let data = "file,text,hex,base64\na.png,,00ff,\nb.png,,,AP8=\nc.png,t,00,\n";
+        let entries = parse_csv(data).unwrap();
+        assert_eq!(entries[0].1, [Expected::Bytes(vec![0x00, 0xff])]);
+        assert_eq!(entries[1].1, [Expected::Bytes(vec![0x00, 0xff])]);
+        // Text takes precedence
+        assert_eq!(entries[2].1, [text("t")]);
+    }
+
+    #[test]
+    fn csv_manifest_errors() {
+        assert!(parse_csv("image,text\na.png,x\n").is_err());
+        assert!(parse_csv("file,payload\na.png,x\n").is_err());
+        assert!(parse_csv("file,text\n,x\n").is_err());
+        assert!(parse_csv("file,hex\na.png,xyz\n").is_err());
+    }
+
+    #[test]
+    fn json_manifest() {
+        let data = r#"{
+            "a.png": ["hello", { "hex": "00ff" }, { "base64": "AP8=" }, { "text": "t" }],
+            "empty.png": []
+        }"#;
+        let mut entries = parse_json(data).unwrap();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            entries,
+            [
+                (
+                    "a.png".to_string(),
+                    vec![
+                        text("hello"),
+                        Expected::Bytes(vec![0x00, 0xff]),
+                        Expected::Bytes(vec![0x00, 0xff]),
+                        text("t"),
+                    ]
+                ),
+                ("empty.png".to_string(), vec![]),
+            ]
+        );
+        assert!(parse_json(r#"{ "a.png": [{ "utf16": "x" }] }"#).is_err());
+        assert!(parse_json(r#"{ "a.png": [{ "hex": "0" }] }"#).is_err());
+        assert!(parse_json(r#"["a.png"]"#).is_err());
+    }
+
+    #[test]
+    fn manifest_paths_and_sidecars() {
+        let dir = scratch("lookup");
+        std::fs::create_dir(dir.join("sub")).unwrap();
+        for image in ["sub/a.png", "b.png", "c.png"] {
+            std::fs::write(dir.join(image), b"").unwrap();
+        }
+        std::fs::write(dir.join("manifest.csv"), "file,text\nsub/a.png,a\n").unwrap();
+        std::fs::write(dir.join("b.png.expected.json"), r#"["b", { "hex": "00" }]"#).unwrap();
+
+        let ground_truth = GroundTruth::load(&dir.join("manifest.csv")).unwrap();
+        // Manifest paths are relative to the manifest
+        let a = dir.join("sub").join("..").join("sub").join("a.png");
+        assert_eq!(ground_truth.expected(&a).unwrap(), Some(vec![text("a")]));
+        assert_eq!(
+            ground_truth.expected(&dir.join("b.png")).unwrap(),
+            Some(vec![text("b"), Expected::Bytes(vec![0x00])])
+        );
+        assert_eq!(ground_truth.expected(&dir.join("c.png")).unwrap(), None);
+
+        std::fs::write(dir.join("c.png.expected.json"), "{").unwrap();
+        assert!(ground_truth.expected(&dir.join("c.png")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unscanned_manifest_entries() {
+        let dir = scratch("unmatched");
+        for image in ["a.png", "b.png"] {
+            std::fs::write(dir.join(image), b"").unwrap();
+        }
+        std::fs::write(
+            dir.join("manifest.json"),
+            r#"{ "a.png": ["a"], "b.png": [] }"#,
+        )
+        .unwrap();
+        let ground_truth = GroundTruth::load(&dir.join("manifest.json")).unwrap();
+        let (a, b) = (canonical(&dir.join("a.png")), canonical(&dir.join("b.png")));
+
+        assert_eq!(
+            unmatched(&ground_truth, &[dir.join("a.png")]),
+            [b.as_path()]
+        );
+        assert_eq!(unmatched(&ground_truth, &[]), [a.as_path(), b.as_path()]);
+        assert!(unmatched(&ground_truth, &[dir.join("b.png"), dir.join("a.png")]).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn symbol(bytes: &[u8]) -> DecodedSymbol {
+        DecodedSymbol::new(Payload::from_bytes(bytes.to_vec()))
+    }
+
+    /// A symbol from an engine that transcodes payloads
+    fn transcoded(text: &str) -> DecodedSymbol {
+        DecodedSymbol {
+            raw_bytes: false,
+            ..DecodedSymbol::new(Payload::from_text(text.to_string()))
+        }
+    }
+
+    #[test]
+    fn transcoded_symbols_are_unverified_against_bytes() {
+        // Shift JIS "テスト" with an ECI, as ZBar hands it back in UTF-8
+        let expected = [Expected::Bytes(vec![0x83, 0x65, 0x83, 0x58, 0x83, 0x67])];
+        let accuracy = Accuracy::score(&expected, &[transcoded("テスト")]);
+        assert_eq!(
+            (
+                accuracy.true_positives,
+                accuracy.misdecodes,
+                accuracy.unverified
+            ),
+            (0, 0, 1)
+        );
+        assert!(accuracy.exact());
+        assert_eq!((accuracy.precision, accuracy.recall), (None, None));
+
+        // A raw-byte engine's wrong bytes are still a misdecode
+        let accuracy = Accuracy::score(&expected, &[symbol(b"tesuto")]);
+        assert_eq!((accuracy.misdecodes, accuracy.unverified), (1, 0));
+
+        // Transcoded symbols still match text and identical bytes
+        let expected = [
+            Expected::Text("テスト".to_string()),
+            Expected::Bytes(b"ab".to_vec()),
+        ];
+        let accuracy = Accuracy::score(&expected, &[transcoded("ab"), transcoded("テスト")]);
+        assert_eq!((accuracy.true_positives, accuracy.unverified), (2, 0));
+        assert_eq!(accuracy.recall, Some(1.0));
+    }
+}
@@ -0,0 +1,303 @@
+//! Engine isolation in worker subprocesses
+//!
+//! `catch_unwind` cannot recover from segfaults or aborts in native engine
+//! code, and nothing in-process can stop an engine stuck in a loop.
+//! [`IsolatedEngine`] forwards every call to a `qr-tester worker` child
+//! process instead and waits for the answer with a timeout. A worker that
+//! crashes or hangs is killed and replaced on the next call.
+//!
+//! Protocol: the parent writes a JSON [`WorkerRequest`] line followed by
+//! `width * height` raw grayscale bytes; the worker answers with one JSON
+//! [`WorkerResponse`] line.
+
+use anyhow::{Context, Result, anyhow};
+use image::GrayImage;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::engine::{
+    DecodedSymbol, Detection, EngineCapabilities, EngineRegistry, QrEngine, StageTiming,
+    panic_message,
+};
+use crate::memory;
+
+/// Failure of an isolated engine call that the engine itself could not report
+#[derive(Debug)]
+pub enum EngineFault {
+    /// The worker process died (segfault, abort, ...)
+    Crashed(String),
+    /// The worker did not answer within the timeout and was killed
+    TimedOut(Duration),
+    /// The engine panicked inside the worker
+    Panicked(String),
+}
+
+impl fmt::Display for EngineFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineFault::Crashed(status) => write!(f, "worker process crashed ({})", status),
+            EngineFault::TimedOut(timeout) => {
+                write!(f, "no answer within {}ms", timeout.as_millis())
+            }
+            EngineFault::Panicked(msg) => write!(f, "engine panicked: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for EngineFault {}
+
+/// Header sent before the raw pixels of each request
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerRequest {
+    width: u32,
+    height: u32,
+}
+
+/// Answer of a worker to one request
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WorkerResponse {
+    /// Found symbols, with the stage timing and heap use measured inside
+    /// the worker
+    Detection(Detection),
+    Error(String),
+    Panic(String),
+}
+
+/// Wrap every engine of `registry` so it runs in worker processes
+///
+/// Workers rebuild their engine from `config`, which must be the config
+/// `registry` was built from.
+pub fn isolate(
+    registry: &EngineRegistry,
+    config: &Config,
+    timeout: Duration,
+) -> Result<EngineRegistry> {
+    let config_json = serde_json::to_string(config).context("Failed to serialize config")?;
+    let mut isolated = EngineRegistry::new();
+    for engine in registry.engines() {
+        isolated.register(Box::new(IsolatedEngine::new(
+            engine.name(),
+            engine.capabilities(),
+            &config_json,
+            timeout,
+        )))?;
+    }
+    Ok(isolated)
+}
+
+/// An engine whose calls run in `qr-tester worker` child processes
+pub struct IsolatedEngine {
+    name: String,
+    capabilities: EngineCapabilities,
+    /// Serialized [`Config`] handed to each worker
+    config_json: String,
+    timeout: Duration,
+    /// Idle workers ready for the next call (one per concurrent caller at most)
+    idle: Mutex<Vec<WorkerProcess>>,
+}
+
+impl IsolatedEngine {
+    pub fn new(
+        name: &str,
+        capabilities: EngineCapabilities,
+        config_json: &str,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            capabilities,
+            config_json: config_json.to_string(),
+            timeout,
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl QrEngine for IsolatedEngine {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn capabilities(&self) -> EngineCapabilities {
+        self.capabilities
+    }
+
+    fn detect(&self, gray_img: &GrayImage) -> Result<Vec<DecodedSymbol>> {
+        self.detect_measured(gray_img)
+            .map(|detection| detection.symbols)
+    }
+
+    fn detect_staged(
+        &self,
+        gray_img: &GrayImage,
+    ) -> Result<(Vec<DecodedSymbol>, Option<StageTiming>)> {
+        self.detect_measured(gray_img)
+            .map(|detection| (detection.symbols, detection.stages))
+    }
+
+    fn detect_measured(&self, gray_img: &GrayImage) -> Result<Detection> {
+        let idle = self.idle.lock().expect("worker pool poisoned").pop();
+        let mut worker = match idle {
+            Some(worker) => worker,
+            None => WorkerProcess::spawn(&self.name, &self.config_json)?,
+        };
+
+        match worker.request(gray_img, self.timeout) {
+            Ok(response) => {
+                self.idle.lock().expect("worker pool poisoned").push(worker);
+                match response {
+                    WorkerResponse::Detection(detection) => Ok(detection),
+                    WorkerResponse::Error(msg) => Err(anyhow!(msg)),
+                    WorkerResponse::Panic(msg) => Err(EngineFault::Panicked(msg).into()),
+                }
+            }
+            Err(fault) => {
+                warn!("{} worker failed: {}", self.name, fault);
+                worker.kill();
+                Err(fault.into())
+            }
+        }
+    }
+}
+
+/// A running worker child process
+struct WorkerProcess {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    /// Response lines read from the worker's stdout by a helper thread
+    responses: Receiver<String>,
+}
+
+impl WorkerProcess {
+    fn spawn(engine_name: &str, config_json: &str) -> Result<Self> {
+        let exe = std::env::current_exe().context("Failed to locate qr-tester executable")?;
+        let mut command = Command::new(exe);
+        command
+            .arg("worker")
+            .arg(engine_name)
+            .arg("--config-json")
+            .arg(config_json);
+        if memory::enabled() {
+            command.arg("--memory");
+        }
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Failed to spawn {} worker", engine_name))?;
+        debug!("Spawned {} worker (pid {})", engine_name, child.id());
+
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take().context("Worker stdout not captured")?;
+
+        // Read on a separate thread so the caller can wait with a timeout
+        let (sender, responses) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            child,
+            stdin,
+            responses,
+        })
+    }
+
+    fn request(
+        &mut self,
+        gray_img: &GrayImage,
+        timeout: Duration,
+    ) -> std::result::Result<WorkerResponse, EngineFault> {
+        let header = WorkerRequest {
+            width: gray_img.width(),
+            height: gray_img.height(),
+        };
+
+        let written = self.stdin.as_mut().map(|stdin| -> std::io::Result<()> {
+            let line = serde_json::to_string(&header)?;
+            stdin.write_all(line.as_bytes())?;
+            stdin.write_all(b"\n")?;
+            stdin.write_all(gray_img.as_raw())?;
+            stdin.flush()
+        });
+        if !matches!(written, Some(Ok(()))) {
+            return Err(EngineFault::Crashed(self.exit_status()));
+        }
+
+        match self.responses.recv_timeout(timeout) {
+            Ok(line) => serde_json::from_str(&line)
+                .map_err(|e| EngineFault::Crashed(format!("unreadable worker response: {}", e))),
+            Err(RecvTimeoutError::Timeout) => Err(EngineFault::TimedOut(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(EngineFault::Crashed(self.exit_status())),
+        }
+    }
+
+    /// Describe how the worker exited, waiting for it if needed
+    fn exit_status(&mut self) -> String {
+        self.stdin = None;
+        match self.child.wait() {
+            Ok(status) => status.to_string(),
+            Err(e) => format!("unknown exit status: {}", e),
+        }
+    }
+
+    fn kill(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Drop for WorkerProcess {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// Serve detection requests for `engine` on stdin/stdout until stdin closes
+pub fn run_worker(engine: &dyn QrEngine) -> Result<()> {
+    let mut input = std::io::stdin().lock();
+    let mut output = std::io::stdout().lock();
+    let mut header = String::new();
+
+    loop {
+        header.clear();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(());
+        }
+
+        let request: WorkerRequest =
+            serde_json::from_str(&header).context("Invalid worker request header")?;
+        let mut pixels = vec![0u8; request.width as usize * request.height as usize];
+        input
+            .read_exact(&mut pixels)
+            .context("Truncated worker request")?;
+        let gray_img = GrayImage::from_raw(request.width, request.height, pixels)
+            .context("Invalid worker request image")?;
+
+        let response = match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            engine.detect_measured(&gray_img)
+        })) {
+            Ok(Ok(detection)) => WorkerResponse::Detection(detection),
+            Ok(Err(e)) => WorkerResponse::Error(format!("{:#}", e)),
+            Err(panic_info) => WorkerResponse::Panic(panic_message(panic_info.as_ref())),
+        };
+
+        serde_json::to_writer(&mut output, &response)?;
+        output.write_all(b"\n")?;
+        output.flush()?;
+    }
+}
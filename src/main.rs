@@ -1,40 +1,340 @@
 mod analyzer;
+mod baseline;
+mod bench;
+mod config;
+mod degradation;
+mod engine;
+mod generator;
+mod ground_truth;
+mod isolation;
+mod memory;
+mod metadata;
+mod payload;
 mod preprocessor;
+mod report;
 mod scanner;
+mod structured_append;
+mod sweep;
 mod timer;
+mod zbar;
 
 use analyzer::QrAnalyzer;
 use anyhow::{Context, Result, bail};
-use clap::Parser;
+use baseline::{Baseline, Comparison};
+use clap::{Parser, Subcommand};
 use colored::Colorize;
+use config::Config;
+use engine::EngineRegistry;
 use log::info;
-use std::path::PathBuf;
+use report::{AnalysisOutput, BenchOutput, Format, ScanOutput, StreamWriter, SweepOutput};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use scanner::QrScanner;
 
+#[global_allocator]
+static ALLOCATOR: memory::CountingAllocator = memory::CountingAllocator;
+
 /// QR code scanning and performance testing tool
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input path (file or directory)
-    #[arg(value_name = "PATH")]
-    input: PathBuf,
+    #[arg(value_name = "PATH", required = true)]
+    input: Option<PathBuf>,
 
     /// Verbose output mode
     #[arg(short, long)]
     verbose: bool,
 
-    /// Output in JSON format
-    #[arg(short, long)]
+    /// Output in JSON format (same as --format json)
+    #[arg(short, long, conflicts_with = "format")]
     json: bool,
 
+    /// Output format (default: from the --output extension, else text)
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Write the report to a file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
     /// Enable debug logging
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     debug: bool,
 
     /// Analyze QR code detection failures in detail
     #[arg(short, long)]
     analyze: bool,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    /// Number of files to scan concurrently (0 = one per CPU)
+    #[arg(short = 'J', long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// Count the allocations and heap peak of every engine call and variant (slows allocation-heavy engines)
+    #[arg(long, conflicts_with = "analyze")]
+    memory: bool,
+
+    /// With --jobs, scan every file a second time on one thread and report that pass's timings and heap figures, so contention does not skew them (codes come from the parallel pass; doubles the scan time)
+    #[arg(long)]
+    serial_timing: bool,
+
+    /// Ground-truth manifest (CSV or JSON) mapping images to expected payloads
+    #[arg(short, long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Degrade each image before scanning, e.g. "blur:2,jpeg:30" (see README for the list)
+    #[arg(long, value_name = "CHAIN", conflicts_with = "analyze")]
+    degrade: Option<String>,
+
+    /// Seed of the random parts of --degrade (noise, warp corners, light direction, occluder)
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "degrade")]
+    degrade_seed: u64,
+
+    #[command(flatten)]
+    baseline: BaselineArgs,
+}
+
+/// Options that pick and configure the engines, shared by scans and benchmarks
+#[derive(clap::Args, Debug)]
+struct EngineArgs {
+    /// Symbologies to decode: qr, micro-qr, rmqr, data-matrix, aztec (comma-separated; default: qr)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    symbologies: Vec<String>,
+
+    /// Character set for payloads that have no ECI and are not UTF-8, e.g. Shift_JIS or GBK
+    #[arg(long, value_name = "NAME")]
+    charset: Option<String>,
+
+    /// Engines to run, in order (comma-separated; default: all)
+    #[arg(short, long, value_name = "NAMES", value_delimiter = ',')]
+    engines: Vec<String>,
+
+    /// Engines to leave out (comma-separated)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    skip_engines: Vec<String>,
+
+    /// Run engines in worker subprocesses so crashes and hangs only fail that call
+    #[arg(long)]
+    isolate: bool,
+
+    /// Per-call timeout for isolated engines, in milliseconds
+    #[arg(
+        long,
+        value_name = "MS",
+        default_value_t = 10_000,
+        requires = "isolate"
+    )]
+    engine_timeout: u64,
+
+    /// JSON config file (command line flags take precedence)
+    #[arg(short, long, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Decode hints for the rxing engine (try-harder,pure-barcode,also-inverted,charset=NAME)
+    #[arg(long, value_name = "HINTS")]
+    rxing_hints: Option<String>,
+
+    /// Add an extra rxing engine with its own hints, e.g. rxing-tryharder=try-harder (repeatable)
+    #[arg(long, value_name = "NAME=HINTS")]
+    rxing_profile: Vec<String>,
+
+    /// Preprocessed variant to scan, e.g. denoised=contrast>median(1)>otsu; replaces the built-in variants (repeatable)
+    #[arg(long, value_name = "NAME=CHAIN")]
+    variant: Vec<String>,
+}
+
+/// Options to save a run as a baseline or compare it with one
+#[derive(clap::Args, Debug)]
+struct BaselineArgs {
+    /// Save this run as a baseline file
+    #[arg(long, value_name = "FILE")]
+    save_baseline: Option<PathBuf>,
+
+    /// Compare this run with a saved baseline and fail on regressions
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// Largest tolerated recall drop against --baseline, in percentage points
+    #[arg(
+        long,
+        value_name = "POINTS",
+        default_value_t = 5.0,
+        requires = "baseline"
+    )]
+    recall_threshold: f64,
+
+    /// Largest tolerated significant slowdown against --baseline, in percent
+    #[arg(long, value_name = "PCT", default_value_t = 5.0, requires = "baseline")]
+    slowdown_threshold: f64,
+}
+
+/// Options of the bench subcommand
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Input path (file or directory)
+    #[arg(value_name = "PATH")]
+    input: PathBuf,
+
+    /// Unmeasured runs per image and engine before timing starts
+    #[arg(long, value_name = "N", default_value_t = 2)]
+    warmup: usize,
+
+    /// Measured runs per image and engine
+    #[arg(short = 'n', long, value_name = "N", default_value_t = 10)]
+    iterations: usize,
+
+    /// Output in JSON format (same as --format json)
+    #[arg(short, long, conflicts_with = "format")]
+    json: bool,
+
+    /// Output format: text, json, csv or markdown (default: from the --output extension, else text)
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Write the report to a file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+
+    #[command(flatten)]
+    baseline: BaselineArgs,
+}
+
+/// Options of the generate subcommand
+#[derive(clap::Args, Debug)]
+struct GenerateArgs {
+    /// Directory to write the images and manifest.csv into
+    #[arg(value_name = "DIR")]
+    output: PathBuf,
+
+    /// Text to encode (repeatable)
+    #[arg(short, long, value_name = "TEXT")]
+    payload: Vec<String>,
+
+    /// File with one text to encode per line
+    #[arg(long, value_name = "FILE")]
+    payload_file: Option<PathBuf>,
+
+    /// Number of random payloads to add
+    #[arg(short, long, value_name = "N", default_value_t = 0)]
+    random: usize,
+
+    /// Length of random payloads in characters, as N or MIN-MAX
+    #[arg(long, value_name = "RANGE", default_value = "8-64")]
+    random_length: String,
+
+    /// Seed of the random payload generator
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    seed: u64,
+
+    /// Symbol versions, e.g. "1-10,40"; "auto" picks the smallest that fits
+    #[arg(long, value_name = "LIST", default_value = "auto")]
+    versions: String,
+
+    /// Error correction levels, e.g. "L,H"
+    #[arg(long, value_name = "LIST", default_value = "M")]
+    ecc: String,
+
+    /// Mask patterns, e.g. "0-7"; "auto" lets the encoder pick
+    #[arg(long, value_name = "LIST", default_value = "auto")]
+    masks: String,
+
+    /// Pixels per module, e.g. "2,4,8"
+    #[arg(long, value_name = "LIST", default_value = "8")]
+    module_sizes: String,
+
+    /// Quiet zone widths in modules, e.g. "0-4"
+    #[arg(long, value_name = "LIST", default_value = "4")]
+    quiet_zones: String,
+}
+
+/// Options of the sweep subcommand
+#[derive(clap::Args, Debug)]
+struct SweepArgs {
+    /// Image to degrade
+    #[arg(value_name = "FILE")]
+    input: PathBuf,
+
+    /// Degradation to vary
+    #[arg(short, long, value_enum)]
+    axis: sweep::Axis,
+
+    /// Values to sweep as FROM:TO[:STEP] (default depends on the axis)
+    #[arg(short, long, value_name = "RANGE", allow_hyphen_values = true)]
+    range: Option<String>,
+
+    /// Seed of the random parts of the degradation (noise, occluder)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    seed: u64,
+
+    /// Ground-truth manifest with the image's expected payloads (default: what the engines decode in the undegraded image)
+    #[arg(short, long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Output in JSON format (same as --format json)
+    #[arg(short, long, conflicts_with = "format")]
+    json: bool,
+
+    /// Output format: text, json or csv (default: from the --output extension, else text)
+    #[arg(short, long, value_enum)]
+    format: Option<Format>,
+
+    /// Write the report to a file instead of stdout
+    #[arg(short, long, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    #[command(flatten)]
+    engine: EngineArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Time each engine over repeated runs per image and report the distribution
+    Bench(Box<BenchArgs>),
+
+    /// Encode QR codes and write them as images with a ground-truth manifest
+    Generate(Box<GenerateArgs>),
+
+    /// Degrade one image in steps along an axis and find where each engine and variant stops decoding
+    Sweep(Box<SweepArgs>),
+
+    /// Print the JSON Schema of a JSON report
+    Schema {
+        /// Report layout to describe
+        #[arg(value_enum, default_value = "scan")]
+        report: report::Report,
+    },
+
+    /// Serve detection requests for one engine on stdin/stdout (used by --isolate)
+    #[command(hide = true)]
+    Worker {
+        /// Engine name
+        engine: String,
+
+        /// Serialized config the engine is built from
+        #[arg(long)]
+        config_json: Option<String>,
+
+        /// Count allocations, as the parent does with --memory
+        #[arg(long)]
+        memory: bool,
+    },
 }
 
 fn main() -> Result<()> {
@@ -48,63 +348,477 @@ fn main() -> Result<()> {
         zbar_pack::set_verbosity(0);
     }
 
+    if let Some(Command::Worker {
+        engine,
+        config_json,
+        memory,
+    }) = &args.command
+    {
+        if *memory {
+            memory::enable();
+        }
+        let config: Config = match config_json {
+            Some(json) => serde_json::from_str(json).context("Invalid worker config")?,
+            None => Config::default(),
+        };
+        let registry = EngineRegistry::from_config(&config)?;
+        let engine = registry
+            .get(engine)
+            .with_context(|| format!("Unknown engine: {}", engine))?;
+        return isolation::run_worker(engine);
+    }
+
+    if let Some(Command::Schema { report }) = &args.command {
+        println!("{}", report::schema(*report)?);
+        return Ok(());
+    }
+
+    if let Some(Command::Generate(generate_args)) = &args.command {
+        let settings = generate_settings(generate_args)?;
+        let summary = generator::generate(&settings, &generate_args.output)?;
+        println!(
+            "Generated {} images in {}",
+            summary.images.to_string().bright_green(),
+            generate_args.output.display()
+        );
+        if summary.skipped > 0 {
+            println!(
+                "Skipped {} combinations whose payload does not fit the version",
+                summary.skipped.to_string().yellow()
+            );
+        }
+        println!("Manifest: {}", summary.manifest.display());
+        return Ok(());
+    }
+
+    if let Some(Command::Sweep(sweep_args)) = &args.command {
+        let SweepArgs {
+            input,
+            axis,
+            range,
+            seed,
+            manifest,
+            json,
+            format,
+            output,
+            engine,
+        } = sweep_args.as_ref();
+        if !input.is_file() {
+            bail!("Sweeps need a single image file: {}", input.display());
+        }
+
+        let output = output.as_deref();
+        let format = output_format(*json, *format, output)?;
+        if !matches!(format, Format::Text | Format::Json | Format::Csv) {
+            bail!("Sweeps only support text, JSON and CSV output");
+        }
+        let (from, to, step) = sweep_range(*axis, range.as_deref()).context("Invalid --range")?;
+        let settings = sweep::SweepSettings {
+            axis: *axis,
+            from,
+            to,
+            step,
+            seed: *seed,
+        };
+
+        let config = load_config(engine)?;
+        let registry = build_registry(engine, &config)?;
+        let mut scanner = QrScanner::new(registry, false);
+        scanner.set_charset(config.charset()?);
+        scanner.set_pipeline(config.pipeline()?);
+        let ground_truth = match manifest {
+            Some(manifest) => ground_truth::GroundTruth::load(manifest)?,
+            None => ground_truth::GroundTruth::default(),
+        };
+        let expected = ground_truth.expected(input)?;
+        if manifest.is_some() && expected.is_none() {
+            bail!("The manifest has no entry for {}", input.display());
+        }
+
+        let img = image::open(input)
+            .with_context(|| format!("Failed to decode image: {}", input.display()))?;
+        let report = sweep::run(&scanner, input, &img, expected, &settings)
+            .with_context(|| format!("Failed to sweep: {}", input.display()))?;
+
+        match format {
+            Format::Json => {
+                let json = serde_json::to_string_pretty(&SweepOutput::new(&report))
+                    .context("Failed to serialize JSON")?;
+                emit(&format!("{}\n", json), output)?;
+            }
+            Format::Csv => emit(&report::sweep_csv(&report)?, output)?,
+            _ => print_sweep(&report),
+        }
+        return Ok(());
+    }
+
+    if let Some(Command::Bench(bench_args)) = &args.command {
+        let BenchArgs {
+            input,
+            warmup,
+            iterations,
+            json,
+            format,
+            output,
+            engine,
+            baseline,
+        } = bench_args.as_ref();
+        if !input.exists() {
+            bail!("Path does not exist: {}", input.display());
+        }
+
+        let output = output.as_deref();
+        let format = output_format(*json, *format, output)?;
+        if !matches!(
+            format,
+            Format::Text | Format::Json | Format::Csv | Format::Markdown
+        ) {
+            bail!("Benchmarks only support text, JSON, CSV and Markdown output");
+        }
+
+        let saved = load_baseline(baseline)?;
+        let config = load_config(engine)?;
+        let registry = build_registry(engine, &config)?;
+        let settings = bench::BenchSettings {
+            warmup: *warmup,
+            iterations: *iterations,
+        };
+        let report = bench::run(&registry, &config.pipeline()?, input, settings)
+            .with_context(|| format!("Failed to benchmark: {}", input.display()))?;
+
+        let comparison = check_baseline(baseline, saved, Baseline::from_bench(&report))?;
+
+        match format {
+            Format::Json => {
+                let json =
+                    serde_json::to_string_pretty(&BenchOutput::new(&report, comparison.as_ref()))
+                        .context("Failed to serialize JSON")?;
+                emit(&format!("{}\n", json), output)?;
+            }
+            Format::Csv => emit(&report::bench_csv(&report)?, output)?,
+            Format::Markdown => emit(&report::bench_markdown(&report), output)?,
+            _ => {
+                print_bench(&report);
+                if let Some(comparison) = &comparison {
+                    print_comparison(comparison);
+                }
+            }
+        }
+
+        return fail_on_regression(comparison.as_ref());
+    }
+
+    let input: &Path = args.input.as_deref().context("Missing input path")?;
+
     info!("QR code scanner starting");
-    info!("Input path: {}", args.input.display());
+    info!("Input path: {}", input.display());
 
     // Validate input path
-    if !args.input.exists() {
-        bail!("Path does not exist: {}", args.input.display());
+    if !input.exists() {
+        bail!("Path does not exist: {}", input.display());
     }
 
+    let output = args.output.as_deref();
+    let format = output_format(args.json, args.format, output)?;
+    let saved = load_baseline(&args.baseline)?;
+    let config = load_config(&args.engine)?;
+    let registry = build_registry(&args.engine, &config)?;
+
     // Handle analyze mode
     if args.analyze {
-        if !args.input.is_file() {
+        if !input.is_file() {
             bail!("Analyze mode requires a single file, not a directory");
         }
 
-        let analyzer = QrAnalyzer::new();
+        let analyzer = QrAnalyzer::new(registry, &config)?;
         let report = analyzer
-            .analyze_file(&args.input)
-            .with_context(|| format!("Failed to analyze file: {}", args.input.display()))?;
+            .analyze_file(input)
+            .with_context(|| format!("Failed to analyze file: {}", input.display()))?;
 
-        if args.json {
-            output_analysis_json(&report)?;
-        } else {
-            analyzer.print_report(&report);
+        match format {
+            Format::Text => analyzer.print_report(&report),
+            Format::Json => output_analysis_json(&report, output)?,
+            _ => bail!("Analyze mode only supports text and JSON output"),
         }
 
         return Ok(());
     }
 
-    let mut scanner = QrScanner::new(args.verbose);
+    if args.memory {
+        memory::enable();
+    }
+    let mut scanner = QrScanner::new(registry, args.verbose);
+    scanner.set_jobs(args.jobs);
+    scanner.set_serial_timing(args.serial_timing);
+    scanner.set_charset(config.charset()?);
+    scanner.set_pipeline(config.pipeline()?);
+    if let Some(spec) = &args.degrade {
+        let chain = degradation::DegradationChain::parse(spec, args.degrade_seed)
+            .context("Invalid --degrade")?;
+        info!("Degrading images with {}", chain);
+        scanner.set_degradation(Some(chain));
+    }
+    if let Some(manifest) = &args.manifest {
+        scanner.set_ground_truth(ground_truth::GroundTruth::load(manifest)?);
+    }
+
+    // Stream results as they come in, if asked to
+    let mut stream = match format {
+        Format::Ndjson => Some(StreamWriter::create(output)?),
+        _ => None,
+    };
+    let mut on_result = |result: &scanner::ScanResult| match &mut stream {
+        Some(stream) => stream.write_file(result),
+        None => Ok(()),
+    };
 
     // Scan based on input type
-    let results = if args.input.is_file() {
+    let results = if input.is_file() {
         info!("Detected single file input");
-        vec![
-            scanner
-                .scan_file(&args.input)
-                .with_context(|| format!("Failed to scan file: {}", args.input.display()))?,
-        ]
-    } else if args.input.is_dir() {
+        let result = scanner
+            .scan_file(input)
+            .with_context(|| format!("Failed to scan file: {}", input.display()))?;
+        on_result(&result)?;
+        vec![result]
+    } else if input.is_dir() {
         info!("Detected directory input");
         scanner
-            .scan_directory(&args.input)
-            .with_context(|| format!("Failed to scan directory: {}", args.input.display()))?
+            .scan_directory(input, on_result)
+            .with_context(|| format!("Failed to scan directory: {}", input.display()))?
     } else {
-        bail!("Unsupported input type: {}", args.input.display());
+        bail!("Unsupported input type: {}", input.display());
     };
 
+    let run = Baseline::from_scan(&results, scanner.stats());
+    let comparison = check_baseline(&args.baseline, saved, run)?;
+
     // Output results
-    if args.json {
-        output_json(&results, scanner.stats())?;
+    let stats = scanner.stats();
+    match format {
+        Format::Text => {
+            output_text(&results, stats, args.verbose);
+            if let Some(comparison) = &comparison {
+                print_comparison(comparison);
+            }
+        }
+        Format::Json => output_json(&results, stats, comparison.as_ref(), output)?,
+        Format::Ndjson => {
+            if let Some(stream) = &mut stream {
+                stream.write_stats(stats, comparison.as_ref())?;
+            }
+        }
+        Format::Csv => emit(&report::csv(&results)?, output)?,
+        Format::Markdown => emit(&report::markdown(&results, stats), output)?,
+        Format::Html => emit(&report::html(&results, stats), output)?,
+    }
+
+    fail_on_regression(comparison.as_ref())
+}
+
+/// Resolve the output format from --json, --format and the --output extension
+fn output_format(json: bool, format: Option<Format>, output: Option<&Path>) -> Result<Format> {
+    let format = if json {
+        Format::Json
+    } else if let Some(format) = format {
+        format
+    } else if let Some(output) = output {
+        Format::from_extension(output).with_context(|| {
+            format!(
+                "Cannot tell the format of {} from its extension; pass --format",
+                output.display()
+            )
+        })?
     } else {
-        output_text(&results, scanner.stats(), args.verbose);
+        Format::Text
+    };
+
+    if format == Format::Text && output.is_some() {
+        bail!("Text output only goes to the terminal; pick another --format for --output");
+    }
+    Ok(format)
+}
+
+/// Collect the payloads and parse the lists of the generate subcommand
+fn generate_settings(args: &GenerateArgs) -> Result<generator::GenerateSettings> {
+    let mut payloads = args.payload.clone();
+    if let Some(path) = &args.payload_file {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read payload file: {}", path.display()))?;
+        payloads.extend(
+            data.lines()
+                .filter(|line| !line.is_empty())
+                .map(str::to_string),
+        );
+    }
+    if args.random > 0 {
+        let lengths =
+            generator::parse_range(&args.random_length).context("Invalid --random-length")?;
+        if lengths.0 == 0 {
+            bail!("Invalid --random-length: payloads need at least one character");
+        }
+        payloads.extend(generator::random_payloads(args.random, lengths, args.seed));
+    }
+    if payloads.is_empty() {
+        bail!("Nothing to encode: pass --payload, --payload-file or --random");
+    }
+
+    let list = |spec: &str, min, max, auto, option: &str| {
+        generator::parse_list(spec, min, max, auto).with_context(|| format!("Invalid {}", option))
+    };
+    let fixed = |values: Vec<Option<u32>>| values.into_iter().flatten().collect();
+    Ok(generator::GenerateSettings {
+        payloads,
+        versions: list(&args.versions, 1, 40, true, "--versions")?
+            .into_iter()
+            .map(|v| v.map(|v| v as u8))
+            .collect(),
+        ecc_levels: args
+            .ecc
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_>>()
+            .context("Invalid --ecc")?,
+        masks: list(&args.masks, 0, 7, true, "--masks")?
+            .into_iter()
+            .map(|m| m.map(|m| m as u8))
+            .collect(),
+        module_sizes: fixed(list(&args.module_sizes, 1, 100, false, "--module-sizes")?),
+        quiet_zones: fixed(list(&args.quiet_zones, 0, 100, false, "--quiet-zones")?),
+    })
+}
+
+/// Parse FROM:TO[:STEP], taking what is left out from the axis defaults
+fn sweep_range(axis: sweep::Axis, range: Option<&str>) -> Result<(f64, f64, f64)> {
+    let (from, to, step) = axis.default_range();
+    let Some(range) = range else {
+        return Ok((from, to, step));
+    };
+    let values = range
+        .split(':')
+        .map(|value| {
+            value
+                .trim()
+                .parse::<f64>()
+                .with_context(|| format!("Invalid number '{}'", value.trim()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    match values[..] {
+        [from, to] => Ok((from, to, step)),
+        [from, to, step] => Ok((from, to, step)),
+        _ => bail!("Expected FROM:TO or FROM:TO:STEP, got '{}'", range),
+    }
+}
+
+/// Print a report, or write it to `output` if given
+fn emit(report: &str, output: Option<&Path>) -> Result<()> {
+    match output {
+        Some(path) => {
+            std::fs::write(path, report)
+                .with_context(|| format!("Failed to write report: {}", path.display()))?;
+            info!("Wrote report to {}", path.display());
+        }
+        None => print!("{}", report),
+    }
+    Ok(())
+}
+
+/// Load the baseline to compare with, if any, before doing any work
+fn load_baseline(args: &BaselineArgs) -> Result<Option<Baseline>> {
+    args.baseline.as_deref().map(Baseline::load).transpose()
+}
+
+/// Compare `run` with the saved baseline and save it as a new one, as asked
+fn check_baseline(
+    args: &BaselineArgs,
+    saved: Option<Baseline>,
+    run: Baseline,
+) -> Result<Option<Comparison>> {
+    let comparison = saved.map(|saved| {
+        baseline::compare(&saved, &run, args.recall_threshold, args.slowdown_threshold)
+    });
+    if let Some(path) = &args.save_baseline {
+        run.save(path)?;
+        info!("Saved baseline to {}", path.display());
     }
+    Ok(comparison)
+}
 
+/// Turn a regression against the baseline into an error, so CI runs fail
+fn fail_on_regression(comparison: Option<&Comparison>) -> Result<()> {
+    let Some(comparison) = comparison else {
+        return Ok(());
+    };
+    let regressed: Vec<&str> = comparison
+        .engines
+        .iter()
+        .filter(|e| !e.regressions.is_empty())
+        .map(|e| e.engine_name.as_str())
+        .collect();
+    if !regressed.is_empty() {
+        bail!("Regression against baseline: {}", regressed.join(", "));
+    }
     Ok(())
 }
 
+/// Load the config file, if any, and apply command line overrides
+fn load_config(args: &EngineArgs) -> Result<Config> {
+    let mut config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    if !args.symbologies.is_empty() {
+        config.symbologies = args
+            .symbologies
+            .iter()
+            .map(|name| name.parse())
+            .collect::<Result<_>>()
+            .context("Invalid --symbologies")?;
+    }
+    if let Some(charset) = &args.charset {
+        config.charset = Some(charset.clone());
+    }
+    config.charset().context("Invalid charset")?;
+    if let Some(hints) = &args.rxing_hints {
+        config.rxing = hints.parse().context("Invalid --rxing-hints")?;
+    }
+    for spec in &args.rxing_profile {
+        config
+            .add_rxing_profile(spec)
+            .context("Invalid --rxing-profile")?;
+    }
+    if !args.variant.is_empty() {
+        config.variants = args.variant.clone();
+    }
+    config
+        .pipeline()
+        .context("Invalid preprocessing variants")?;
+
+    Ok(config)
+}
+
+/// Resolve which detection engines to run, isolating them if asked to
+fn build_registry(args: &EngineArgs, config: &Config) -> Result<EngineRegistry> {
+    let registry = EngineRegistry::from_config(config)?
+        .select(&args.engines, &args.skip_engines)
+        .and_then(|registry| registry.supporting(&config.symbologies))
+        .context("Invalid engine selection")?;
+    info!("Engines: {}", registry.names().join(", "));
+
+    if !args.isolate {
+        return Ok(registry);
+    }
+    info!(
+        "Isolating engines in worker processes ({}ms timeout)",
+        args.engine_timeout
+    );
+    isolation::isolate(
+        &registry,
+        config,
+        Duration::from_millis(args.engine_timeout),
+    )
+}
+
 /// Initialize logging system
 fn init_logger(debug: bool) {
     let log_level = if debug { "debug" } else { "error" };
@@ -115,7 +829,7 @@ fn init_logger(debug: bool) {
 }
 
 /// Output text format results (tabular)
-fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, _verbose: bool) {
+fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, verbose: bool) {
     if results.is_empty() {
         println!("No image files found");
         return;
@@ -127,20 +841,22 @@ fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, _verbo
             .bright_cyan()
             .bold()
     );
-    println!("{}", "=".repeat(140).bright_blue());
+    println!("{}", "=".repeat(150).bright_blue());
 
     // Table header
     println!(
-        "{:<50} {:>10} {:>6} {:>14} {:>14} {:>14} {:>14}",
+        "{:<50} {:>10} {:>5} {:>12} {:>12} {:>12} {:>12} {:>12} {:>12}",
         "File Path".bright_yellow(),
         "Engine".bright_yellow(),
         "QRs".bright_yellow(),
         "Preprocess".bright_yellow(),
-        "Detection".bright_yellow(),
+        "Engine Time".bright_yellow(),
+        "Detect".bright_yellow(),
+        "Decode".bright_yellow(),
         "Total".bright_yellow(),
         "File Total".bright_yellow()
     );
-    println!("{}", "-".repeat(140));
+    println!("{}", "-".repeat(150));
 
     // Data rows - show each engine's result separately
     for result in results {
@@ -155,10 +871,11 @@ fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, _verbo
 
         let path = truncate_path(&result.file_path.display().to_string(), 50);
         let timing = &result.timing.qr_detection;
-        let file_total = timing.to_ms(timing.total);
+        let file_total = timing.to_ms(result.timing.total);
 
         // Show each engine's results as separate rows
-        let preprocess_time = timing.to_ms(timing.to_grayscale);
+        let preprocess_time = timing.to_ms(timing.preprocess());
+        let stage = |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{:.2}ms", ms));
 
         for (idx, engine_result) in result.engine_results.iter().enumerate() {
             let display_path = if idx == 0 {
@@ -177,22 +894,33 @@ fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, _verbo
             let engine_total = preprocess_time + detection_time;
 
             println!(
-                "{:<50} {:>10} {:>6} {:>13.2}ms {:>13.2}ms {:>13.2}ms {:>14}",
+                "{:<50} {:>10} {:>5} {:>10.2}ms {:>10.2}ms {:>12} {:>12} {:>10.2}ms {:>12}",
                 display_path,
                 engine_result.engine_name,
                 engine_result.qr_codes.len(),
                 preprocess_time,
                 detection_time,
+                stage(engine_result.detect_ms),
+                stage(engine_result.decode_ms),
                 engine_total,
                 display_file_total
             );
         }
 
+        for set in &result.structured_append {
+            print_appended_set(set);
+        }
+
+        if verbose {
+            print_stages(&result.timing);
+            print_variant_grid(&result.engine_results);
+        }
+
         // Add separator between files
-        println!("{}", "-".repeat(140).dimmed());
+        println!("{}", "-".repeat(150).dimmed());
     }
 
-    println!("{}", "=".repeat(140).bright_blue());
+    println!("{}", "=".repeat(150).bright_blue());
 
     // Statistics
     println!(
@@ -204,9 +932,460 @@ fn output_text(results: &[scanner::ScanResult], stats: &timer::ScanStats, _verbo
         stats.total_qr_codes.to_string().bright_green(),
         stats.avg_duration_per_file.as_secs_f64() * 1000.0
     );
+    if stats.threads > 1 {
+        println!(
+            "{}  Scanned on {} threads",
+            "Note:".bright_cyan(),
+            stats.threads
+        );
+    }
+    if !stats.memory.is_empty() {
+        print_memory(&stats.memory);
+    }
+    if let Some(report) = &stats.accuracy {
+        print_accuracy(report);
+    }
     println!();
 }
 
+/// Print heap use per engine call, lowest mean peak first
+fn print_memory(engines: &[memory::EngineMemory]) {
+    println!("\n{}", "Memory (per engine call):".bright_cyan());
+    println!(
+        "{:>12} {:>8} {:>10} {:>12} {:>12} {:>12}",
+        "Engine".bright_yellow(),
+        "Calls".bright_yellow(),
+        "Allocs".bright_yellow(),
+        "Allocated".bright_yellow(),
+        "Mean Peak".bright_yellow(),
+        "Max Peak".bright_yellow()
+    );
+
+    let mut ranked: Vec<&memory::EngineMemory> = engines.iter().collect();
+    ranked.sort_by(|a, b| a.mean_peak_bytes.total_cmp(&b.mean_peak_bytes));
+    for engine in ranked {
+        let calls = engine.calls.max(1) as f64;
+        println!(
+            "{:>12} {:>8} {:>10.0} {:>12} {:>12} {:>12}",
+            engine.engine_name,
+            engine.calls,
+            engine.allocations as f64 / calls,
+            memory::format_bytes(engine.allocated_bytes as f64 / calls),
+            memory::format_bytes(engine.mean_peak_bytes),
+            memory::format_bytes(engine.max_peak_bytes as f64)
+        );
+    }
+}
+
+/// Print where each engine and variant broke during a sweep, with a strip of
+/// passes (#) and failures (.) over the swept values
+fn print_sweep(report: &sweep::SweepReport) {
+    let source = if report.ground_truth {
+        "from the ground truth"
+    } else {
+        "decoded in the undegraded image"
+    };
+    println!(
+        "{} {} ({} values from {} to {}), {} expected payloads {}",
+        "Sweep:".bright_cyan(),
+        report.label,
+        report.values.len(),
+        report.values.first().copied().unwrap_or_default(),
+        report.values.last().copied().unwrap_or_default(),
+        report.expected_payloads,
+        source
+    );
+    println!(
+        "{:>12} {:>18} {:>10} {:>10} {:>8}  {}",
+        "Engine".bright_yellow(),
+        "Variant".bright_yellow(),
+        "Last Pass".bright_yellow(),
+        "First Fail".bright_yellow(),
+        "Passed".bright_yellow(),
+        "Pattern".bright_yellow()
+    );
+
+    let value = |value: Option<f64>| value.map_or("-".to_string(), |v| v.to_string());
+    for series in &report.series {
+        let pattern: String = report
+            .values
+            .iter()
+            .map(|&v| match series.points.iter().find(|p| p.value == v) {
+                Some(point) if point.passed => '#',
+                Some(_) => '.',
+                None => ' ',
+            })
+            .collect();
+        let pattern = if series.first_fail.is_none() {
+            pattern.green()
+        } else if series.last_pass.is_none() {
+            pattern.red()
+        } else {
+            pattern.yellow()
+        };
+        println!(
+            "{:>12} {:>18} {:>10} {:>10} {:>8}  {}",
+            series.engine_name,
+            series.variant_name,
+            value(series.last_pass),
+            value(series.first_fail),
+            format!("{}/{}", series.passes, report.values.len()),
+            pattern
+        );
+    }
+}
+
+/// Print precision and recall against the ground truth, per engine and overall
+fn print_accuracy(report: &ground_truth::AccuracyReport) {
+    println!(
+        "\n{}  {} files with ground truth, {} expected codes",
+        "Accuracy:".bright_cyan(),
+        report.overall.files,
+        report.overall.expected
+    );
+    println!(
+        "{:>12} {:>6} {:>6} {:>10} {:>6} {:>10} {:>10}",
+        "Engine".bright_yellow(),
+        "TP".bright_yellow(),
+        "FP".bright_yellow(),
+        "Misdecode".bright_yellow(),
+        "Miss".bright_yellow(),
+        "Precision".bright_yellow(),
+        "Recall".bright_yellow()
+    );
+
+    let rate = |rate: Option<f64>| rate.map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0));
+    let rows = report
+        .engines
+        .iter()
+        .map(|e| (e.engine_name.as_str(), &e.accuracy))
+        .chain(std::iter::once(("all", &report.overall)));
+    for (name, accuracy) in rows {
+        println!(
+            "{:>12} {:>6} {:>6} {:>10} {:>6} {:>10} {:>10}",
+            name,
+            accuracy.true_positives,
+            accuracy.false_positives,
+            accuracy.misdecodes,
+            accuracy.misses,
+            rate(accuracy.precision),
+            rate(accuracy.recall)
+        );
+    }
+}
+
+/// Print benchmark timings per file and engine, then per engine over all files
+fn print_bench(report: &bench::BenchReport) {
+    let env = &report.environment;
+    println!(
+        "\n{}",
+        format!(
+            "Benchmark Results ({} iterations after {} warmup runs)",
+            report.iterations, report.warmup
+        )
+        .bright_cyan()
+        .bold()
+    );
+    println!(
+        "{}  qr-tester {} ({} build) on {}/{}, {}{} CPUs; reference workload {:.2}ms",
+        "Environment:".bright_cyan(),
+        env.version,
+        env.build,
+        env.os,
+        env.arch,
+        env.cpu
+            .as_ref()
+            .map_or(String::new(), |cpu| format!("{}, ", cpu)),
+        env.cpus,
+        env.reference_ms
+    );
+    println!("{}", "=".repeat(150).bright_blue());
+
+    let header = |first: &str| {
+        println!(
+            "{:<50} {:>10} {:>5} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>8} {:>8}",
+            first.bright_yellow(),
+            "Engine".bright_yellow(),
+            "QRs".bright_yellow(),
+            "Min".bright_yellow(),
+            "Median".bright_yellow(),
+            "Mean".bright_yellow(),
+            "p95".bright_yellow(),
+            "p99".bright_yellow(),
+            "StdDev".bright_yellow(),
+            "Outliers".bright_yellow(),
+            "xRef".bright_yellow()
+        );
+        println!("{}", "-".repeat(150));
+    };
+    let row = |first: String, engine: &bench::EngineBench| {
+        let summary = &engine.summary;
+        let outliers = format!("{:>8}", summary.outliers.len());
+        let mut line = format!(
+            "{:<50} {:>10} {:>5} {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms {:>8.2}ms {} {:>8.2}",
+            first,
+            engine.engine_name,
+            engine.qr_codes,
+            summary.min_ms,
+            summary.median_ms,
+            summary.mean_ms,
+            summary.p95_ms,
+            summary.p99_ms,
+            summary.std_dev_ms,
+            if summary.outliers.is_empty() {
+                outliers.normal()
+            } else {
+                outliers.yellow()
+            },
+            engine.relative_median
+        );
+        if engine.failures > 0 {
+            line.push_str(&format!(" {}", format!("{} failed", engine.failures).red()));
+        }
+        println!("{}", line);
+    };
+
+    header("File Path");
+    for file in &report.files {
+        let path = truncate_path(&file.file_path, 50);
+        for (idx, engine) in file.engines.iter().enumerate() {
+            row(
+                if idx == 0 {
+                    path.clone()
+                } else {
+                    String::new()
+                },
+                engine,
+            );
+        }
+        println!("{}", "-".repeat(150).dimmed());
+    }
+    for failed in &report.failed_files {
+        println!(
+            "{:<50} {} {}",
+            truncate_path(&failed.file_path, 50),
+            "FAILED".red(),
+            failed.error.dimmed()
+        );
+    }
+
+    if report.files.len() > 1 {
+        println!();
+        header("All Files");
+        for (idx, engine) in report.engines.iter().enumerate() {
+            let label = if idx == 0 {
+                format!("{} files", report.files.len())
+            } else {
+                String::new()
+            };
+            row(label, engine);
+        }
+    }
+    println!("{}", "=".repeat(150).bright_blue());
+    println!(
+        "{}  one sample is one pass over all variants; outliers are beyond 1.5 IQR; xRef is the median over the reference workload",
+        "Note:".bright_cyan()
+    );
+    println!();
+}
+
+/// Print what changed per engine since the baseline
+fn print_comparison(comparison: &Comparison) {
+    println!("{}", "Baseline Comparison".bright_cyan().bold());
+    println!("{}", "=".repeat(100).bright_blue());
+    println!(
+        "{:>12} {:>16} {:>8} {:>8} {:>24} {:>10} {:>8}",
+        "Engine".bright_yellow(),
+        "Recall".bright_yellow(),
+        "Failing".bright_yellow(),
+        "Passing".bright_yellow(),
+        "Time".bright_yellow(),
+        "Change".bright_yellow(),
+        "p".bright_yellow()
+    );
+    println!("{}", "-".repeat(100));
+
+    let rate = |rate: Option<f64>| rate.map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0));
+    for engine in &comparison.engines {
+        let recall = format!(
+            "{} -> {}",
+            rate(engine.recall_before),
+            rate(engine.recall_after)
+        );
+        let time = match (engine.time_before_ms, engine.time_after_ms) {
+            (Some(before), Some(after)) => format!("{:.2}ms -> {:.2}ms", before, after),
+            _ => "-".to_string(),
+        };
+        let change = engine
+            .time_change_pct
+            .map_or("-".to_string(), |c| format!("{:+.1}%", c));
+        let p = engine
+            .p_value
+            .map_or("-".to_string(), |p| format!("{:.3}", p));
+        let line = format!(
+            "{:>12} {:>16} {:>8} {:>8} {:>24} {:>10} {:>8}",
+            engine.engine_name,
+            recall,
+            engine.newly_failing.len(),
+            engine.newly_passing.len(),
+            time,
+            change,
+            p
+        );
+        if engine.regressions.is_empty() {
+            println!("{}", line);
+        } else {
+            println!("{}  {}", line.red(), engine.regressions.join("; ").red());
+        }
+        for path in &engine.newly_failing {
+            println!("{:>12} {} {}", "", "newly failing:".red(), path);
+        }
+        for path in &engine.newly_passing {
+            println!("{:>12} {} {}", "", "newly passing:".green(), path);
+        }
+    }
+
+    for name in &comparison.only_in_baseline {
+        println!("{:>12} {}", name, "not run (in baseline only)".dimmed());
+    }
+    for name in &comparison.only_in_run {
+        println!("{:>12} {}", name, "not in baseline".dimmed());
+    }
+    println!("{}", "=".repeat(100).bright_blue());
+    let verdict = if comparison.regressed() {
+        "regression".red()
+    } else {
+        "no regression".green()
+    };
+    println!(
+        "{}  {} (recall drop over {} points, or over {}% slower with p < {})",
+        "Result:".bright_cyan(),
+        verdict,
+        comparison.recall_threshold_points,
+        comparison.slowdown_threshold_pct,
+        baseline::SIGNIFICANCE_LEVEL
+    );
+    println!();
+}
+
+/// Print one Structured Append set found in a file
+fn print_appended_set(set: &structured_append::AppendedSet) {
+    let label = format!("  Structured Append (parity 0x{:02x}):", set.parity);
+    let mut status = if set.is_complete() {
+        format!("complete, {} symbols", set.total).green()
+    } else {
+        let missing: Vec<_> = set.missing.iter().map(|i| i.to_string()).collect();
+        format!(
+            "{}/{} symbols, missing {}",
+            set.found.len(),
+            set.total,
+            missing.join(", ")
+        )
+        .yellow()
+    }
+    .to_string();
+    if set.parity_ok == Some(false) {
+        status.push_str(&format!(", {}", "parity mismatch".red()));
+    }
+    if !set.in_order {
+        status.push_str(", decoded out of order");
+    }
+    if !set.conflicting.is_empty() {
+        status.push_str(&format!(", conflicting symbols {:?}", set.conflicting));
+    }
+    println!("{} {}", label.bright_cyan(), status);
+}
+
+/// Print the time of each stage before the engines run, and the heap peak
+/// of each variant
+fn print_stages(timing: &timer::ScanTiming) {
+    let detection = &timing.qr_detection;
+    let variants: Vec<String> = detection
+        .variants
+        .iter()
+        .map(|v| {
+            format!(
+                "{} {:.2}ms{}",
+                v.variant_name,
+                detection.to_ms(v.duration),
+                v.memory.map_or(String::new(), |m| format!(
+                    "/{}",
+                    memory::format_bytes(m.peak_bytes as f64)
+                ))
+            )
+        })
+        .collect();
+    let degrade = if timing.degrade.is_zero() {
+        String::new()
+    } else {
+        format!(", degrade {:.2}ms", detection.to_ms(timing.degrade))
+    };
+    println!(
+        "{}",
+        format!(
+            "  Read {:.2}ms, decode {:.2}ms{}, resize {:.2}ms; variants: {}",
+            detection.to_ms(timing.read_file),
+            detection.to_ms(timing.decode_image),
+            degrade,
+            detection.to_ms(detection.resize),
+            variants.join(", ")
+        )
+        .dimmed()
+    );
+}
+
+/// Print the engine × variant matrix for one file (codes found and time per cell)
+fn print_variant_grid(engine_results: &[scanner::EngineResult]) {
+    let Some(first) = engine_results.first() else {
+        return;
+    };
+
+    let col_width = first
+        .variants
+        .iter()
+        .map(|v| v.variant_name.chars().count())
+        .max()
+        .unwrap_or(0)
+        .max(12);
+
+    let mut header = format!("{:>61}", "");
+    for variant in &first.variants {
+        header.push_str(&format!(
+            " {:>width$}",
+            variant.variant_name,
+            width = col_width
+        ));
+    }
+    println!("{}", header.dimmed());
+
+    for engine_result in engine_results {
+        let mut row = format!("{:>61}", engine_result.engine_name);
+        for variant in &engine_result.variants {
+            let cell = match &variant.outcome {
+                scanner::VariantOutcome::Ok if variant.qr_codes.is_empty() => {
+                    format!("- {:.1}ms", variant.duration_ms)
+                }
+                scanner::VariantOutcome::Ok => {
+                    format!("{} {:.1}ms", variant.qr_codes.len(), variant.duration_ms)
+                }
+                scanner::VariantOutcome::Error(_) => "ERROR".to_string(),
+                scanner::VariantOutcome::Panic(_) => "PANIC".to_string(),
+                scanner::VariantOutcome::Crash(_) => "CRASH".to_string(),
+                scanner::VariantOutcome::Timeout(_) => "TIMEOUT".to_string(),
+            };
+            let cell = format!(" {:>width$}", cell, width = col_width);
+            match &variant.outcome {
+                scanner::VariantOutcome::Ok if variant.qr_codes.is_empty() => {
+                    row.push_str(&cell.dimmed().to_string())
+                }
+                scanner::VariantOutcome::Ok => row.push_str(&cell.green().to_string()),
+                _ => row.push_str(&cell.red().to_string()),
+            }
+        }
+        println!("{}", row);
+    }
+}
+
 /// Truncate path to fit column width (handles UTF-8 safely)
 fn truncate_path(path: &str, max_len: usize) -> String {
     // Use char count instead of byte count for proper handling
@@ -229,109 +1408,20 @@ fn truncate_path(path: &str, max_len: usize) -> String {
 }
 
 /// Output results in JSON format
-fn output_json(results: &[scanner::ScanResult], stats: &timer::ScanStats) -> Result<()> {
-    #[derive(serde::Serialize)]
-    struct JsonOutput<'a> {
-        results: Vec<JsonResult>,
-        stats: &'a timer::ScanStats,
-    }
-
-    #[derive(serde::Serialize)]
-    struct JsonResult {
-        file_path: String,
-        qr_codes: Vec<String>,
-        timing: timer::ScanTiming,
-        success: bool,
-        error: Option<String>,
-    }
-
-    let json_results: Vec<JsonResult> = results
-        .iter()
-        .map(|r| JsonResult {
-            file_path: r.file_path.display().to_string(),
-            qr_codes: r.qr_codes.clone(),
-            timing: r.timing.clone(),
-            success: r.success,
-            error: r.error.clone(),
-        })
-        .collect();
-
-    let output = JsonOutput {
-        results: json_results,
-        stats,
-    };
-
-    let json = serde_json::to_string_pretty(&output).context("Failed to serialize JSON")?;
-
-    println!("{}", json);
-
-    Ok(())
+fn output_json(
+    results: &[scanner::ScanResult],
+    stats: &timer::ScanStats,
+    comparison: Option<&Comparison>,
+    output: Option<&Path>,
+) -> Result<()> {
+    let json = serde_json::to_string_pretty(&ScanOutput::new(results, stats, comparison))
+        .context("Failed to serialize JSON")?;
+    emit(&format!("{}\n", json), output)
 }
 
 /// Output analysis results in JSON format
-fn output_analysis_json(report: &analyzer::AnalysisReport) -> Result<()> {
-    #[derive(serde::Serialize)]
-    struct JsonAnalysisOutput {
-        file_path: String,
-        image_size: (u32, u32),
-        variants_tested: usize,
-        overall_success: bool,
-        engine_analyses: Vec<JsonEngineAnalysis>,
-        recommendations: Vec<String>,
-    }
-
-    #[derive(serde::Serialize)]
-    struct JsonEngineAnalysis {
-        engine_name: String,
-        grids_detected: usize,
-        success: bool,
-        summary: String,
-        decode_results: Vec<JsonGridAnalysis>,
-    }
-
-    #[derive(serde::Serialize)]
-    struct JsonGridAnalysis {
-        grid_index: usize,
-        decode_success: bool,
-        error_type: Option<String>,
-        error_detail: String,
-        content: Option<String>,
-    }
-
-    let engine_analyses: Vec<JsonEngineAnalysis> = report
-        .engine_analyses
-        .iter()
-        .map(|a| JsonEngineAnalysis {
-            engine_name: a.engine_name.clone(),
-            grids_detected: a.grids_detected,
-            success: a.success,
-            summary: a.summary.clone(),
-            decode_results: a
-                .decode_results
-                .iter()
-                .map(|r| JsonGridAnalysis {
-                    grid_index: r.grid_index,
-                    decode_success: r.decode_success,
-                    error_type: r.error_type.clone(),
-                    error_detail: r.error_detail.clone(),
-                    content: r.content.clone(),
-                })
-                .collect(),
-        })
-        .collect();
-
-    let output = JsonAnalysisOutput {
-        file_path: report.file_path.clone(),
-        image_size: report.image_size,
-        variants_tested: report.variants_tested,
-        overall_success: report.overall_success,
-        engine_analyses,
-        recommendations: report.recommendations.clone(),
-    };
-
-    let json = serde_json::to_string_pretty(&output).context("Failed to serialize JSON")?;
-
-    println!("{}", json);
-
-    Ok(())
+fn output_analysis_json(report: &analyzer::AnalysisReport, output: Option<&Path>) -> Result<()> {
+    let json = serde_json::to_string_pretty(&AnalysisOutput::new(report))
+        .context("Failed to serialize JSON")?;
+    emit(&format!("{}\n", json), output)
 }
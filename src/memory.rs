@@ -0,0 +1,221 @@
+//! Heap accounting
+//!
+//! [`CountingAllocator`] wraps the system allocator and keeps per-thread
+//! counters, so [`measure`] can attribute allocations to one call even while
+//! other threads scan in parallel. Memory freed on a different thread than
+//! the one that allocated it is credited to the freeing thread.
+//!
+//! Counting is off until [`enable`] is called (`--memory`): with it off, the
+//! allocator only checks a flag, so runs that do not ask for heap figures
+//! are timed without the bookkeeping.
+//!
+//! Only allocations through the Rust allocator are seen: native code that
+//! calls `malloc` directly (ZBar) is invisible here.
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether allocations are counted
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Start counting allocations; call before the work to be measured
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether [`enable`] was called
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Global allocator that counts allocations and tracks the heap peak
+pub struct CountingAllocator;
+
+struct Counters {
+    /// Bytes currently allocated by this thread (negative if it freed more
+    /// than it allocated)
+    current: Cell<isize>,
+    /// Highest `current` since the innermost running [`measure`] started
+    peak: Cell<isize>,
+    allocations: Cell<u64>,
+    allocated: Cell<u64>,
+}
+
+thread_local! {
+    static COUNTERS: Counters = const {
+        Counters {
+            current: Cell::new(0),
+            peak: Cell::new(0),
+            allocations: Cell::new(0),
+            allocated: Cell::new(0),
+        }
+    };
+}
+
+/// Account for `grown` new bytes and `freed` released ones; the counters
+/// are gone while a thread shuts down, so that is ignored
+fn record(grown: usize, freed: usize, allocation: bool) {
+    if !enabled() {
+        return;
+    }
+    let _ = COUNTERS.try_with(|c| {
+        let current = c.current.get() + grown as isize - freed as isize;
+        c.current.set(current);
+        if current > c.peak.get() {
+            c.peak.set(current);
+        }
+        if allocation {
+            c.allocations.set(c.allocations.get() + 1);
+            c.allocated.set(c.allocated.get() + grown as u64);
+        }
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            record(layout.size(), 0, true);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc_zeroed(layout) };
+        if !ptr.is_null() {
+            record(layout.size(), 0, true);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        record(0, layout.size(), false);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { System.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() {
+            record(new_size, layout.size(), true);
+        }
+        new_ptr
+    }
+}
+
+/// Heap use of one call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MemoryUsage {
+    /// Allocations and reallocations made
+    pub allocations: u64,
+    /// Bytes requested by those (a reallocation counts its new size)
+    pub allocated_bytes: u64,
+    /// Highest heap use above the level at the start of the call
+    pub peak_bytes: u64,
+    /// Heap still held when the call returned, e.g. its result (negative if
+    /// the call freed memory allocated before it)
+    pub retained_bytes: i64,
+}
+
+impl MemoryUsage {
+    /// Combine the usage of consecutive calls: counts add up, the peak is the
+    /// highest of either
+    pub fn add(&mut self, other: &MemoryUsage) {
+        self.allocations += other.allocations;
+        self.allocated_bytes += other.allocated_bytes;
+        self.peak_bytes = self.peak_bytes.max(other.peak_bytes);
+        self.retained_bytes += other.retained_bytes;
+    }
+}
+
+/// Restores the enclosing measurement's peak, also if the call panics
+struct PeakGuard(isize);
+
+impl Drop for PeakGuard {
+    fn drop(&mut self) {
+        let _ = COUNTERS.try_with(|c| c.peak.set(c.peak.get().max(self.0)));
+    }
+}
+
+/// Run `f` and measure the heap it used on this thread; `None` unless
+/// counting is enabled
+pub fn measure<T>(f: impl FnOnce() -> T) -> (T, Option<MemoryUsage>) {
+    if !enabled() {
+        return (f(), None);
+    }
+    let (current, peak, allocations, allocated) = COUNTERS.with(|c| {
+        let start = (
+            c.current.get(),
+            c.peak.get(),
+            c.allocations.get(),
+            c.allocated.get(),
+        );
+        c.peak.set(c.current.get());
+        start
+    });
+    let guard = PeakGuard(peak);
+
+    let value = f();
+
+    let usage = COUNTERS.with(|c| MemoryUsage {
+        allocations: c.allocations.get() - allocations,
+        allocated_bytes: c.allocated.get() - allocated,
+        peak_bytes: (c.peak.get() - current).max(0) as u64,
+        retained_bytes: (c.current.get() - current) as i64,
+    });
+    drop(guard);
+    (value, Some(usage))
+}
+
+/// Memory use of one engine over all calls of a scan
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct EngineMemory {
+    pub engine_name: String,
+    /// Measured engine calls (one per file and variant)
+    pub calls: usize,
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+    /// Highest peak of any call
+    pub max_peak_bytes: u64,
+    /// Average peak per call
+    pub mean_peak_bytes: f64,
+    #[serde(skip)]
+    peak_sum: u64,
+}
+
+impl EngineMemory {
+    pub fn new(engine_name: &str) -> Self {
+        Self {
+            engine_name: engine_name.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Add one call
+    pub fn add(&mut self, usage: &MemoryUsage) {
+        self.calls += 1;
+        self.allocations += usage.allocations;
+        self.allocated_bytes += usage.allocated_bytes;
+        self.max_peak_bytes = self.max_peak_bytes.max(usage.peak_bytes);
+        self.peak_sum += usage.peak_bytes;
+        self.mean_peak_bytes = self.peak_sum as f64 / self.calls as f64;
+    }
+}
+
+/// Human-readable byte count
+pub fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", value, UNITS[unit])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
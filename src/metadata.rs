@@ -0,0 +1,476 @@
+//! Structured QR symbol metadata
+//!
+//! Engines expose different subsets of a symbol's parameters: rqrr reports
+//! version, ECC level and mask; quircs adds the ECI; rxing only reports the
+//! ECC level but hands back the corrected data codewords, from which
+//! [`read_segments`] recovers the segment modes, ECI, Structured Append
+//! header and raw payload bytes.
+
+use anyhow::{Result, bail};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Parameters of one decoded symbol; fields an engine does not expose are `None`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SymbolMetadata {
+    /// Symbol version (1-40)
+    pub version: Option<u32>,
+    /// Error correction level
+    pub ecc_level: Option<EccLevel>,
+    /// Data mask pattern (0-7)
+    pub mask: Option<u8>,
+    /// Modes of the data segments, in symbol order
+    pub modes: Option<Vec<SegmentMode>>,
+    /// First ECI assignment number, if the symbol has an ECI designator
+    pub eci: Option<u32>,
+    /// Position in a Structured Append set, if the symbol is part of one
+    pub structured_append: Option<StructuredAppend>,
+}
+
+impl SymbolMetadata {
+    /// Fill fields that are unknown here from another engine's view of the
+    /// same symbol
+    pub fn fill_missing(&mut self, other: &SymbolMetadata) {
+        self.version = self.version.or(other.version);
+        self.ecc_level = self.ecc_level.or(other.ecc_level);
+        self.mask = self.mask.or(other.mask);
+        if self.modes.is_none() {
+            self.modes = other.modes.clone();
+        }
+        self.eci = self.eci.or(other.eci);
+        self.structured_append = self.structured_append.or(other.structured_append);
+    }
+}
+
+/// Structured Append header of one symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub struct StructuredAppend {
+    /// Position of this symbol in the set, from 0
+    pub index: u8,
+    /// Number of symbols in the set (1-16)
+    pub total: u8,
+    /// XOR of all payload bytes of the whole set; identifies the set
+    pub parity: u8,
+}
+
+/// QR error correction level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum EccLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl EccLevel {
+    /// Level from the two format information bits, as rqrr and quircs report it
+    pub fn from_format_bits(bits: u16) -> Option<Self> {
+        match bits {
+            0 => Some(EccLevel::M),
+            1 => Some(EccLevel::L),
+            2 => Some(EccLevel::H),
+            3 => Some(EccLevel::Q),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for EccLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(level: &str) -> Result<Self> {
+        match level.trim().to_ascii_uppercase().as_str() {
+            "L" => Ok(EccLevel::L),
+            "M" => Ok(EccLevel::M),
+            "Q" => Ok(EccLevel::Q),
+            "H" => Ok(EccLevel::H),
+            _ => bail!("Unknown ECC level '{}' (expected L, M, Q or H)", level),
+        }
+    }
+}
+
+/// Encoding mode of a data segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SegmentMode {
+    Numeric,
+    Alphanumeric,
+    Byte,
+    Kanji,
+    Hanzi,
+}
+
+/// Segment modes, first ECI, Structured Append header and payload of a symbol
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Segments {
+    pub modes: Vec<SegmentMode>,
+    pub eci: Option<u32>,
+    pub structured_append: Option<StructuredAppend>,
+    /// Payload bytes as rqrr and quircs produce them: digits and
+    /// alphanumerics as ASCII, byte segments verbatim, Kanji as Shift JIS
+    /// and Hanzi as GB 2312 byte pairs
+    pub data: Vec<u8>,
+}
+
+/// Walk the corrected data codewords of a version `version` symbol
+///
+/// Returns `None` if the bit stream is malformed.
+pub fn read_segments(data_codewords: &[u8], version: u32) -> Option<Segments> {
+    // Character count indicator widths for numeric, alphanumeric, byte and kanji
+    let count_bits: [usize; 4] = match version {
+        1..=9 => [10, 9, 8, 8],
+        10..=26 => [12, 11, 16, 10],
+        27..=40 => [14, 13, 16, 12],
+        _ => return None,
+    };
+
+    let mut bits = BitReader::new(data_codewords);
+    let mut segments = Segments::default();
+
+    while bits.remaining() >= 4 {
+        let mode = match bits.take(4)? {
+            0b0000 => break,
+            0b0001 => SegmentMode::Numeric,
+            0b0010 => SegmentMode::Alphanumeric,
+            0b0100 => SegmentMode::Byte,
+            0b1000 => SegmentMode::Kanji,
+            0b1101 => {
+                // Only the GB 2312 subset is defined
+                if bits.take(4)? != 0b0001 {
+                    return None;
+                }
+                SegmentMode::Hanzi
+            }
+            0b0111 => {
+                let eci = read_eci(&mut bits)?;
+                segments.eci.get_or_insert(eci);
+                continue;
+            }
+            0b0011 => {
+                let header = StructuredAppend {
+                    index: bits.take(4)? as u8,
+                    total: bits.take(4)? as u8 + 1,
+                    parity: bits.take(8)? as u8,
+                };
+                segments.structured_append.get_or_insert(header);
+                continue;
+            }
+            // FNC1 in first position carries no data, in second an application indicator
+            0b0101 => continue,
+            0b1001 => {
+                bits.take(8)?;
+                continue;
+            }
+            _ => return None,
+        };
+
+        let count_width = match mode {
+            SegmentMode::Numeric => count_bits[0],
+            SegmentMode::Alphanumeric => count_bits[1],
+            SegmentMode::Byte => count_bits[2],
+            SegmentMode::Kanji | SegmentMode::Hanzi => count_bits[3],
+        };
+        let count = bits.take(count_width)? as usize;
+        read_data(&mut bits, mode, count, &mut segments.data)?;
+        segments.modes.push(mode);
+    }
+
+    Some(segments)
+}
+
+/// Read `count` characters of a `mode` segment into `data`
+fn read_data(
+    bits: &mut BitReader,
+    mode: SegmentMode,
+    count: usize,
+    data: &mut Vec<u8>,
+) -> Option<()> {
+    const ALPHANUMERIC: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+    match mode {
+        SegmentMode::Numeric => {
+            let mut left = count;
+            while left > 0 {
+                let digits = left.min(3);
+                let value = bits.take([0, 4, 7, 10][digits])?;
+                if value >= 10u32.pow(digits as u32) {
+                    return None;
+                }
+                data.extend(format!("{:0digits$}", value).bytes());
+                left -= digits;
+            }
+        }
+        SegmentMode::Alphanumeric => {
+            for _ in 0..count / 2 {
+                let value = bits.take(11)? as usize;
+                data.push(*ALPHANUMERIC.get(value / 45)?);
+                data.push(ALPHANUMERIC[value % 45]);
+            }
+            if count % 2 == 1 {
+                data.push(*ALPHANUMERIC.get(bits.take(6)? as usize)?);
+            }
+        }
+        SegmentMode::Byte => {
+            for _ in 0..count {
+                data.push(bits.take(8)? as u8);
+            }
+        }
+        SegmentMode::Kanji => {
+            for _ in 0..count {
+                let value = bits.take(13)?;
+                let pair = ((value / 0xc0) << 8) | (value % 0xc0);
+                let sjis = if pair + 0x8140 <= 0x9ffc {
+                    pair + 0x8140
+                } else {
+                    pair + 0xc140
+                };
+                data.extend_from_slice(&(sjis as u16).to_be_bytes());
+            }
+        }
+        SegmentMode::Hanzi => {
+            for _ in 0..count {
+                let value = bits.take(13)?;
+                let pair = ((value / 0x60) << 8) | (value % 0x60);
+                let gb = if pair < 0x0a00 {
+                    pair + 0xa1a1
+                } else {
+                    pair + 0xa6a1
+                };
+                data.extend_from_slice(&(gb as u16).to_be_bytes());
+            }
+        }
+    }
+    Some(())
+}
+
+/// Read an ECI designator of one to three bytes
+fn read_eci(bits: &mut BitReader) -> Option<u32> {
+    let first = bits.take(8)?;
+    if first & 0x80 == 0 {
+        Some(first)
+    } else if first & 0xc0 == 0x80 {
+        Some((first & 0x3f) << 8 | bits.take(8)?)
+    } else if first & 0xe0 == 0xc0 {
+        Some((first & 0x1f) << 16 | bits.take(16)?)
+    } else {
+        None
+    }
+}
+
+/// MSB-first reader over a byte slice
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.data.len() * 8 - self.pos
+    }
+
+    fn take(&mut self, n: usize) -> Option<u32> {
+        if n > self.remaining() {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = self.data[self.pos / 8] >> (7 - self.pos % 8) & 1;
+            value = value << 1 | bit as u32;
+            self.pos += 1;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcodegen::{BitBuffer, QrSegment, QrSegmentMode};
+
+    /// Data codewords as an encoder lays them out: each segment's mode
+    /// indicator, character count and data, then the terminator, zero bits
+    /// up to a byte boundary and one round of pad codewords
+    fn codewords(segments: &[QrSegment], version: u32) -> Vec<u8> {
+        let mut bits = BitBuffer(Vec::new());
+        for segment in segments {
+            append_segment(&mut bits, segment, version);
+        }
+        finish(bits)
+    }
+
+    fn append_segment(bits: &mut BitBuffer, segment: &QrSegment, version: u32) {
+        let column = match version {
+            1..=9 => 0,
+            10..=26 => 1,
+            _ => 2,
+        };
+        let (indicator, count_bits) = match segment.mode() {
+            QrSegmentMode::Numeric => (0b0001, [10, 12, 14][column]),
+            QrSegmentMode::Alphanumeric => (0b0010, [9, 11, 13][column]),
+            QrSegmentMode::Byte => (0b0100, [8, 16, 16][column]),
+            QrSegmentMode::Kanji => (0b1000, [8, 10, 12][column]),
+            QrSegmentMode::Eci => (0b0111, 0),
+        };
+        bits.append_bits(indicator, 4);
+        bits.append_bits(segment.num_chars() as u32, count_bits);
+        bits.0.extend(segment.data());
+    }
+
+    fn finish(mut bits: BitBuffer) -> Vec<u8> {
+        bits.append_bits(0, 4);
+        while !bits.0.len().is_multiple_of(8) {
+            bits.0.push(false);
+        }
+        let mut bytes: Vec<u8> = bits
+            .0
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8))
+            .collect();
+        bytes.extend([0xec, 0x11]);
+        bytes
+    }
+
+    #[test]
+    fn numeric_example_from_the_standard() {
+        // ISO/IEC 18004 Annex I: "01234567" in a 1-M symbol
+        let data = [
+            0x10, 0x20, 0x0c, 0x56, 0x61, 0x80, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11, 0xec, 0x11,
+            0xec, 0x11,
+        ];
+        let segments = read_segments(&data, 1).unwrap();
+        assert_eq!(segments.modes, [SegmentMode::Numeric]);
+        assert_eq!(segments.data, b"01234567");
+        assert_eq!(segments.eci, None);
+        assert_eq!(segments.structured_append, None);
+    }
+
+    #[test]
+    fn mixed_modes() {
+        let segments = [
+            QrSegment::make_numeric("31415926535"),
+            QrSegment::make_alphanumeric("HELLO WORLD $%*+-./:"),
+            QrSegment::make_bytes("héllo".as_bytes()),
+        ];
+        let parsed = read_segments(&codewords(&segments, 2), 2).unwrap();
+        assert_eq!(
+            parsed.modes,
+            [
+                SegmentMode::Numeric,
+                SegmentMode::Alphanumeric,
+                SegmentMode::Byte
+            ]
+        );
+        assert_eq!(
+            parsed.data,
+            "31415926535HELLO WORLD $%*+-./:héllo".as_bytes()
+        );
+    }
+
+    #[test]
+    fn numeric_remainders() {
+        for digits in ["1", "12", "123", "1234", "12345"] {
+            let parsed = read_segments(&codewords(&[QrSegment::make_numeric(digits)], 1), 1);
+            assert_eq!(parsed.unwrap().data, digits.as_bytes());
+        }
+    }
+
+    #[test]
+    fn count_widths_follow_the_version() {
+        let payload = vec![b'x'; 300];
+        for version in [10, 27] {
+            let data = codewords(&[QrSegment::make_bytes(&payload)], version);
+            let parsed = read_segments(&data, version).unwrap();
+            assert_eq!(parsed.modes, [SegmentMode::Byte]);
+            assert_eq!(parsed.data, payload);
+        }
+        let digits = "9".repeat(1100);
+        let data = codewords(&[QrSegment::make_numeric(&digits)], 27);
+        assert_eq!(read_segments(&data, 27).unwrap().data, digits.as_bytes());
+    }
+
+    #[test]
+    fn eci_designators() {
+        // One, two and three byte designators; only the first ECI is kept
+        for eci in [26, 1000, 100_000] {
+            let segments = [
+                QrSegment::make_eci(eci),
+                QrSegment::make_bytes(b"abc"),
+                QrSegment::make_eci(3),
+            ];
+            let parsed = read_segments(&codewords(&segments, 1), 1).unwrap();
+            assert_eq!(parsed.eci, Some(eci));
+            assert_eq!(parsed.modes, [SegmentMode::Byte]);
+            assert_eq!(parsed.data, b"abc");
+        }
+    }
+
+    #[test]
+    fn kanji_example_from_the_standard() {
+        // ISO/IEC 18004 7.4.6: 点 (0x935F) and 茗 (0xE4AA) in Shift JIS
+        let mut bits = BitBuffer(Vec::new());
+        bits.append_bits(0b1000, 4);
+        bits.append_bits(2, 8);
+        bits.append_bits(0x0d9f, 13);
+        bits.append_bits(0x1aaa, 13);
+        let parsed = read_segments(&finish(bits), 1).unwrap();
+        assert_eq!(parsed.modes, [SegmentMode::Kanji]);
+        assert_eq!(parsed.data, [0x93, 0x5f, 0xe4, 0xaa]);
+    }
+
+    #[test]
+    fn hanzi_gb2312_subset() {
+        // 、 (0xA1A2) from the first range, 啊 (0xB0A1) from the second
+        let mut bits = BitBuffer(Vec::new());
+        bits.append_bits(0b1101, 4);
+        bits.append_bits(0b0001, 4);
+        bits.append_bits(2, 8);
+        bits.append_bits(0x0001, 13);
+        bits.append_bits(0x03c0, 13);
+        let parsed = read_segments(&finish(bits), 1).unwrap();
+        assert_eq!(parsed.modes, [SegmentMode::Hanzi]);
+        assert_eq!(parsed.data, [0xa1, 0xa2, 0xb0, 0xa1]);
+    }
+
+    #[test]
+    fn structured_append_header() {
+        let mut bits = BitBuffer(Vec::new());
+        bits.append_bits(0b0011, 4);
+        bits.append_bits(2, 4);
+        bits.append_bits(4, 4);
+        bits.append_bits(0x5a, 8);
+        append_segment(&mut bits, &QrSegment::make_alphanumeric("PART3"), 1);
+        let parsed = read_segments(&finish(bits), 1).unwrap();
+        assert_eq!(
+            parsed.structured_append,
+            Some(StructuredAppend {
+                index: 2,
+                total: 5,
+                parity: 0x5a
+            })
+        );
+        assert_eq!(parsed.modes, [SegmentMode::Alphanumeric]);
+        assert_eq!(parsed.data, b"PART3");
+    }
+
+    #[test]
+    fn malformed_streams() {
+        let data = codewords(&[QrSegment::make_bytes(b"abcdef")], 1);
+        // Cut off inside the byte segment
+        assert_eq!(read_segments(&data[..4], 1), None);
+        // Version out of range
+        assert_eq!(read_segments(&data, 41), None);
+        // Reserved mode indicator
+        assert_eq!(read_segments(&[0b1111_0000], 1), None);
+        // Numeric group above 999
+        let mut bits = BitBuffer(Vec::new());
+        bits.append_bits(0b0001, 4);
+        bits.append_bits(3, 10);
+        bits.append_bits(1000, 10);
+        assert_eq!(read_segments(&finish(bits), 1), None);
+    }
+}
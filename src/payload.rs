@@ -0,0 +1,418 @@
+//! Binary-safe QR payloads
+//!
+//! Engines hand back payloads in different shapes: rqrr and quircs expose the
+//! raw bytes, rxing a decoded string plus byte segments, bardecoder and zbar
+//! only text. [`Payload`] keeps the raw bytes as the identity of a code, so
+//! binary payloads compare equal across engines instead of being dropped.
+//!
+//! The text is decoded from the bytes in one place, [`Payload::decode_text`],
+//! so every engine reports the same text for the same bytes whatever
+//! character set it would have guessed on its own.
+
+use anyhow::{Result, anyhow};
+use encoding::types::{DecoderTrap, EncodingRef};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::metadata::{SegmentMode, SymbolMetadata};
+
+/// Content of one decoded symbol
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(into = "PayloadRepr", try_from = "PayloadRepr")]
+pub struct Payload {
+    /// Raw payload bytes
+    pub bytes: Vec<u8>,
+    /// Text interpretation, if the payload is text
+    pub text: Option<String>,
+    /// Character set `text` was decoded with
+    pub encoding: Option<String>,
+}
+
+impl Payload {
+    /// Payload from raw bytes; text is set when the bytes are valid UTF-8
+    pub fn from_bytes(bytes: Vec<u8>) -> Self {
+        let text = String::from_utf8(bytes.clone()).ok();
+        Self {
+            encoding: text.as_ref().map(|_| Charset::UTF8.name().to_string()),
+            bytes,
+            text,
+        }
+    }
+
+    /// Payload from an engine that only reports text
+    pub fn from_text(text: String) -> Self {
+        Self {
+            bytes: text.as_bytes().to_vec(),
+            text: Some(text),
+            encoding: Some(Charset::UTF8.name().to_string()),
+        }
+    }
+
+    /// Decode the text from the raw bytes of a symbol with `metadata`
+    ///
+    /// The character set comes from, in order: the ECI designator, Kanji or
+    /// Hanzi segments (Shift JIS and GB 2312), UTF-8 if the bytes are valid
+    /// UTF-8, and `fallback`. A character set that cannot decode the bytes
+    /// is skipped; if none can, the payload is binary and has no text.
+    pub fn decode_text(&mut self, metadata: &SymbolMetadata, fallback: Option<Charset>) {
+        let modes = metadata.modes.as_deref().unwrap_or_default();
+        let implied = if modes.contains(&SegmentMode::Kanji) {
+            Charset::from_name("Shift_JIS")
+        } else if modes.contains(&SegmentMode::Hanzi) {
+            Charset::from_name("GB2312")
+        } else {
+            None
+        };
+
+        let decoded = [
+            metadata.eci.and_then(Charset::from_eci),
+            implied,
+            Some(Charset::UTF8),
+            fallback,
+        ]
+        .into_iter()
+        .flatten()
+        .find_map(|charset| Some((charset.decode(&self.bytes)?, charset)));
+
+        (self.text, self.encoding) = match decoded {
+            Some((text, charset)) => (Some(text), Some(charset.name().to_string())),
+            None => (None, None),
+        };
+    }
+
+    /// Human-readable form: the text, or a hex dump for binary payloads
+    pub fn display(&self) -> Cow<'_, str> {
+        match &self.text {
+            Some(text) => Cow::Borrowed(text),
+            None => Cow::Owned(format!("<{} bytes: {}>", self.bytes.len(), self.to_hex())),
+        }
+    }
+
+    /// Lowercase hex encoding of the raw bytes
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.bytes)
+    }
+
+    /// Standard base64 encoding (with padding) of the raw bytes
+    pub fn to_base64(&self) -> String {
+        to_base64(&self.bytes)
+    }
+}
+
+/// A character set payload bytes can be decoded with
+#[derive(Clone, Copy)]
+pub struct Charset(EncodingRef);
+
+impl Charset {
+    pub const UTF8: Charset = Charset(encoding::all::UTF_8);
+
+    /// Look up a character set by name, e.g. `Shift_JIS`, `GBK` or `ISO-8859-1`
+    pub fn from_name(name: &str) -> Option<Self> {
+        // The WHATWG labels map ISO-8859-1 and US-ASCII to windows-1252
+        match name.trim().to_ascii_lowercase().as_str() {
+            "iso-8859-1" | "iso8859-1" | "latin1" => Some(Charset(encoding::all::ISO_8859_1)),
+            "us-ascii" | "ascii" => Some(Charset(encoding::all::ASCII)),
+            label => encoding::label::encoding_from_whatwg_label(label).map(Charset),
+        }
+    }
+
+    /// Character set assigned to an ECI designator; `None` for binary data
+    /// and unsupported assignments
+    pub fn from_eci(eci: u32) -> Option<Self> {
+        let name = match eci {
+            1 | 3 => "ISO-8859-1",
+            4..=13 | 15..=18 => return Self::from_name(&format!("ISO-8859-{}", eci - 2)),
+            20 => "Shift_JIS",
+            21 => "windows-1250",
+            22 => "windows-1251",
+            23 => "windows-1252",
+            24 => "windows-1256",
+            25 => "UTF-16BE",
+            26 => "UTF-8",
+            27 | 170 => "US-ASCII",
+            28 => "Big5",
+            29 => "GB2312",
+            30 => "EUC-KR",
+            32 => "GB18030",
+            33 => "UTF-16LE",
+            _ => return None,
+        };
+        Self::from_name(name)
+    }
+
+    /// Canonical name, as reported in [`Payload::encoding`]
+    pub fn name(&self) -> &'static str {
+        self.0.whatwg_name().unwrap_or(self.0.name())
+    }
+
+    /// Decode `bytes`, or `None` if they are not valid in this character set
+    pub fn decode(&self, bytes: &[u8]) -> Option<String> {
+        self.0.decode(bytes, DecoderTrap::Strict).ok()
+    }
+}
+
+impl fmt::Debug for Charset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Charset({})", self.name())
+    }
+}
+
+impl FromStr for Charset {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        Self::from_name(name).ok_or_else(|| anyhow!("Unknown character set '{}'", name))
+    }
+}
+
+/// JSON form of a [`Payload`]: text plus hex and base64 of the raw bytes
+#[derive(Serialize, Deserialize, JsonSchema)]
+struct PayloadRepr {
+    text: Option<String>,
+    encoding: Option<String>,
+    hex: String,
+    base64: String,
+}
+
+impl From<Payload> for PayloadRepr {
+    fn from(payload: Payload) -> Self {
+        Self {
+            hex: payload.to_hex(),
+            base64: payload.to_base64(),
+            text: payload.text,
+            encoding: payload.encoding,
+        }
+    }
+}
+
+impl TryFrom<PayloadRepr> for Payload {
+    type Error = String;
+
+    fn try_from(repr: PayloadRepr) -> Result<Self, Self::Error> {
+        Ok(Self {
+            bytes: from_hex(&repr.hex)?,
+            text: repr.text,
+            encoding: repr.encoding,
+        })
+    }
+}
+
+/// Lowercase hex encoding
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode a hex string
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, String> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(format!("odd-length hex string: {}", hex));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("invalid hex string: {}", hex))
+        })
+        .collect()
+}
+
+/// Decode a standard base64 string (padding optional)
+pub fn from_base64(base64: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(base64.len() / 4 * 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in base64.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return Err(format!("invalid base64 string: {}", base64)),
+        };
+        acc = acc << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+/// Standard base64 encoding with padding
+pub fn to_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::SymbolMetadata;
+
+    /// "テスト" in Shift JIS
+    const SJIS_TEST: [u8; 6] = [0x83, 0x65, 0x83, 0x58, 0x83, 0x67];
+
+    fn decoded(bytes: &[u8], metadata: &SymbolMetadata, fallback: Option<&str>) -> Payload {
+        let mut payload = Payload::from_bytes(bytes.to_vec());
+        payload.decode_text(metadata, fallback.map(|name| name.parse().unwrap()));
+        payload
+    }
+
+    fn text_and_encoding(payload: &Payload) -> (Option<&str>, Option<&str>) {
+        (payload.text.as_deref(), payload.encoding.as_deref())
+    }
+
+    #[test]
+    fn base64_padding() {
+        // RFC 4648 test vectors: no padding, two and one padding characters
+        for (bytes, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(to_base64(bytes.as_bytes()), encoded);
+            assert_eq!(from_base64(encoded).unwrap(), bytes.as_bytes());
+            assert_eq!(
+                from_base64(encoded.trim_end_matches('=')).unwrap(),
+                bytes.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn base64_and_hex_round_trip_all_bytes() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for len in [254, 255, 256] {
+            assert_eq!(
+                from_base64(&to_base64(&bytes[..len])).unwrap(),
+                &bytes[..len]
+            );
+            assert_eq!(from_hex(&to_hex(&bytes[..len])).unwrap(), &bytes[..len]);
+        }
+        assert!(from_base64("Zm9v!").is_err());
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn eci_takes_precedence() {
+        let metadata = SymbolMetadata {
+            eci: Some(20),
+            ..SymbolMetadata::default()
+        };
+        let payload = decoded(&SJIS_TEST, &metadata, None);
+        assert_eq!(
+            text_and_encoding(&payload),
+            (Some("テスト"), Some("shift_jis"))
+        );
+
+        // Valid UTF-8 is still read in the ECI's character set
+        let metadata = SymbolMetadata {
+            eci: Some(3),
+            ..SymbolMetadata::default()
+        };
+        let payload = decoded("é".as_bytes(), &metadata, None);
+        assert_eq!(payload.text.as_deref(), Some("Ã©"));
+    }
+
+    #[test]
+    fn kanji_and_hanzi_segments_imply_a_charset() {
+        let metadata = SymbolMetadata {
+            modes: Some(vec![SegmentMode::Kanji]),
+            ..SymbolMetadata::default()
+        };
+        let payload = decoded(&SJIS_TEST, &metadata, None);
+        assert_eq!(
+            text_and_encoding(&payload),
+            (Some("テスト"), Some("shift_jis"))
+        );
+
+        // "啊" in GB 2312
+        let metadata = SymbolMetadata {
+            modes: Some(vec![SegmentMode::Hanzi]),
+            ..SymbolMetadata::default()
+        };
+        let payload = decoded(&[0xb0, 0xa1], &metadata, None);
+        assert_eq!(payload.text.as_deref(), Some("啊"));
+    }
+
+    #[test]
+    fn utf8_before_fallback() {
+        let payload = decoded(
+            "テスト".as_bytes(),
+            &SymbolMetadata::default(),
+            Some("Shift_JIS"),
+        );
+        assert_eq!(text_and_encoding(&payload), (Some("テスト"), Some("utf-8")));
+    }
+
+    #[test]
+    fn fallback_gives_the_same_text_as_utf8() {
+        let sjis = decoded(&SJIS_TEST, &SymbolMetadata::default(), Some("Shift_JIS"));
+        let utf8 = decoded(
+            "テスト".as_bytes(),
+            &SymbolMetadata::default(),
+            Some("Shift_JIS"),
+        );
+        assert_eq!(sjis.text, utf8.text);
+        assert_eq!(sjis.encoding.as_deref(), Some("shift_jis"));
+        assert_ne!(sjis.bytes, utf8.bytes);
+    }
+
+    #[test]
+    fn undecodable_charsets_are_skipped() {
+        // An ECI that cannot decode the bytes falls through to the fallback
+        let metadata = SymbolMetadata {
+            eci: Some(26),
+            ..SymbolMetadata::default()
+        };
+        let payload = decoded(&SJIS_TEST, &metadata, Some("Shift_JIS"));
+        assert_eq!(
+            text_and_encoding(&payload),
+            (Some("テスト"), Some("shift_jis"))
+        );
+
+        // Without a fallback, non-UTF-8 bytes are binary
+        let payload = decoded(&SJIS_TEST, &SymbolMetadata::default(), None);
+        assert_eq!(text_and_encoding(&payload), (None, None));
+        assert!(payload.display().starts_with("<6 bytes: 836583"));
+    }
+
+    #[test]
+    fn eci_assignments() {
+        let name = |eci| Charset::from_eci(eci).map(|charset| charset.name());
+        assert_eq!(name(20), Some("shift_jis"));
+        assert_eq!(name(26), Some("utf-8"));
+        assert_eq!(name(29), Some("gbk"));
+        assert_eq!(
+            name(7),
+            name(9).and(Charset::from_name("ISO-8859-5").map(|c| c.name()))
+        );
+        assert_eq!(name(899), None);
+    }
+}
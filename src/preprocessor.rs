@@ -1,8 +1,30 @@
+//! Image preprocessing
+//!
+//! Every image is scanned in several preprocessed variants. Each variant is
+//! a named chain of steps applied to the grayscale image, written as
+//! `NAME=STEP>STEP>...`, e.g. `denoised=contrast>gaussian(1.0)>sauvola(31,0.2)`;
+//! an empty chain (`original=`) is the grayscale image itself. Chains that
+//! start with the same steps share their result, so a common prefix is
+//! computed once per image.
+
+use anyhow::{Context, Result, bail};
 use image::{DynamicImage, GrayImage, ImageBuffer, Luma};
 use imageproc::contrast::{
-    ThresholdType, adaptive_threshold, otsu_level, stretch_contrast, threshold,
+    ThresholdType, adaptive_threshold, equalize_histogram, otsu_level, stretch_contrast, threshold,
 };
+use imageproc::filter::{gaussian_blur_f32, median_filter, sharpen3x3};
+use imageproc::morphology::{Mask, grayscale_close, grayscale_open};
 use log::debug;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::memory::{self, MemoryUsage};
+use crate::timer::{Timer, VariantTiming};
+
+/// Largest side images are scanned at; larger images are scaled down first
+pub const MAX_DIMENSION: u32 = 2000;
 
 /// Preprocess image for better QR code detection
 pub struct ImagePreprocessor;
@@ -57,50 +79,422 @@ impl ImagePreprocessor {
         }
     }
 
-    /// Generate multiple preprocessed versions of an image
-    pub fn generate_variants(img: &DynamicImage) -> Vec<(String, GrayImage)> {
-        let mut variants = Vec::new();
-        let gray = img.to_luma8();
+    /// Scale `img` down so its larger side is at most `max_dimension`
+    pub fn limit_size(img: &DynamicImage, max_dimension: u32) -> DynamicImage {
+        let longest = img.width().max(img.height());
+        if longest > max_dimension {
+            let scale = max_dimension as f32 / longest as f32;
+            debug!(
+                "Resizing large image ({}x{}) by {:.2}x",
+                img.width(),
+                img.height(),
+                scale
+            );
+            Self::resize(img, scale)
+        } else {
+            img.clone()
+        }
+    }
 
-        // Original grayscale
-        variants.push(("original".to_string(), gray.clone()));
+    /// Sauvola binarization over a `window` pixels wide neighbourhood:
+    /// the threshold follows the local mean, lowered where the local
+    /// contrast (standard deviation) is low
+    pub fn sauvola(gray: &GrayImage, window: u32, k: f32) -> GrayImage {
+        let (width, height) = gray.dimensions();
+        let radius = window / 2;
 
-        // Contrast enhanced
-        let enhanced = Self::enhance_contrast(&gray);
-        variants.push(("contrast_enhanced".to_string(), enhanced.clone()));
+        // Integral images of the values and their squares
+        let stride = width as usize + 1;
+        let mut sums = vec![0u64; stride * (height as usize + 1)];
+        let mut squares = vec![0u64; stride * (height as usize + 1)];
+        for (y, row) in gray.rows().enumerate() {
+            let (mut row_sum, mut row_square) = (0, 0);
+            for (x, pixel) in row.enumerate() {
+                let value = pixel[0] as u64;
+                row_sum += value;
+                row_square += value * value;
+                let at = (y + 1) * stride + x + 1;
+                sums[at] = sums[at - stride] + row_sum;
+                squares[at] = squares[at - stride] + row_square;
+            }
+        }
+
+        ImageBuffer::from_fn(width, height, |x, y| {
+            let (left, right) = (
+                x.saturating_sub(radius) as usize,
+                (x + radius + 1).min(width) as usize,
+            );
+            let (top, bottom) = (
+                y.saturating_sub(radius) as usize,
+                (y + radius + 1).min(height) as usize,
+            );
+            let area = ((right - left) * (bottom - top)) as f64;
+            let window_sum = |table: &[u64]| {
+                (table[bottom * stride + right] + table[top * stride + left]
+                    - table[top * stride + right]
+                    - table[bottom * stride + left]) as f64
+            };
+            let mean = window_sum(&sums) / area;
+            let deviation = (window_sum(&squares) / area - mean * mean).max(0.0).sqrt();
+            let level = mean * (1.0 + k as f64 * (deviation / 128.0 - 1.0));
+            if gray.get_pixel(x, y)[0] as f64 > level {
+                Luma([255])
+            } else {
+                Luma([0])
+            }
+        })
+    }
+}
+
+/// One preprocessing step on a grayscale image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// Stretch the gray levels to the full range (`contrast`)
+    Contrast,
+    /// Histogram equalization (`equalize`)
+    Equalize,
+    /// Swap dark and light, for light codes on dark backgrounds (`invert`)
+    Invert,
+    /// Global binarization at Otsu's level (`otsu`)
+    Otsu,
+    /// Global binarization at a fixed level (`threshold(LEVEL)`)
+    Threshold(u8),
+    /// Local mean binarization with this block radius, or one derived from
+    /// the image size (`adaptive` or `adaptive(RADIUS)`)
+    Adaptive(Option<u32>),
+    /// Sauvola binarization (`sauvola(WINDOW)` or `sauvola(WINDOW,K)`)
+    Sauvola { window: u32, k: f32 },
+    /// Gaussian blur (`gaussian(SIGMA)`)
+    Gaussian(f32),
+    /// Median filter, against salt-and-pepper noise (`median(RADIUS)`)
+    Median(u32),
+    /// 3×3 sharpening (`sharpen`)
+    Sharpen,
+    /// Morphological opening, removing light specks smaller than the
+    /// square (`open(RADIUS)`)
+    Open(u8),
+    /// Morphological closing, removing dark specks (`close(RADIUS)`)
+    Close(u8),
+}
 
-        // Otsu binarization (safer than adaptive threshold)
-        variants.push(("otsu".to_string(), Self::otsu_binarization(&enhanced)));
+/// Steps with their arguments, for error messages
+const STEPS: &str = "contrast, equalize, invert, otsu, threshold(LEVEL), adaptive[(RADIUS)], sauvola(WINDOW[,K]), gaussian(SIGMA), median(RADIUS), sharpen, open(RADIUS), close(RADIUS)";
 
-        // Inverted for dark backgrounds
-        variants.push(("inverted".to_string(), Self::invert(&enhanced)));
+/// Default Sauvola sensitivity
+const SAUVOLA_K: f32 = 0.2;
 
-        // Only add adaptive threshold for reasonably sized images
-        // Skip for large images to avoid integral_image overflow (u32 limit)
-        let width = gray.width();
-        let height = gray.height();
+impl Step {
+    /// Why the step cannot run on a `width` × `height` image, if it cannot
+    fn unsupported(&self, width: u32, height: u32) -> Option<&'static str> {
         let pixel_count = (width as u64) * (height as u64);
+        match self {
+            // Keep width * height * 255 below u32::MAX, or imageproc's
+            // integral image overflows (u32::MAX / 255 ≈ 16,843,009 pixels)
+            Step::Adaptive(_) if pixel_count >= 10_000_000 => {
+                Some("too many pixels for adaptive thresholding")
+            }
+            Step::Adaptive(None) if width <= 100 || height <= 100 => {
+                Some("too small to derive an adaptive block radius")
+            }
+            _ => None,
+        }
+    }
 
-        // Conservative limit: ensure width * height * 255 < u32::MAX
-        // u32::MAX / 255 ≈ 16,843,009 pixels
-        if width > 100 && height > 100 && pixel_count < 10_000_000 {
-            // Adaptive threshold (small blocks) - safely
-            let block_radius = (width.min(height) / 50).clamp(5, 50);
-            debug!(
-                "Using adaptive threshold with block_radius: {}",
-                block_radius
-            );
-            variants.push((
-                "adaptive".to_string(),
-                Self::adaptive_threshold_image(&enhanced, block_radius),
-            ));
-        } else if pixel_count >= 10_000_000 {
-            debug!(
-                "Skipping adaptive threshold for large image ({}x{} = {} pixels)",
-                width, height, pixel_count
-            );
+    /// Apply to `gray`
+    pub fn apply(&self, gray: &GrayImage) -> GrayImage {
+        match *self {
+            Step::Contrast => ImagePreprocessor::enhance_contrast(gray),
+            Step::Equalize => equalize_histogram(gray),
+            Step::Invert => ImagePreprocessor::invert(gray),
+            Step::Otsu => ImagePreprocessor::otsu_binarization(gray),
+            Step::Threshold(level) => threshold(gray, level, ThresholdType::Binary),
+            Step::Adaptive(radius) => {
+                let block_radius =
+                    radius.unwrap_or_else(|| (gray.width().min(gray.height()) / 50).clamp(5, 50));
+                debug!(
+                    "Using adaptive threshold with block_radius: {}",
+                    block_radius
+                );
+                ImagePreprocessor::adaptive_threshold_image(gray, block_radius)
+            }
+            Step::Sauvola { window, k } => ImagePreprocessor::sauvola(gray, window, k),
+            Step::Gaussian(sigma) => gaussian_blur_f32(gray, sigma),
+            Step::Median(radius) => median_filter(gray, radius, radius),
+            Step::Sharpen => sharpen3x3(gray),
+            Step::Open(radius) => grayscale_open(gray, &Mask::square(radius)),
+            Step::Close(radius) => grayscale_close(gray, &Mask::square(radius)),
+        }
+    }
+}
+
+impl FromStr for Step {
+    type Err = anyhow::Error;
+
+    /// Parse `name` or `name(ARG,...)`
+    fn from_str(spec: &str) -> Result<Self> {
+        let spec = spec.trim();
+        let (name, args) = match spec.split_once('(') {
+            Some((name, rest)) => {
+                let args = rest
+                    .strip_suffix(')')
+                    .with_context(|| format!("Missing ')' in '{}'", spec))?;
+                let args = args
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| {
+                        arg.parse::<f64>()
+                            .with_context(|| format!("Invalid number '{}' in '{}'", arg, spec))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                (name.trim(), args)
+            }
+            None => (spec, Vec::new()),
+        };
+        let whole = |value: f64, min: f64, max: f64| {
+            (value.fract() == 0.0 && (min..=max).contains(&value)).then_some(value)
+        };
+
+        let step = match (name, args.as_slice()) {
+            ("contrast", &[]) => Some(Step::Contrast),
+            ("equalize", &[]) => Some(Step::Equalize),
+            ("invert", &[]) => Some(Step::Invert),
+            ("otsu", &[]) => Some(Step::Otsu),
+            ("sharpen", &[]) => Some(Step::Sharpen),
+            ("threshold", &[level]) => whole(level, 0.0, 255.0).map(|l| Step::Threshold(l as u8)),
+            ("adaptive", &[]) => Some(Step::Adaptive(None)),
+            ("adaptive", &[radius]) => {
+                whole(radius, 1.0, 1000.0).map(|r| Step::Adaptive(Some(r as u32)))
+            }
+            ("sauvola", &[window]) => whole(window, 3.0, 1000.0).map(|w| Step::Sauvola {
+                window: w as u32,
+                k: SAUVOLA_K,
+            }),
+            ("sauvola", &[window, k]) if (0.0..=1.0).contains(&k) => whole(window, 3.0, 1000.0)
+                .map(|w| Step::Sauvola {
+                    window: w as u32,
+                    k: k as f32,
+                }),
+            ("gaussian", &[sigma]) if sigma > 0.0 && sigma <= 100.0 => {
+                Some(Step::Gaussian(sigma as f32))
+            }
+            ("median", &[radius]) => whole(radius, 1.0, 100.0).map(|r| Step::Median(r as u32)),
+            ("open", &[radius]) => whole(radius, 1.0, 255.0).map(|r| Step::Open(r as u8)),
+            ("close", &[radius]) => whole(radius, 1.0, 255.0).map(|r| Step::Close(r as u8)),
+            _ => None,
+        };
+        step.with_context(|| format!("Invalid step '{}' (expected one of {})", spec, STEPS))
+    }
+}
+
+impl fmt::Display for Step {
+    /// The spec this parses from
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Step::Contrast => write!(f, "contrast"),
+            Step::Equalize => write!(f, "equalize"),
+            Step::Invert => write!(f, "invert"),
+            Step::Otsu => write!(f, "otsu"),
+            Step::Threshold(level) => write!(f, "threshold({})", level),
+            Step::Adaptive(None) => write!(f, "adaptive"),
+            Step::Adaptive(Some(radius)) => write!(f, "adaptive({})", radius),
+            Step::Sauvola { window, k } => write!(f, "sauvola({},{})", window, k),
+            Step::Gaussian(sigma) => write!(f, "gaussian({})", sigma),
+            Step::Median(radius) => write!(f, "median({})", radius),
+            Step::Sharpen => write!(f, "sharpen"),
+            Step::Open(radius) => write!(f, "open({})", radius),
+            Step::Close(radius) => write!(f, "close({})", radius),
+        }
+    }
+}
+
+/// A named chain of steps; the empty chain is the grayscale image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl FromStr for Variant {
+    type Err = anyhow::Error;
+
+    /// Parse `NAME=STEP>STEP>...`; without a name, the chain names itself
+    fn from_str(spec: &str) -> Result<Self> {
+        let (name, chain) = match spec.split_once('=') {
+            Some((name, chain)) => (name.trim().to_string(), chain),
+            None => (spec.split_whitespace().collect(), spec),
+        };
+        if name.is_empty() {
+            bail!("Variant needs a name: {}", spec);
+        }
+        let steps = if chain.trim().is_empty() {
+            Vec::new()
+        } else {
+            chain
+                .split('>')
+                .map(str::parse)
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid variant '{}'", name))?
+        };
+        Ok(Self { name, steps })
+    }
+}
+
+/// Key of the image after the first `steps`, shared by chains with the same
+/// prefix
+fn chain_key(steps: &[Step]) -> String {
+    let steps: Vec<String> = steps.iter().map(ToString::to_string).collect();
+    steps.join(">")
+}
+
+/// The variants every image is scanned in, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub variants: Vec<Variant>,
+}
+
+/// The built-in variants; adaptive is skipped on images too small or too
+/// large for it
+pub const DEFAULT_VARIANTS: [&str; 5] = [
+    "original=",
+    "contrast_enhanced=contrast",
+    "otsu=contrast>otsu",
+    "inverted=contrast>invert",
+    "adaptive=contrast>adaptive",
+];
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::parse(&DEFAULT_VARIANTS).expect("built-in variants parse")
+    }
+}
+
+impl Pipeline {
+    /// Parse variant specs (see [`Variant`])
+    pub fn parse<S: AsRef<str>>(specs: &[S]) -> Result<Self> {
+        let mut variants: Vec<Variant> = Vec::with_capacity(specs.len());
+        for spec in specs {
+            let variant: Variant = spec.as_ref().parse()?;
+            if variants.iter().any(|v| v.name == variant.name) {
+                bail!("Duplicate variant name '{}'", variant.name);
+            }
+            variants.push(variant);
+        }
+        if variants.is_empty() {
+            bail!("No preprocessing variants given");
+        }
+        Ok(Self { variants })
+    }
+
+    /// Generate the variants of an image
+    pub fn run(&self, img: &DynamicImage) -> Vec<(String, GrayImage)> {
+        self.run_timed(img).0
+    }
+
+    /// Generate the variants along with the time and heap each took; a
+    /// variant is charged for the steps no earlier variant had computed
+    pub fn run_timed(&self, img: &DynamicImage) -> (Vec<(String, GrayImage)>, Vec<VariantTiming>) {
+        let mut computed: HashMap<String, GrayImage> = HashMap::new();
+        let mut variants = Vec::new();
+        let mut timings = Vec::new();
+
+        for variant in &self.variants {
+            let unsupported = variant
+                .steps
+                .iter()
+                .find_map(|step| step.unsupported(img.width(), img.height()));
+            if let Some(reason) = unsupported {
+                debug!(
+                    "Skipping variant {} for {}x{} image: {}",
+                    variant.name,
+                    img.width(),
+                    img.height(),
+                    reason
+                );
+                continue;
+            }
+
+            let (duration, memory) = compute(img, &variant.steps, &mut computed);
+            let image = computed[&chain_key(&variant.steps)].clone();
+            timings.push(VariantTiming {
+                variant_name: variant.name.clone(),
+                duration,
+                memory,
+            });
+            variants.push((variant.name.clone(), image));
         }
 
-        variants
+        (variants, timings)
+    }
+}
+
+/// Apply `steps` to the grayscale `img`, starting from the longest prefix
+/// already in `computed` and moving every new intermediate image into it;
+/// returns the time and heap of the conversion and the steps alone, without
+/// the cache
+fn compute(
+    img: &DynamicImage,
+    steps: &[Step],
+    computed: &mut HashMap<String, GrayImage>,
+) -> (Duration, Option<MemoryUsage>) {
+    let mut duration = Duration::ZERO;
+    let mut memory: Option<MemoryUsage> = None;
+    let mut measured = |f: &dyn Fn() -> GrayImage| {
+        let ((image, elapsed), usage) = memory::measure(|| Timer::time(f));
+        duration += elapsed;
+        if let Some(usage) = usage {
+            memory.get_or_insert_default().add(&usage);
+        }
+        image
+    };
+
+    let done = (0..=steps.len())
+        .rev()
+        .find(|&n| computed.contains_key(&chain_key(&steps[..n])));
+    let done = match done {
+        Some(n) => n,
+        None => {
+            let gray = measured(&|| img.to_luma8());
+            computed.insert(String::new(), gray);
+            0
+        }
+    };
+    for n in done..steps.len() {
+        let input = &computed[&chain_key(&steps[..n])];
+        let image = measured(&|| steps[n].apply(input));
+        computed.insert(chain_key(&steps[..=n]), image);
+    }
+    (duration, memory)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_memory_is_one_image_per_step() {
+        memory::enable();
+        let (width, height) = (300, 200);
+        let size = (width * height) as u64;
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+            Luma([((x ^ y) & 0xff) as u8])
+        }));
+        let pipeline = Pipeline::parse(&["gray=", "inverted=invert", "otsu=invert>otsu"]).unwrap();
+
+        let (variants, timings) = pipeline.run_timed(&img);
+        assert_eq!(variants.len(), 3);
+        for timing in &timings {
+            let peak = timing.memory.unwrap().peak_bytes;
+            assert!(
+                (size..2 * size).contains(&peak),
+                "{} peaked at {} bytes for a {}-byte image",
+                timing.variant_name,
+                peak,
+                size
+            );
+        }
+        // `otsu` takes `inverted` from the cache: neither that step nor a
+        // copy of its image is charged to it
+        assert!(timings[2].memory.unwrap().allocated_bytes < 2 * size);
     }
 }
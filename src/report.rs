@@ -0,0 +1,1359 @@
+//! Report writers
+//!
+//! Besides the terminal table, scan results can be written as JSON, CSV (one
+//! row per file and engine, for spreadsheets), Markdown (tables to paste
+//! into documents) or a self-contained HTML page with sortable tables,
+//! per-engine summaries and thumbnails of the images engines failed on.
+//!
+//! Benchmarks are written as JSON, CSV (one row per file and engine) or
+//! Markdown. Robustness sweeps are written as JSON or as CSV with one
+//! column per engine and variant, ready to chart.
+//!
+//! The JSON reports of scans, benchmarks, analyses and sweeps carry a
+//! `schema_version`; `qr-tester schema` prints their JSON Schema, and the
+//! schemas of the current version are shipped in `schemas/`.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::{DynamicImage, ImageFormat};
+use log::debug;
+use schemars::{JsonSchema, schema_for};
+use serde::Serialize;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, BufWriter, Cursor};
+use std::path::Path;
+
+use crate::analyzer::AnalysisReport;
+use crate::baseline::Comparison;
+use crate::bench::{BenchReport, EngineBench};
+use crate::engine::DecodedSymbol;
+use crate::ground_truth::Accuracy;
+use crate::memory::format_bytes;
+use crate::payload::to_base64;
+use crate::scanner::{EngineResult, ScanResult, VariantOutcome};
+use crate::structured_append::AppendedSet;
+use crate::sweep::SweepReport;
+use crate::timer::{ScanStats, ScanTiming};
+
+/// Largest side of the thumbnails in HTML reports, in pixels
+const THUMBNAIL_SIZE: u32 = 160;
+
+/// How scan results are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Colored table on the terminal
+    Text,
+    Json,
+    /// One JSON object per line: each file as soon as it is scanned, then
+    /// the statistics
+    Ndjson,
+    /// One row per file and engine
+    Csv,
+    Markdown,
+    /// Self-contained page with sortable tables and thumbnails
+    Html,
+}
+
+impl Format {
+    /// Format implied by the extension of an output file, if any
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "json" => Some(Format::Json),
+            "ndjson" | "jsonl" => Some(Format::Ndjson),
+            "csv" => Some(Format::Csv),
+            "md" | "markdown" => Some(Format::Markdown),
+            "html" | "htm" => Some(Format::Html),
+            _ => None,
+        }
+    }
+}
+
+/// One engine over all successfully scanned files
+struct EngineSummary<'a> {
+    engine_name: &'a str,
+    files: usize,
+    files_with_codes: usize,
+    qr_codes: usize,
+    /// Variant calls that errored, panicked, crashed or timed out
+    failed_calls: usize,
+    total_ms: f64,
+    mean_peak_bytes: Option<f64>,
+    accuracy: Option<&'a Accuracy>,
+}
+
+impl EngineSummary<'_> {
+    fn mean_ms(&self) -> f64 {
+        self.total_ms / self.files.max(1) as f64
+    }
+}
+
+/// Per-engine summaries, in run order
+fn summarize<'a>(results: &'a [ScanResult], stats: &'a ScanStats) -> Vec<EngineSummary<'a>> {
+    let mut summaries: Vec<EngineSummary> = Vec::new();
+    for engine_result in results
+        .iter()
+        .filter(|r| r.success)
+        .flat_map(|r| &r.engine_results)
+    {
+        let pos = match summaries
+            .iter()
+            .position(|s| s.engine_name == engine_result.engine_name)
+        {
+            Some(pos) => pos,
+            None => {
+                let name = engine_result.engine_name.as_str();
+                summaries.push(EngineSummary {
+                    engine_name: name,
+                    files: 0,
+                    files_with_codes: 0,
+                    qr_codes: 0,
+                    failed_calls: 0,
+                    total_ms: 0.0,
+                    mean_peak_bytes: stats
+                        .memory
+                        .iter()
+                        .find(|m| m.engine_name == name && m.calls > 0)
+                        .map(|m| m.mean_peak_bytes),
+                    accuracy: stats.accuracy.as_ref().and_then(|report| {
+                        report
+                            .engines
+                            .iter()
+                            .find(|e| e.engine_name == name)
+                            .map(|e| &e.accuracy)
+                    }),
+                });
+                summaries.len() - 1
+            }
+        };
+        let summary = &mut summaries[pos];
+        summary.files += 1;
+        summary.files_with_codes += !engine_result.qr_codes.is_empty() as usize;
+        summary.qr_codes += engine_result.qr_codes.len();
+        summary.failed_calls += engine_result
+            .variants
+            .iter()
+            .filter(|v| !matches!(v.outcome, VariantOutcome::Ok))
+            .count();
+        summary.total_ms += engine_result.duration_ms;
+    }
+    summaries
+}
+
+/// Why an engine failed on a file, if it did: it decoded nothing, or read
+/// codes wrong or missed some against the ground truth
+fn failure(engine_result: &EngineResult) -> Option<String> {
+    if let Some(accuracy) = &engine_result.accuracy {
+        let problems: Vec<String> = [
+            (accuracy.misses, "missed"),
+            (accuracy.misdecodes, "misdecoded"),
+            (accuracy.false_positives, "false positives"),
+        ]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, what)| format!("{} {}", count, what))
+        .collect();
+        return (!problems.is_empty()).then(|| problems.join(", "));
+    }
+    engine_result
+        .qr_codes
+        .is_empty()
+        .then(|| "no codes".to_string())
+}
+
+fn ms(value: Option<f64>) -> String {
+    value.map_or(String::new(), |ms| format!("{:.3}", ms))
+}
+
+fn rate(value: Option<f64>) -> String {
+    value.map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0))
+}
+
+const CSV_COLUMNS: [&str; 18] = [
+    "file_path",
+    "engine",
+    "success",
+    "qr_codes",
+    "payloads",
+    "preprocess_ms",
+    "engine_ms",
+    "detect_ms",
+    "decode_ms",
+    "total_ms",
+    "file_total_ms",
+    "allocations",
+    "peak_bytes",
+    "true_positives",
+    "false_positives",
+    "misdecodes",
+    "misses",
+    "error",
+];
+
+/// Results as CSV, one row per file and engine; files that could not be
+/// scanned get a single row with the error
+pub fn csv(results: &[ScanResult]) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(CSV_COLUMNS)?;
+
+    for result in results {
+        let file_path = result.file_path.display().to_string();
+        if !result.success {
+            let mut record = vec![String::new(); CSV_COLUMNS.len()];
+            record[0] = file_path;
+            record[2] = "false".to_string();
+            record[CSV_COLUMNS.len() - 1] = result.error.clone().unwrap_or_default();
+            writer.write_record(&record)?;
+            continue;
+        }
+
+        let detection = &result.timing.qr_detection;
+        let preprocess_ms = detection.to_ms(detection.preprocess());
+        let file_total_ms = detection.to_ms(result.timing.total);
+        for engine_result in &result.engine_results {
+            let payloads: Vec<_> = engine_result
+                .qr_codes
+                .iter()
+                .map(|symbol| symbol.payload.display())
+                .collect();
+            let memory = engine_result.memory.as_ref();
+            let accuracy = engine_result.accuracy.as_ref();
+            let count = |count: fn(&Accuracy) -> usize| {
+                accuracy.map_or(String::new(), |a| count(a).to_string())
+            };
+            writer.write_record([
+                file_path.clone(),
+                engine_result.engine_name.clone(),
+                "true".to_string(),
+                engine_result.qr_codes.len().to_string(),
+                payloads.join("\n"),
+                format!("{:.3}", preprocess_ms),
+                format!("{:.3}", engine_result.duration_ms),
+                ms(engine_result.detect_ms),
+                ms(engine_result.decode_ms),
+                format!("{:.3}", preprocess_ms + engine_result.duration_ms),
+                format!("{:.3}", file_total_ms),
+                memory.map_or(String::new(), |m| m.allocations.to_string()),
+                memory.map_or(String::new(), |m| m.peak_bytes.to_string()),
+                count(|a| a.true_positives),
+                count(|a| a.false_positives),
+                count(|a| a.misdecodes),
+                count(|a| a.misses),
+                String::new(),
+            ])?;
+        }
+    }
+
+    let data = writer.into_inner().context("Failed to write CSV")?;
+    String::from_utf8(data).context("CSV is not UTF-8")
+}
+
+/// Sweep results as CSV for charting: one row per value, one column per
+/// engine and variant (`engine/variant`) holding 1 if it passed and 0 if
+/// not, left empty where the variant was not scanned
+pub fn sweep_csv(report: &SweepReport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    let header = std::iter::once(report.label.clone()).chain(
+        report
+            .series
+            .iter()
+            .map(|s| format!("{}/{}", s.engine_name, s.variant_name)),
+    );
+    writer.write_record(header)?;
+
+    for &value in &report.values {
+        let cells = report.series.iter().map(|s| {
+            s.points
+                .iter()
+                .find(|p| p.value == value)
+                .map_or(String::new(), |p| u8::from(p.passed).to_string())
+        });
+        writer.write_record(std::iter::once(value.to_string()).chain(cells))?;
+    }
+
+    let data = writer.into_inner().context("Failed to write CSV")?;
+    String::from_utf8(data).context("CSV is not UTF-8")
+}
+
+const BENCH_CSV_COLUMNS: [&str; 13] = [
+    "file_path",
+    "engine",
+    "qr_codes",
+    "failures",
+    "min_ms",
+    "median_ms",
+    "mean_ms",
+    "p95_ms",
+    "p99_ms",
+    "std_dev_ms",
+    "outliers",
+    "relative_median",
+    "error",
+];
+
+/// Benchmark timings as CSV, one row per file and engine; files that could
+/// not be benchmarked get a single row with the error
+pub fn bench_csv(report: &BenchReport) -> Result<String> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(BENCH_CSV_COLUMNS)?;
+
+    for file in &report.files {
+        for engine in &file.engines {
+            let summary = &engine.summary;
+            writer.write_record([
+                file.file_path.clone(),
+                engine.engine_name.clone(),
+                engine.qr_codes.to_string(),
+                engine.failures.to_string(),
+                format!("{:.3}", summary.min_ms),
+                format!("{:.3}", summary.median_ms),
+                format!("{:.3}", summary.mean_ms),
+                format!("{:.3}", summary.p95_ms),
+                format!("{:.3}", summary.p99_ms),
+                format!("{:.3}", summary.std_dev_ms),
+                summary.outliers.len().to_string(),
+                format!("{:.3}", engine.relative_median),
+                String::new(),
+            ])?;
+        }
+    }
+    for failed in &report.failed_files {
+        let mut record = vec![String::new(); BENCH_CSV_COLUMNS.len()];
+        record[0] = failed.file_path.clone();
+        record[BENCH_CSV_COLUMNS.len() - 1] = failed.error.clone();
+        writer.write_record(&record)?;
+    }
+
+    let data = writer.into_inner().context("Failed to write CSV")?;
+    String::from_utf8(data).context("CSV is not UTF-8")
+}
+
+/// Escape a Markdown table cell; control characters become U+FFFD
+fn md(text: &str) -> String {
+    text.replace('|', "\\|")
+        .replace(['\r', '\n'], " ")
+        .replace(|c: char| c.is_control(), "\u{fffd}")
+}
+
+/// Results as Markdown: the statistics, a per-engine summary and a table
+/// per file and engine
+pub fn markdown(results: &[ScanResult], stats: &ScanStats) -> String {
+    let mut out = String::new();
+    let summaries = summarize(results, stats);
+    let with_accuracy = stats.accuracy.is_some();
+
+    let _ = writeln!(out, "# QR Code Scan Report\n");
+    let _ = writeln!(
+        out,
+        "{} files: {} succeeded, {} failed, {} with codes, {} codes in total, {:.2}ms per file on average\n",
+        stats.total_files,
+        stats.successful_scans,
+        stats.failed_scans,
+        stats.files_with_qr,
+        stats.total_qr_codes,
+        stats.avg_duration_per_file.as_secs_f64() * 1000.0
+    );
+
+    let _ = writeln!(out, "## Engines\n");
+    let mut columns = vec![
+        "Engine",
+        "Files with codes",
+        "Codes",
+        "Failed calls",
+        "Mean time",
+        "Mean peak heap",
+    ];
+    if with_accuracy {
+        columns.extend(["Precision", "Recall"]);
+    }
+    let _ = writeln!(
+        out,
+        "| {} |\n|---|{}",
+        columns.join(" | "),
+        "---:|".repeat(columns.len() - 1)
+    );
+    for summary in &summaries {
+        let _ = write!(
+            out,
+            "| {} | {}/{} | {} | {} | {:.2}ms | {} |",
+            md(summary.engine_name),
+            summary.files_with_codes,
+            summary.files,
+            summary.qr_codes,
+            summary.failed_calls,
+            summary.mean_ms(),
+            summary
+                .mean_peak_bytes
+                .map_or("-".to_string(), format_bytes)
+        );
+        if with_accuracy {
+            let accuracy = summary.accuracy;
+            let _ = write!(
+                out,
+                " {} | {} |",
+                rate(accuracy.and_then(|a| a.precision)),
+                rate(accuracy.and_then(|a| a.recall))
+            );
+        }
+        out.push('\n');
+    }
+
+    let _ = writeln!(out, "\n## Results\n");
+    let _ = writeln!(
+        out,
+        "| File | Engine | QRs | Preprocess | Engine Time | Detect | Decode | Total | File Total |"
+    );
+    let _ = writeln!(out, "|---|---|---:|---:|---:|---:|---:|---:|---:|");
+    let stage = |ms: Option<f64>| ms.map_or("-".to_string(), |ms| format!("{:.2}ms", ms));
+    for result in results.iter().filter(|r| r.success) {
+        let detection = &result.timing.qr_detection;
+        let preprocess_ms = detection.to_ms(detection.preprocess());
+        for (idx, engine_result) in result.engine_results.iter().enumerate() {
+            let (path, file_total) = if idx == 0 {
+                (
+                    format!("`{}`", md(&result.file_path.display().to_string())),
+                    format!("{:.2}ms", detection.to_ms(result.timing.total)),
+                )
+            } else {
+                (String::new(), String::new())
+            };
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {:.2}ms | {:.2}ms | {} | {} | {:.2}ms | {} |",
+                path,
+                md(&engine_result.engine_name),
+                engine_result.qr_codes.len(),
+                preprocess_ms,
+                engine_result.duration_ms,
+                stage(engine_result.detect_ms),
+                stage(engine_result.decode_ms),
+                preprocess_ms + engine_result.duration_ms,
+                file_total
+            );
+        }
+    }
+
+    let failed: Vec<&ScanResult> = results.iter().filter(|r| !r.success).collect();
+    if !failed.is_empty() {
+        let _ = writeln!(out, "\n## Failed Files\n");
+        for result in failed {
+            let _ = writeln!(
+                out,
+                "- `{}`: {}",
+                result.file_path.display(),
+                md(result.error.as_deref().unwrap_or("unknown error"))
+            );
+        }
+    }
+    out
+}
+
+/// Benchmark timings as Markdown: the environment, a table per file and
+/// engine and, for several files, one per engine over all of them
+pub fn bench_markdown(report: &BenchReport) -> String {
+    let mut out = String::new();
+    let env = &report.environment;
+
+    let _ = writeln!(out, "# QR Code Benchmark Report\n");
+    let _ = writeln!(
+        out,
+        "{} iterations after {} warmup runs. qr-tester {} ({} build) on {}/{}, {}{} CPUs; reference workload {:.2}ms\n",
+        report.iterations,
+        report.warmup,
+        env.version,
+        env.build,
+        env.os,
+        env.arch,
+        env.cpu
+            .as_ref()
+            .map_or(String::new(), |cpu| format!("{}, ", md(cpu))),
+        env.cpus,
+        env.reference_ms
+    );
+
+    let table = |out: &mut String, first: &str, rows: Vec<(String, &EngineBench)>| {
+        let _ = writeln!(
+            out,
+            "| {} | Engine | Codes | Failures | Min | Median | Mean | p95 | p99 | Std dev | Outliers | xRef |\n|---|---|{}",
+            first,
+            "---:|".repeat(10)
+        );
+        for (label, engine) in rows {
+            let summary = &engine.summary;
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {:.2}ms | {:.2}ms | {:.2}ms | {:.2}ms | {:.2}ms | {:.2}ms | {} | {:.2} |",
+                label,
+                md(&engine.engine_name),
+                engine.qr_codes,
+                engine.failures,
+                summary.min_ms,
+                summary.median_ms,
+                summary.mean_ms,
+                summary.p95_ms,
+                summary.p99_ms,
+                summary.std_dev_ms,
+                summary.outliers.len(),
+                engine.relative_median
+            );
+        }
+        out.push('\n');
+    };
+
+    let _ = writeln!(out, "## Files\n");
+    let rows = report
+        .files
+        .iter()
+        .flat_map(|file| {
+            file.engines
+                .iter()
+                .map(|engine| (format!("`{}`", md(&file.file_path)), engine))
+        })
+        .collect();
+    table(&mut out, "File", rows);
+
+    if !report.failed_files.is_empty() {
+        let _ = writeln!(out, "## Failed files\n");
+        for failed in &report.failed_files {
+            let _ = writeln!(out, "- `{}`: {}", md(&failed.file_path), md(&failed.error));
+        }
+        out.push('\n');
+    }
+
+    if report.files.len() > 1 {
+        let _ = writeln!(out, "## All files\n");
+        let label = format!("{} files", report.files.len());
+        let rows = report
+            .engines
+            .iter()
+            .enumerate()
+            .map(|(idx, engine)| {
+                (
+                    if idx == 0 {
+                        label.clone()
+                    } else {
+                        String::new()
+                    },
+                    engine,
+                )
+            })
+            .collect();
+        table(&mut out, "Files", rows);
+    }
+
+    let _ = writeln!(
+        out,
+        "One sample is one pass over all variants; outliers are beyond 1.5 IQR; xRef is the median over the reference workload."
+    );
+    out
+}
+
+/// Escape text for HTML content and attribute values; control characters,
+/// which HTML does not allow, become U+FFFD
+fn html_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c if c.is_control() => escaped.push('\u{fffd}'),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Numeric table cell: shows `text`, sorts by `value` (missing values last)
+fn num_cell(value: Option<f64>, text: &str) -> String {
+    format!(
+        "<td class=\"num\" data-value=\"{}\">{}</td>",
+        value.map_or(String::new(), |v| v.to_string()),
+        html_escape(text)
+    )
+}
+
+/// PNG thumbnail of an image as a data URL, if it can be decoded
+fn thumbnail(path: &Path) -> Option<String> {
+    let img = match image::open(path) {
+        Ok(img) => img,
+        Err(e) => {
+            debug!("No thumbnail for {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    let thumb = DynamicImage::ImageRgba8(img.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgba8());
+    let mut png = Vec::new();
+    thumb
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .ok()?;
+    Some(format!("data:image/png;base64,{}", to_base64(&png)))
+}
+
+const STYLE: &str = "
+body { font-family: system-ui, sans-serif; margin: 2em; color: #222; }
+h1, h2 { font-weight: 600; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+th, td { padding: 4px 10px; border-bottom: 1px solid #ddd; text-align: left; }
+th { background: #f3f3f3; cursor: pointer; user-select: none; white-space: nowrap; }
+th[data-order=asc]::after { content: ' \\25B2'; }
+th[data-order=desc]::after { content: ' \\25BC'; }
+td.num { text-align: right; font-variant-numeric: tabular-nums; }
+td.path { font-family: monospace; }
+.failures { display: flex; flex-wrap: wrap; gap: 1em; }
+figure { margin: 0; padding: 8px; border: 1px solid #ddd; border-radius: 4px; width: 240px; }
+figure img { display: block; max-width: 160px; max-height: 160px; margin: 0 auto 6px; image-rendering: pixelated; }
+figcaption { font-size: 0.85em; overflow-wrap: anywhere; }
+figcaption ul { padding-left: 1.2em; margin: 4px 0 0; }
+.bad { color: #b00020; }
+";
+
+const SCRIPT: &str = "
+document.querySelectorAll('table.sortable th').forEach(th => {
+  th.addEventListener('click', () => {
+    const table = th.closest('table');
+    const ascending = th.dataset.order !== 'asc';
+    table.querySelectorAll('th').forEach(h => delete h.dataset.order);
+    th.dataset.order = ascending ? 'asc' : 'desc';
+    const key = row => {
+      const cell = row.cells[th.cellIndex];
+      return cell.dataset.value ?? cell.textContent;
+    };
+    const body = table.tBodies[0];
+    const rows = Array.from(body.rows).sort((a, b) => {
+      const x = key(a), y = key(b);
+      if (x === '' || y === '') return (x === '') - (y === '');
+      const order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+      return ascending ? order : -order;
+    });
+    body.append(...rows);
+  });
+});
+";
+
+/// Results as a self-contained HTML page
+pub fn html(results: &[ScanResult], stats: &ScanStats) -> String {
+    let mut out = String::new();
+    let summaries = summarize(results, stats);
+    let with_accuracy = stats.accuracy.is_some();
+
+    let _ = writeln!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>QR Code Scan Report</title>\n<style>{}</style>\n</head>\n<body>",
+        STYLE
+    );
+    let _ = writeln!(out, "<h1>QR Code Scan Report</h1>");
+    let _ = writeln!(
+        out,
+        "<p>{} files: {} succeeded, <span class=\"bad\">{} failed</span>, {} with codes, {} codes in total, {:.2}ms per file on average (qr-tester {})</p>",
+        stats.total_files,
+        stats.successful_scans,
+        stats.failed_scans,
+        stats.files_with_qr,
+        stats.total_qr_codes,
+        stats.avg_duration_per_file.as_secs_f64() * 1000.0,
+        env!("CARGO_PKG_VERSION")
+    );
+
+    let _ = writeln!(
+        out,
+        "<h2>Engines</h2>\n<table class=\"sortable\">\n<thead><tr>"
+    );
+    let mut headers = vec![
+        "Engine",
+        "Files with codes",
+        "Codes",
+        "Failed calls",
+        "Mean time",
+        "Mean peak heap",
+    ];
+    if with_accuracy {
+        headers.extend(["Precision", "Recall"]);
+    }
+    for header in headers {
+        let _ = write!(out, "<th>{}</th>", header);
+    }
+    let _ = writeln!(out, "</tr></thead>\n<tbody>");
+    for summary in &summaries {
+        let _ = write!(out, "<tr><td>{}</td>", html_escape(summary.engine_name));
+        out.push_str(&num_cell(
+            Some(summary.files_with_codes as f64),
+            &format!("{}/{}", summary.files_with_codes, summary.files),
+        ));
+        out.push_str(&num_cell(
+            Some(summary.qr_codes as f64),
+            &summary.qr_codes.to_string(),
+        ));
+        out.push_str(&num_cell(
+            Some(summary.failed_calls as f64),
+            &summary.failed_calls.to_string(),
+        ));
+        out.push_str(&num_cell(
+            Some(summary.mean_ms()),
+            &format!("{:.2}ms", summary.mean_ms()),
+        ));
+        out.push_str(&num_cell(
+            summary.mean_peak_bytes,
+            &summary
+                .mean_peak_bytes
+                .map_or("-".to_string(), format_bytes),
+        ));
+        if with_accuracy {
+            for value in [
+                summary.accuracy.and_then(|a| a.precision),
+                summary.accuracy.and_then(|a| a.recall),
+            ] {
+                out.push_str(&num_cell(value, &rate(value)));
+            }
+        }
+        let _ = writeln!(out, "</tr>");
+    }
+    let _ = writeln!(out, "</tbody>\n</table>");
+
+    let _ = writeln!(
+        out,
+        "<h2>Results</h2>\n<table class=\"sortable\">\n<thead><tr>"
+    );
+    for header in [
+        "File",
+        "Engine",
+        "QRs",
+        "Payloads",
+        "Preprocess",
+        "Engine Time",
+        "Detect",
+        "Decode",
+        "Total",
+        "File Total",
+        "Peak Heap",
+    ] {
+        let _ = write!(out, "<th>{}</th>", header);
+    }
+    let _ = writeln!(out, "</tr></thead>\n<tbody>");
+    let time =
+        |ms: Option<f64>| num_cell(ms, &ms.map_or("-".to_string(), |ms| format!("{:.2}ms", ms)));
+    for result in results.iter().filter(|r| r.success) {
+        let detection = &result.timing.qr_detection;
+        let preprocess_ms = detection.to_ms(detection.preprocess());
+        let file_total_ms = detection.to_ms(result.timing.total);
+        let path = html_escape(&result.file_path.display().to_string());
+        for engine_result in &result.engine_results {
+            let payloads: Vec<String> = engine_result
+                .qr_codes
+                .iter()
+                .map(|symbol| html_escape(&symbol.payload.display()))
+                .collect();
+            let peak = engine_result.memory.map(|m| m.peak_bytes as f64);
+            let _ = write!(
+                out,
+                "<tr><td class=\"path\">{}</td><td>{}</td>",
+                path,
+                html_escape(&engine_result.engine_name)
+            );
+            out.push_str(&num_cell(
+                Some(engine_result.qr_codes.len() as f64),
+                &engine_result.qr_codes.len().to_string(),
+            ));
+            let _ = write!(out, "<td>{}</td>", payloads.join("<br>"));
+            out.push_str(&time(Some(preprocess_ms)));
+            out.push_str(&time(Some(engine_result.duration_ms)));
+            out.push_str(&time(engine_result.detect_ms));
+            out.push_str(&time(engine_result.decode_ms));
+            out.push_str(&time(Some(preprocess_ms + engine_result.duration_ms)));
+            out.push_str(&time(Some(file_total_ms)));
+            out.push_str(&num_cell(peak, &peak.map_or("-".to_string(), format_bytes)));
+            let _ = writeln!(out, "</tr>");
+        }
+    }
+    let _ = writeln!(out, "</tbody>\n</table>");
+
+    let failing: Vec<(&ScanResult, Vec<String>)> = results
+        .iter()
+        .filter_map(|result| {
+            let reasons: Vec<String> = if result.success {
+                result
+                    .engine_results
+                    .iter()
+                    .filter_map(|e| Some(format!("{}: {}", e.engine_name, failure(e)?)))
+                    .collect()
+            } else {
+                vec![
+                    result
+                        .error
+                        .clone()
+                        .unwrap_or_else(|| "scan failed".to_string()),
+                ]
+            };
+            (!reasons.is_empty()).then_some((result, reasons))
+        })
+        .collect();
+    if !failing.is_empty() {
+        let _ = writeln!(
+            out,
+            "<h2>Failing Images</h2>\n<p>Images some engine decoded nothing from or read wrong, and images that could not be scanned.</p>\n<div class=\"failures\">"
+        );
+        for (result, reasons) in failing {
+            let _ = write!(out, "<figure>");
+            match thumbnail(&result.file_path) {
+                Some(src) => {
+                    let _ = write!(out, "<img src=\"{}\" alt=\"\">", src);
+                }
+                None => {
+                    let _ = write!(out, "<p class=\"bad\">No preview</p>");
+                }
+            }
+            let _ = write!(
+                out,
+                "<figcaption><code>{}</code><ul>",
+                html_escape(&result.file_path.display().to_string())
+            );
+            for reason in reasons {
+                let _ = write!(out, "<li class=\"bad\">{}</li>", html_escape(&reason));
+            }
+            let _ = writeln!(out, "</ul></figcaption></figure>");
+        }
+        let _ = writeln!(out, "</div>");
+    }
+
+    let _ = writeln!(out, "<script>{}</script>\n</body>\n</html>", SCRIPT);
+    out
+}
+
+/// Version of the JSON report layout, given as `schema_version` in every
+/// JSON report; bumped when a field is removed, renamed or changes meaning
+/// (new fields may appear within a version)
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A JSON report layout, for `qr-tester schema`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Report {
+    /// Scan results (`--format json`)
+    Scan,
+    /// One line of streamed scan results (`--format ndjson`)
+    Ndjson,
+    /// Benchmark results (`bench --json`)
+    Bench,
+    /// Failure analysis (`--analyze --format json`)
+    Analysis,
+    /// Robustness sweep (`sweep --format json`)
+    Sweep,
+}
+
+/// JSON Schema of a report layout
+pub fn schema(report: Report) -> Result<String> {
+    let schema = match report {
+        Report::Scan => schema_for!(ScanOutput),
+        Report::Ndjson => schema_for!(StreamRecord),
+        Report::Bench => schema_for!(BenchOutput),
+        Report::Analysis => schema_for!(AnalysisOutput),
+        Report::Sweep => schema_for!(SweepOutput),
+    };
+    serde_json::to_string_pretty(&schema).context("Failed to serialize JSON Schema")
+}
+
+/// JSON report of a scan
+#[derive(Serialize, JsonSchema)]
+#[schemars(title = "qr-tester scan report")]
+pub struct ScanOutput<'a> {
+    /// Version of the report layout, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    /// One entry per file, in path order
+    pub results: Vec<FileOutput<'a>>,
+    pub stats: &'a ScanStats,
+    /// Comparison with `--baseline`, if given
+    pub comparison: Option<&'a Comparison>,
+}
+
+impl<'a> ScanOutput<'a> {
+    pub fn new(
+        results: &'a [ScanResult],
+        stats: &'a ScanStats,
+        comparison: Option<&'a Comparison>,
+    ) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            results: results.iter().map(FileOutput::new).collect(),
+            stats,
+            comparison,
+        }
+    }
+}
+
+/// Scan result of one file
+#[derive(Serialize, JsonSchema)]
+pub struct FileOutput<'a> {
+    pub file_path: String,
+    /// Codes found by any engine, deduplicated
+    pub qr_codes: &'a [DecodedSymbol],
+    /// Structured Append sets among `qr_codes`, reassembled where complete
+    pub structured_append: &'a [AppendedSet],
+    /// Score of `qr_codes` against the file's ground truth, if it has any
+    pub accuracy: Option<&'a Accuracy>,
+    /// Results of each engine, in run order
+    pub engine_results: &'a [EngineResult],
+    pub timing: &'a ScanTiming,
+    /// Whether the file could be read and decoded
+    pub success: bool,
+    pub error: Option<&'a str>,
+}
+
+impl<'a> FileOutput<'a> {
+    pub fn new(result: &'a ScanResult) -> Self {
+        Self {
+            file_path: result.file_path.display().to_string(),
+            qr_codes: &result.qr_codes,
+            structured_append: &result.structured_append,
+            accuracy: result.accuracy.as_ref(),
+            engine_results: &result.engine_results,
+            timing: &result.timing,
+            success: result.success,
+            error: result.error.as_deref(),
+        }
+    }
+}
+
+/// One line of an NDJSON scan report
+#[derive(Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[schemars(title = "qr-tester NDJSON scan record")]
+pub enum StreamRecord<'a> {
+    /// Result of one file, written as soon as the file is scanned
+    File {
+        /// Version of the report layout, see [`SCHEMA_VERSION`]
+        schema_version: u32,
+        #[serde(flatten)]
+        file: FileOutput<'a>,
+    },
+    /// Statistics, written after the last file
+    Stats {
+        /// Version of the report layout, see [`SCHEMA_VERSION`]
+        schema_version: u32,
+        stats: &'a ScanStats,
+        /// Comparison with `--baseline`, if given
+        comparison: Option<&'a Comparison>,
+    },
+}
+
+/// Writes scan results as NDJSON, flushing every line so the stream can
+/// be followed and survives an aborted run
+pub struct StreamWriter {
+    out: Box<dyn io::Write>,
+}
+
+impl StreamWriter {
+    /// Stream to `output`, or to stdout if not given
+    pub fn create(output: Option<&Path>) -> Result<Self> {
+        let out: Box<dyn io::Write> = match output {
+            Some(path) => {
+                Box::new(BufWriter::new(File::create(path).with_context(|| {
+                    format!("Failed to create report: {}", path.display())
+                })?))
+            }
+            None => Box::new(io::stdout()),
+        };
+        Ok(Self { out })
+    }
+
+    pub fn write_file(&mut self, result: &ScanResult) -> Result<()> {
+        self.write(&StreamRecord::File {
+            schema_version: SCHEMA_VERSION,
+            file: FileOutput::new(result),
+        })
+    }
+
+    pub fn write_stats(
+        &mut self,
+        stats: &ScanStats,
+        comparison: Option<&Comparison>,
+    ) -> Result<()> {
+        self.write(&StreamRecord::Stats {
+            schema_version: SCHEMA_VERSION,
+            stats,
+            comparison,
+        })
+    }
+
+    fn write(&mut self, record: &StreamRecord) -> Result<()> {
+        let mut line = serde_json::to_vec(record).context("Failed to serialize JSON")?;
+        line.push(b'\n');
+        self.out
+            .write_all(&line)
+            .and_then(|_| self.out.flush())
+            .context("Failed to write report")
+    }
+}
+
+/// JSON report of a benchmark
+#[derive(Serialize, JsonSchema)]
+#[schemars(title = "qr-tester benchmark report")]
+pub struct BenchOutput<'a> {
+    /// Version of the report layout, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub report: &'a BenchReport,
+    /// Comparison with `--baseline`, if given
+    pub comparison: Option<&'a Comparison>,
+}
+
+impl<'a> BenchOutput<'a> {
+    pub fn new(report: &'a BenchReport, comparison: Option<&'a Comparison>) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            report,
+            comparison,
+        }
+    }
+}
+
+/// JSON report of a failure analysis
+#[derive(Serialize, JsonSchema)]
+#[schemars(title = "qr-tester analysis report")]
+pub struct AnalysisOutput<'a> {
+    /// Version of the report layout, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub report: &'a AnalysisReport,
+}
+
+impl<'a> AnalysisOutput<'a> {
+    pub fn new(report: &'a AnalysisReport) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            report,
+        }
+    }
+}
+
+/// JSON report of a robustness sweep
+#[derive(Serialize, JsonSchema)]
+#[schemars(title = "qr-tester sweep report")]
+pub struct SweepOutput<'a> {
+    /// Version of the report layout, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub report: &'a SweepReport,
+}
+
+impl<'a> SweepOutput<'a> {
+    pub fn new(report: &'a SweepReport) -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            report,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bench::{Environment, FailedFile, FileBench, Summary};
+    use crate::payload::Payload;
+    use crate::sweep::{Axis, SweepPoint, SweepSeries};
+    use crate::timer::ScanTiming;
+    use image::{GrayImage, Luma};
+    use std::path::PathBuf;
+
+    /// A scanned file with one engine that decoded `payloads`
+    fn scanned(path: &str, engine_name: &str, payloads: &[&str]) -> ScanResult {
+        let qr_codes: Vec<DecodedSymbol> = payloads
+            .iter()
+            .map(|text| DecodedSymbol::new(Payload::from_text(text.to_string())))
+            .collect();
+        ScanResult {
+            file_path: PathBuf::from(path),
+            qr_codes: qr_codes.clone(),
+            structured_append: Vec::new(),
+            accuracy: None,
+            engine_results: vec![EngineResult {
+                engine_name: engine_name.to_string(),
+                qr_codes,
+                duration_ms: 1.5,
+                detect_ms: None,
+                decode_ms: None,
+                memory: None,
+                structured_append: Vec::new(),
+                accuracy: None,
+                variants: Vec::new(),
+            }],
+            timing: ScanTiming::new(),
+            success: true,
+            error: None,
+        }
+    }
+
+    /// A file that could not be scanned
+    fn unscanned(path: &str, error: &str) -> ScanResult {
+        ScanResult {
+            engine_results: Vec::new(),
+            success: false,
+            error: Some(error.to_string()),
+            ..scanned(path, "", &[])
+        }
+    }
+
+    fn stats_of(results: &[ScanResult]) -> ScanStats {
+        let mut stats = ScanStats::new();
+        stats.total_files = results.len();
+        stats.successful_scans = results.iter().filter(|r| r.success).count();
+        stats.failed_scans = stats.total_files - stats.successful_scans;
+        stats
+    }
+
+    fn engine_bench(engine_name: &str, samples_ms: Vec<f64>) -> EngineBench {
+        EngineBench {
+            engine_name: engine_name.to_string(),
+            qr_codes: 1,
+            failures: 0,
+            summary: Summary::of(&samples_ms),
+            samples_ms,
+            relative_median: 0.5,
+        }
+    }
+
+    fn bench_report(files: &[&str], failed: &[(&str, &str)]) -> BenchReport {
+        BenchReport {
+            environment: Environment {
+                version: "0.0.0".to_string(),
+                build: "debug".to_string(),
+                os: "linux".to_string(),
+                arch: "x86_64".to_string(),
+                cpu: Some("CPU | 4 cores".to_string()),
+                cpus: 4,
+                reference_ms: 2.0,
+            },
+            warmup: 1,
+            iterations: 3,
+            files: files
+                .iter()
+                .map(|path| FileBench {
+                    file_path: path.to_string(),
+                    variants: 5,
+                    engines: vec![
+                        engine_bench("rqrr", vec![1.0, 2.0, 3.0]),
+                        engine_bench("a|b", vec![4.0, 5.0, 6.0]),
+                    ],
+                })
+                .collect(),
+            failed_files: failed
+                .iter()
+                .map(|(path, error)| FailedFile {
+                    file_path: path.to_string(),
+                    error: error.to_string(),
+                })
+                .collect(),
+            engines: vec![engine_bench("rqrr", vec![2.0, 4.0, 6.0])],
+        }
+    }
+
+    /// Lines of the Markdown table under `heading`
+    fn table_rows<'a>(markdown: &'a str, heading: &str) -> Vec<&'a str> {
+        markdown
+            .split(heading)
+            .nth(1)
+            .unwrap()
+            .lines()
+            .skip_while(|line| !line.starts_with('|'))
+            .take_while(|line| line.starts_with('|'))
+            .collect()
+    }
+
+    #[test]
+    fn csv_escapes_cells() {
+        let results = [
+            scanned("codes, more.png", "rqrr", &["say \"hi\"", "a,b"]),
+            scanned("empty.png", "rqrr", &[]),
+            unscanned("bad.png", "Failed, \"really\"\nbroken"),
+        ];
+        let text = csv(&results).unwrap();
+        assert!(text.contains("\"codes, more.png\""));
+        assert!(text.contains("\"say \"\"hi\"\"\na,b\""));
+
+        let mut reader = ::csv::Reader::from_reader(text.as_bytes());
+        assert_eq!(reader.headers().unwrap(), CSV_COLUMNS.as_slice());
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.len() == CSV_COLUMNS.len()));
+        assert_eq!(&records[0][0], "codes, more.png");
+        assert_eq!(&records[0][3], "2");
+        assert_eq!(&records[0][4], "say \"hi\"\na,b");
+        assert_eq!(&records[1][3], "0");
+        assert_eq!(
+            (&records[2][2], &records[2][17]),
+            ("false", "Failed, \"really\"\nbroken")
+        );
+    }
+
+    #[test]
+    fn bench_csv_rows() {
+        let report = bench_report(
+            &["a,\"b\".png", "c.png"],
+            &[("d.png", "Unsupported, \"format\"")],
+        );
+        let text = bench_csv(&report).unwrap();
+        let mut reader = ::csv::Reader::from_reader(text.as_bytes());
+        assert_eq!(reader.headers().unwrap(), BENCH_CSV_COLUMNS.as_slice());
+        let records: Vec<_> = reader.records().map(Result::unwrap).collect();
+        // Two engines for each of two files, then the failed file
+        assert_eq!(records.len(), 5);
+        assert!(records.iter().all(|r| r.len() == BENCH_CSV_COLUMNS.len()));
+        assert_eq!((&records[0][0], &records[0][1]), ("a,\"b\".png", "rqrr"));
+        assert_eq!(&records[1][1], "a|b");
+        assert_eq!(&records[0][5], "2.000");
+        assert_eq!(
+            (&records[4][0], &records[4][12]),
+            ("d.png", "Unsupported, \"format\"")
+        );
+    }
+
+    #[test]
+    fn sweep_csv_columns() {
+        let point = |value, passed| SweepPoint {
+            value,
+            passed,
+            qr_codes: passed as usize,
+            duration_ms: 1.0,
+        };
+        let series = |engine_name: &str, variant_name: &str, points| SweepSeries {
+            engine_name: engine_name.to_string(),
+            variant_name: variant_name.to_string(),
+            last_pass: None,
+            first_fail: None,
+            passes: 0,
+            points,
+        };
+        let report = SweepReport {
+            file_path: "a.png".to_string(),
+            axis: Axis::Blur,
+            label: "blur, sigma".to_string(),
+            values: vec![0.5, 1.0],
+            seed: 0,
+            ground_truth: false,
+            expected_payloads: 1,
+            series: vec![
+                series(
+                    "rqrr",
+                    "original",
+                    vec![point(0.5, true), point(1.0, false)],
+                ),
+                // Only generated for the larger image
+                series("rqrr", "adaptive", vec![point(0.5, true)]),
+            ],
+        };
+        assert_eq!(
+            sweep_csv(&report).unwrap(),
+            "\"blur, sigma\",rqrr/original,rqrr/adaptive\n0.5,1,1\n1,0,\n"
+        );
+    }
+
+    #[test]
+    fn markdown_escapes_cells() {
+        let results = [
+            scanned("a|b.png", "x|y", &["p|q"]),
+            scanned("c.png", "rqrr", &[]),
+            unscanned("bad.png", "Broken | file\nat line 2"),
+        ];
+        let text = markdown(&results, &stats_of(&results));
+        assert!(text.contains("`a\\|b.png`"));
+        assert!(text.contains("| x\\|y |"));
+        assert!(text.contains("- `bad.png`: Broken \\| file at line 2\n"));
+
+        let rows = table_rows(&text, "## Results");
+        assert_eq!(
+            rows[0],
+            "| File | Engine | QRs | Preprocess | Engine Time | Detect | Decode | Total | File Total |"
+        );
+        // Header, separator and one row per scanned file and engine
+        assert_eq!(rows.len(), 4);
+        // Escaped pipes do not add cells
+        assert!(
+            rows.iter()
+                .all(|row| row.replace("\\|", "").matches('|').count() == 10)
+        );
+        assert_eq!(table_rows(&text, "## Engines").len(), 4);
+    }
+
+    #[test]
+    fn bench_markdown_escapes_cells() {
+        let report = bench_report(&["a|b.png", "c.png"], &[("d.png", "Bad | data")]);
+        let text = bench_markdown(&report);
+        assert!(text.contains("CPU \\| 4 cores"));
+        assert!(text.contains("- `d.png`: Bad \\| data\n"));
+
+        let rows = table_rows(&text, "## Files");
+        assert!(rows[0].starts_with("| File | Engine | Codes | Failures | Min | Median |"));
+        assert_eq!(rows.len(), 6);
+        assert!(rows[2].starts_with("| `a\\|b.png` | rqrr | 1 | 0 | 1.00ms | 2.00ms |"));
+        assert!(rows[3].starts_with("| `a\\|b.png` | a\\|b |"));
+        assert!(
+            rows.iter()
+                .all(|row| row.replace("\\|", "").matches('|').count() == 13)
+        );
+        assert_eq!(table_rows(&text, "## All files").len(), 3);
+
+        // A single file has no table over all files
+        let single = bench_markdown(&bench_report(&["c.png"], &[]));
+        assert!(!single.contains("## All files") && !single.contains("## Failed files"));
+    }
+
+    #[test]
+    fn html_escapes_text() {
+        let results = [scanned(
+            "<a&\"b\">.png",
+            "rqrr",
+            &["<script>alert(\"&\")</script>"],
+        )];
+        let page = html(&results, &stats_of(&results));
+        assert!(page.contains("<td class=\"path\">&lt;a&amp;&quot;b&quot;&gt;.png</td>"));
+        assert!(page.contains("&lt;script&gt;alert(&quot;&amp;&quot;)&lt;/script&gt;"));
+        assert!(!page.contains("<script>alert"));
+        assert_eq!(page.matches("<tr><td class=\"path\">").count(), 1);
+        // Every engine decoded something, so nothing is shown as failing
+        assert!(!page.contains("Failing Images") && !page.contains("<figure>"));
+    }
+
+    #[test]
+    fn html_shows_failing_images() {
+        let dir = std::env::temp_dir().join(format!("qr-tester-report-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let image = dir.join("blank.png");
+        GrayImage::from_pixel(400, 200, Luma([255]))
+            .save(&image)
+            .unwrap();
+
+        let results = [
+            scanned(image.to_str().unwrap(), "rqrr", &[]),
+            scanned("found.png", "rqrr", &["ok"]),
+            unscanned("<missing>.png", "No such file"),
+        ];
+        let page = html(&results, &stats_of(&results));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(page.matches("<tr><td class=\"path\">").count(), 2);
+        assert_eq!(page.matches("<figure>").count(), 2);
+        // The image is decoded into a thumbnail; the missing file has none
+        assert_eq!(page.matches("<img src=\"data:image/png;base64,").count(), 1);
+        assert!(page.contains("<li class=\"bad\">rqrr: no codes</li>"));
+        assert_eq!(page.matches("<p class=\"bad\">No preview</p>").count(), 1);
+        assert!(page.contains("<code>&lt;missing&gt;.png</code>"));
+    }
+
+    #[test]
+    fn shipped_schemas_are_current() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("schemas");
+        for report in Report::value_variants() {
+            let name = report.to_possible_value().unwrap().get_name().to_string();
+            let path = dir.join(format!("{}.schema.json", name));
+            let shipped = std::fs::read_to_string(&path).unwrap();
+            assert!(
+                shipped.trim_end() == schema(*report).unwrap(),
+                "{} is out of date; regenerate it with `qr-tester schema {} > {}`",
+                path.display(),
+                name,
+                path.display()
+            );
+        }
+    }
+}
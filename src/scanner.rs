@@ -1,25 +1,77 @@
 use anyhow::{Context, Result};
-use bardecoder::default_decoder;
-use image::{DynamicImage, GrayImage};
+use image::DynamicImage;
 use log::{debug, error, info};
-use rxing::{
-    BinaryBitmap, DecodeHints, Exceptions, Luma8LuminanceSource, Reader, common::HybridBinarizer,
-    qrcode::QRCodeReader,
-};
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
 use walkdir::WalkDir;
-use zbar_pack::{Image as ZBarPackImage, ImageScanner as ZBarPackScanner};
 
-use crate::preprocessor::ImagePreprocessor;
-use crate::timer::{ScanStats, ScanTiming, Timer};
+use crate::degradation::DegradationChain;
+use crate::engine::{DecodedSymbol, Detection, EngineRegistry, StageTiming, panic_message};
+use crate::ground_truth::{Accuracy, GroundTruth, warn_unmatched};
+use crate::isolation::EngineFault;
+use crate::memory::{self, EngineMemory, MemoryUsage};
+use crate::payload::Charset;
+use crate::preprocessor::{ImagePreprocessor, MAX_DIMENSION, Pipeline};
+use crate::structured_append::{AppendedSet, reassemble};
+use crate::timer::{QrDetectionTiming, ScanStats, ScanTiming, Timer};
+
+/// How a single engine call on one image variant ended
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "status", content = "message", rename_all = "snake_case")]
+pub enum VariantOutcome {
+    /// Engine returned normally (possibly with no codes)
+    Ok,
+    /// Engine returned an error
+    Error(String),
+    /// Engine panicked
+    Panic(String),
+    /// Isolated worker process crashed
+    Crash(String),
+    /// Isolated worker did not answer in time
+    Timeout(String),
+}
+
+/// Result of one engine on one preprocessed image variant
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct VariantResult {
+    pub variant_name: String,
+    pub qr_codes: Vec<DecodedSymbol>,
+    pub duration_ms: f64,
+    /// Time locating symbols, for engines whose API separates it from decoding
+    pub detect_ms: Option<f64>,
+    /// Time decoding located symbols, for the same engines
+    pub decode_ms: Option<f64>,
+    /// Heap used by the engine call; `None` without `--memory` or if it did
+    /// not return normally
+    pub memory: Option<MemoryUsage>,
+    pub outcome: VariantOutcome,
+}
 
 /// Results from individual detection engine
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, JsonSchema)]
 pub struct EngineResult {
     pub engine_name: String,
-    pub qr_codes: Vec<String>,
+    /// Distinct symbols found across all variants, in discovery order
+    pub qr_codes: Vec<DecodedSymbol>,
     pub duration_ms: f64, // Time spent by this engine alone
+    /// Time locating symbols over all variants, where the engine reports it
+    pub detect_ms: Option<f64>,
+    /// Time decoding located symbols over all variants, where the engine reports it
+    pub decode_ms: Option<f64>,
+    /// Heap used over all variants: allocations add up, the peak is the
+    /// highest of any variant
+    pub memory: Option<MemoryUsage>,
+    /// Structured Append sets among `qr_codes`
+    pub structured_append: Vec<AppendedSet>,
+    /// Score of `qr_codes` against the file's ground truth, if it has any
+    pub accuracy: Option<Accuracy>,
+    /// Per-variant breakdown, in variant order
+    pub variants: Vec<VariantResult>,
 }
 
 /// Scan result for a single file
@@ -27,8 +79,12 @@ pub struct EngineResult {
 pub struct ScanResult {
     /// File path
     pub file_path: PathBuf,
-    /// Detected QR code data (deduplicated across all engines)
-    pub qr_codes: Vec<String>,
+    /// Detected QR codes (deduplicated across all engines)
+    pub qr_codes: Vec<DecodedSymbol>,
+    /// Structured Append sets among `qr_codes`, reassembled where complete
+    pub structured_append: Vec<AppendedSet>,
+    /// Score of `qr_codes` against the file's ground truth, if it has any
+    pub accuracy: Option<Accuracy>,
     /// Results from each detection engine
     pub engine_results: Vec<EngineResult>,
     /// Timing information
@@ -41,47 +97,192 @@ pub struct ScanResult {
 
 /// QR code scanner
 pub struct QrScanner {
+    /// Detection engines to run, in order
+    registry: EngineRegistry,
+    /// Report engine errors and panics as they happen
+    verbose: bool,
+    /// Number of files scanned concurrently in directory scans
+    jobs: usize,
+    /// Re-time files in a single-threaded pass after a parallel scan
+    serial_timing: bool,
+    /// Character set for payloads without an ECI that are not UTF-8
+    charset: Option<Charset>,
+    /// Degradations applied to each image before scanning
+    degradation: Option<DegradationChain>,
+    /// Preprocessed variants each engine scans
+    pipeline: Pipeline,
+    /// Expected payloads to score results against
+    ground_truth: GroundTruth,
     /// Statistics
     stats: ScanStats,
 }
 
 impl QrScanner {
-    pub fn new(_verbose: bool) -> Self {
+    /// Create a scanner that runs the engines in `registry`
+    pub fn new(registry: EngineRegistry, verbose: bool) -> Self {
         Self {
+            registry,
+            verbose,
+            jobs: 1,
+            serial_timing: false,
+            charset: None,
+            degradation: None,
+            pipeline: Pipeline::default(),
+            ground_truth: GroundTruth::default(),
             stats: ScanStats::new(),
         }
     }
 
+    /// Set the number of worker threads for directory scans (0 = one per CPU)
+    pub fn set_jobs(&mut self, jobs: usize) {
+        self.jobs = if jobs == 0 {
+            std::thread::available_parallelism().map_or(1, |n| n.get())
+        } else {
+            jobs
+        };
+    }
+
+    /// Report timings from a single-threaded pass when scanning in parallel,
+    /// so contention between workers does not skew the numbers
+    pub fn set_serial_timing(&mut self, serial_timing: bool) {
+        self.serial_timing = serial_timing;
+    }
+
+    /// Set the character set for payloads that have no ECI and are not UTF-8
+    pub fn set_charset(&mut self, charset: Option<Charset>) {
+        self.charset = charset;
+    }
+
+    /// Degrade each image this way before scanning it
+    pub fn set_degradation(&mut self, degradation: Option<DegradationChain>) {
+        self.degradation = degradation;
+    }
+
+    /// Scan these preprocessed variants of each image
+    pub fn set_pipeline(&mut self, pipeline: Pipeline) {
+        self.pipeline = pipeline;
+    }
+
+    /// Score results against a manifest (sidecar files are used either way)
+    pub fn set_ground_truth(&mut self, ground_truth: GroundTruth) {
+        self.ground_truth = ground_truth;
+    }
+
     /// Scan a single file
     pub fn scan_file(&mut self, path: &Path) -> Result<ScanResult> {
+        warn_unmatched(&self.ground_truth, &[path.to_path_buf()]);
+        let result = self.scan_path(path)?;
+        self.record(&result);
+        Ok(result)
+    }
+
+    /// Run every engine over the variants of an already decoded image,
+    /// without touching the statistics; returns (all_qr_codes, engine_results)
+    pub fn scan_image(
+        &self,
+        img: &DynamicImage,
+    ) -> Result<(Vec<DecodedSymbol>, Vec<EngineResult>)> {
+        self.detect_qr_codes(img, &mut QrDetectionTiming::new())
+    }
+
+    /// Add a finished scan to the statistics
+    fn record(&mut self, result: &ScanResult) {
+        self.stats.total_files += 1;
+
+        if !result.success {
+            self.stats.failed_scans += 1;
+            return;
+        }
+
+        self.stats.successful_scans += 1;
+        self.stats.total_duration += result.timing.total;
+
+        if !result.qr_codes.is_empty() {
+            self.stats.files_with_qr += 1;
+            self.stats.total_qr_codes += result.qr_codes.len();
+        }
+
+        if memory::enabled() {
+            for engine_result in &result.engine_results {
+                let pos = match self
+                    .stats
+                    .memory
+                    .iter()
+                    .position(|m| m.engine_name == engine_result.engine_name)
+                {
+                    Some(pos) => pos,
+                    None => {
+                        self.stats
+                            .memory
+                            .push(EngineMemory::new(&engine_result.engine_name));
+                        self.stats.memory.len() - 1
+                    }
+                };
+                for memory in engine_result
+                    .variants
+                    .iter()
+                    .filter_map(|v| v.memory.as_ref())
+                {
+                    self.stats.memory[pos].add(memory);
+                }
+            }
+        }
+
+        if let Some(accuracy) = &result.accuracy {
+            let engines: Vec<_> = result
+                .engine_results
+                .iter()
+                .filter_map(|e| Some((e.engine_name.as_str(), e.accuracy.as_ref()?)))
+                .collect();
+            self.stats
+                .accuracy
+                .get_or_insert_default()
+                .add(accuracy, &engines);
+        }
+    }
+
+    /// Scan a single file without touching the statistics
+    fn scan_path(&self, path: &Path) -> Result<ScanResult> {
         let total_timer = Timer::start();
         let mut timing = ScanTiming::new();
 
         debug!("Scanning file: {}", path.display());
+        let expected = self.ground_truth.expected(path)?;
 
         // Read and decode image
+        let (file_data, read_file) = Timer::time(|| fs::read(path));
         let file_data =
-            fs::read(path).with_context(|| format!("Failed to read file: {}", path.display()))?;
-        let img = image::load_from_memory(&file_data)
-            .with_context(|| format!("Failed to decode image: {}", path.display()))?;
+            file_data.with_context(|| format!("Failed to read file: {}", path.display()))?;
+        timing.read_file = read_file;
+        let (img, decode_image) = Timer::time(|| image::load_from_memory(&file_data));
+        let img = img.with_context(|| format!("Failed to decode image: {}", path.display()))?;
+        timing.decode_image = decode_image;
+
+        let img = match &self.degradation {
+            Some(chain) => {
+                let (degraded, degrade) = Timer::time(|| chain.apply(&img));
+                timing.degrade = degrade;
+                degraded.with_context(|| format!("Failed to degrade image: {}", path.display()))?
+            }
+            None => img,
+        };
 
         // QR detection with detailed timing
-        let (qr_codes, engine_results) = self.detect_qr_codes(&img, &mut timing.qr_detection)?;
+        let (qr_codes, mut engine_results) =
+            self.detect_qr_codes(&img, &mut timing.qr_detection)?;
 
         timing.total = total_timer.elapsed();
 
-        // Update statistics
-        self.stats.total_files += 1;
-        self.stats.successful_scans += 1;
-        self.stats.total_duration += timing.total;
-
-        if !qr_codes.is_empty() {
-            self.stats.files_with_qr += 1;
-            self.stats.total_qr_codes += qr_codes.len();
+        if let Some(expected) = &expected {
+            for engine_result in &mut engine_results {
+                engine_result.accuracy = Some(Accuracy::score(expected, &engine_result.qr_codes));
+            }
         }
 
         Ok(ScanResult {
             file_path: path.to_path_buf(),
+            structured_append: reassemble(&qr_codes, self.charset),
+            accuracy: expected.map(|expected| Accuracy::score(&expected, &qr_codes)),
             qr_codes,
             engine_results,
             timing,
@@ -96,27 +297,55 @@ impl QrScanner {
         engine_name: &str,
         variant_name: &str,
         detect_fn: F,
-    ) -> Result<Vec<String>>
+    ) -> (Detection, VariantOutcome)
     where
-        F: FnOnce() -> Result<Vec<String>> + std::panic::UnwindSafe,
+        F: FnOnce() -> Result<Detection>,
     {
-        let result = std::panic::catch_unwind(detect_fn);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(detect_fn));
 
         match result {
-            Ok(Ok(codes)) => Ok(codes),
+            Ok(Ok(detection)) => (detection, VariantOutcome::Ok),
+            Ok(Err(e)) if e.is::<EngineFault>() => {
+                error!("{} failed on variant {}: {}", engine_name, variant_name, e);
+                let outcome = match e.downcast::<EngineFault>() {
+                    Ok(EngineFault::Crashed(status)) => VariantOutcome::Crash(status),
+                    Ok(fault @ EngineFault::TimedOut(_)) => {
+                        VariantOutcome::Timeout(fault.to_string())
+                    }
+                    Ok(EngineFault::Panicked(msg)) => VariantOutcome::Panic(msg),
+                    Err(e) => VariantOutcome::Error(format!("{:#}", e)),
+                };
+                (Detection::default(), outcome)
+            }
             Ok(Err(e)) => {
                 debug!(
                     "{} failed on variant {}: {:?}",
                     engine_name, variant_name, e
                 );
-                Ok(Vec::new())
+                if self.verbose {
+                    eprintln!(
+                        "{} failed on variant {}: {:#}",
+                        engine_name, variant_name, e
+                    );
+                }
+                (
+                    Detection::default(),
+                    VariantOutcome::Error(format!("{:#}", e)),
+                )
             }
             Err(panic_info) => {
                 error!(
                     "{} panicked on variant {} (likely a bug in the library): {:?}",
                     engine_name, variant_name, panic_info
                 );
-                Ok(Vec::new())
+                let message = panic_message(panic_info.as_ref());
+                if self.verbose {
+                    eprintln!(
+                        "{} panicked on variant {}: {}",
+                        engine_name, variant_name, message
+                    );
+                }
+                (Detection::default(), VariantOutcome::Panic(message))
             }
         }
     }
@@ -126,439 +355,416 @@ impl QrScanner {
     fn detect_qr_codes(
         &self,
         img: &DynamicImage,
-        timing: &mut crate::timer::QrDetectionTiming,
-    ) -> Result<(Vec<String>, Vec<EngineResult>)> {
+        timing: &mut QrDetectionTiming,
+    ) -> Result<(Vec<DecodedSymbol>, Vec<EngineResult>)> {
         let total_timer = Timer::start();
 
         // Step 0: Resize large images for better performance and detection
-        let gray_timer = Timer::start();
         let width = img.width();
         let height = img.height();
-        let max_dimension = width.max(height);
-
-        let working_img = if max_dimension > 2000 {
-            let scale = 2000.0 / max_dimension as f32;
-            debug!(
-                "Resizing large image ({}x{}) by {:.2}x",
-                width, height, scale
-            );
-            ImagePreprocessor::resize(img, scale)
-        } else {
-            img.clone()
-        };
+        let (working_img, resize) =
+            Timer::time(|| ImagePreprocessor::limit_size(img, MAX_DIMENSION));
+        timing.resize = resize;
+
+        // Engines report corners in working image coordinates
+        let scale_x = width as f32 / working_img.width() as f32;
+        let scale_y = height as f32 / working_img.height() as f32;
 
         // Step 1: Convert to grayscale and preprocess
-        let variants = ImagePreprocessor::generate_variants(&working_img);
-        timing.to_grayscale = gray_timer.elapsed();
+        let (variants, variant_timings) = self.pipeline.run_timed(&working_img);
+        timing.variants = variant_timings;
         debug!(
             "Image preprocessing completed, generated {} variants in {:.2}ms",
             variants.len(),
-            timing.to_ms(timing.to_grayscale)
+            timing.to_ms(timing.preprocess())
         );
 
-        let mut all_results = std::collections::HashSet::new();
+        let mut all_results = Vec::new();
         let mut engine_results = Vec::new();
 
-        // Step 2: Try rqrr (fast)
-        let prepare_timer = Timer::start();
-        let rqrr_timer = Timer::start();
-        let mut rqrr_codes = std::collections::HashSet::new();
-        for (variant_name, gray_img) in &variants {
-            debug!("Trying rqrr with variant: {}", variant_name);
-            let gray_img_clone = gray_img.clone();
-            match self.safe_detect("rqrr", variant_name, move || {
-                self.detect_with_rqrr(&gray_img_clone)
-            }) {
-                Ok(codes) if !codes.is_empty() => {
-                    debug!(
-                        "rqrr found {} codes with variant: {}",
-                        codes.len(),
-                        variant_name
-                    );
-                    rqrr_codes.extend(codes);
+        // Step 2: Run every registered engine over every variant
+        let engines_timer = Timer::start();
+        for engine in self.registry.engines() {
+            let engine_name = engine.name();
+            let engine_timer = Timer::start();
+            let mut engine_codes = Vec::new();
+            let mut engine_stages: Option<StageTiming> = None;
+            let mut engine_memory: Option<MemoryUsage> = None;
+            let capabilities = engine.capabilities();
+            debug!("Trying {} for detection ({:?})", engine_name, capabilities);
+            let mut variant_results = Vec::with_capacity(variants.len());
+            for (variant_name, gray_img) in &variants {
+                debug!("Trying {} with variant: {}", engine_name, variant_name);
+                let variant_timer = Timer::start();
+                let (detection, outcome) = self.safe_detect(engine_name, variant_name, || {
+                    engine.detect_measured(gray_img)
+                });
+                let Detection {
+                    symbols: mut codes,
+                    stages,
+                    memory,
+                } = detection;
+                let variant_duration = variant_timer.elapsed();
+                for symbol in &mut codes {
+                    symbol.scale(scale_x, scale_y);
+                    symbol.raw_bytes = capabilities.raw_bytes;
                 }
-                _ => {}
-            }
-        }
-        let rqrr_duration = rqrr_timer.elapsed();
-        all_results.extend(rqrr_codes.iter().cloned());
-        engine_results.push(EngineResult {
-            engine_name: "rqrr".to_string(),
-            qr_codes: rqrr_codes.into_iter().collect(),
-            duration_ms: timing.to_ms(rqrr_duration),
-        });
-        timing.prepare_image = prepare_timer.elapsed();
-        timing.detect_grids = prepare_timer.elapsed();
-
-        // Step 3: Try rxing (more robust)
-        let decode_timer = Timer::start();
-        let rxing_timer = Timer::start();
-        let mut rxing_codes = std::collections::HashSet::new();
-        debug!("Trying rxing for more robust detection");
-        for (variant_name, gray_img) in &variants {
-            debug!("Trying rxing with variant: {}", variant_name);
-            let gray_img_clone = gray_img.clone();
-            let working_img_clone = working_img.clone();
-            match self.safe_detect("rxing", variant_name, move || {
-                self.detect_with_rxing(&gray_img_clone, &working_img_clone)
-            }) {
-                Ok(codes) if !codes.is_empty() => {
+                if !codes.is_empty() {
                     debug!(
-                        "rxing found {} codes with variant: {}",
+                        "{} found {} codes with variant: {}",
+                        engine_name,
                         codes.len(),
                         variant_name
                     );
-                    rxing_codes.extend(codes);
+                    merge_symbols(&mut engine_codes, &codes);
                 }
-                _ => {}
-            }
-        }
-        let rxing_duration = rxing_timer.elapsed();
-        all_results.extend(rxing_codes.iter().cloned());
-        engine_results.push(EngineResult {
-            engine_name: "rxing".to_string(),
-            qr_codes: rxing_codes.into_iter().collect(),
-            duration_ms: timing.to_ms(rxing_duration),
-        });
-
-        // Step 4: Try quircs (pure Rust library)
-        let quircs_timer = Timer::start();
-        let mut quircs_codes = std::collections::HashSet::new();
-        debug!("Trying quircs for detection");
-        for (variant_name, gray_img) in &variants {
-            debug!("Trying quircs with variant: {}", variant_name);
-            let gray_img_clone = gray_img.clone();
-            match self.safe_detect("quircs", variant_name, move || {
-                self.detect_with_quircs(&gray_img_clone)
-            }) {
-                Ok(codes) if !codes.is_empty() => {
-                    debug!(
-                        "quircs found {} codes with variant: {}",
-                        codes.len(),
-                        variant_name
-                    );
-                    quircs_codes.extend(codes);
+                if let Some(stages) = stages {
+                    let total = engine_stages.get_or_insert_default();
+                    total.detect += stages.detect;
+                    total.decode += stages.decode;
                 }
-                _ => {}
-            }
-        }
-        let quircs_duration = quircs_timer.elapsed();
-        all_results.extend(quircs_codes.iter().cloned());
-        engine_results.push(EngineResult {
-            engine_name: "quircs".to_string(),
-            qr_codes: quircs_codes.into_iter().collect(),
-            duration_ms: timing.to_ms(quircs_duration),
-        });
-
-        // Step 5: Try bardecoder (image-based decoder)
-        let bardecoder_timer = Timer::start();
-        let mut bardecoder_codes = std::collections::HashSet::new();
-        debug!("Trying bardecoder for detection");
-        for (variant_name, gray_img) in &variants {
-            debug!("Trying bardecoder with variant: {}", variant_name);
-            let gray_img_clone = gray_img.clone();
-            match self.safe_detect("bardecoder", variant_name, move || {
-                self.detect_with_bardecoder(&gray_img_clone)
-            }) {
-                Ok(codes) if !codes.is_empty() => {
-                    debug!(
-                        "bardecoder found {} codes with variant: {}",
-                        codes.len(),
-                        variant_name
-                    );
-                    bardecoder_codes.extend(codes);
+                if let Some(memory) = &memory {
+                    engine_memory.get_or_insert_default().add(memory);
                 }
-                _ => {}
+                variant_results.push(VariantResult {
+                    variant_name: variant_name.clone(),
+                    qr_codes: codes,
+                    duration_ms: timing.to_ms(variant_duration),
+                    detect_ms: stages.map(|s| timing.to_ms(s.detect)),
+                    decode_ms: stages.map(|s| timing.to_ms(s.decode)),
+                    memory,
+                    outcome,
+                });
             }
+            let engine_duration = engine_timer.elapsed();
+            merge_symbols(&mut all_results, &engine_codes);
+            engine_results.push(EngineResult {
+                engine_name: engine_name.to_string(),
+                structured_append: reassemble(&engine_codes, self.charset),
+                accuracy: None,
+                qr_codes: engine_codes,
+                duration_ms: timing.to_ms(engine_duration),
+                detect_ms: engine_stages.map(|s| timing.to_ms(s.detect)),
+                decode_ms: engine_stages.map(|s| timing.to_ms(s.decode)),
+                memory: engine_memory,
+                variants: variant_results,
+            });
         }
-        let bardecoder_duration = bardecoder_timer.elapsed();
-        all_results.extend(bardecoder_codes.iter().cloned());
-        engine_results.push(EngineResult {
-            engine_name: "bardecoder".to_string(),
-            qr_codes: bardecoder_codes.into_iter().collect(),
-            duration_ms: timing.to_ms(bardecoder_duration),
-        });
+        timing.engines = engines_timer.elapsed();
 
-        // Step 6: Try zbar-pack (safe vendored ZBar bindings)
-        let zbar_pack_timer = Timer::start();
-        let mut zbar_pack_codes = std::collections::HashSet::new();
-        debug!("Trying zbar-pack for detection");
-        for (variant_name, gray_img) in &variants {
-            debug!("Trying zbar-pack with variant: {}", variant_name);
-            let gray_img_clone = gray_img.clone();
-            match self.safe_detect("zbar-pack", variant_name, move || {
-                self.detect_with_zbar_pack(&gray_img_clone)
-            }) {
-                Ok(codes) if !codes.is_empty() => {
-                    debug!(
-                        "zbar-pack found {} codes with variant: {}",
-                        codes.len(),
-                        variant_name
-                    );
-                    zbar_pack_codes.extend(codes);
-                }
-                _ => {}
+        // Decode texts only now: rqrr reports no ECI and quircs no segment
+        // modes, so each symbol is decoded with what all engines saw of it
+        for symbol in &mut all_results {
+            let metadata = symbol.metadata.clone();
+            symbol.decode_text(&metadata, self.charset);
+        }
+        for engine_result in &mut engine_results {
+            decode_texts(&mut engine_result.qr_codes, &all_results, self.charset);
+            for variant in &mut engine_result.variants {
+                decode_texts(&mut variant.qr_codes, &all_results, self.charset);
             }
         }
-        let zbar_pack_duration = zbar_pack_timer.elapsed();
-        all_results.extend(zbar_pack_codes.iter().cloned());
-        engine_results.push(EngineResult {
-            engine_name: "zbar-pack".to_string(),
-            qr_codes: zbar_pack_codes.into_iter().collect(),
-            duration_ms: timing.to_ms(zbar_pack_duration),
-        });
 
-        timing.decode_qr = decode_timer.elapsed();
         timing.total = total_timer.elapsed();
 
-        let results: Vec<String> = all_results.into_iter().collect();
         debug!(
             "Total QR codes found: {} in {:.2}ms",
-            results.len(),
+            all_results.len(),
             timing.to_ms(timing.total)
         );
 
-        Ok((results, engine_results))
+        Ok((all_results, engine_results))
     }
 
-    /// Detect QR codes using rqrr (fast, good for standard QR codes)
-    fn detect_with_rqrr(&self, gray_img: &GrayImage) -> Result<Vec<String>> {
-        let mut img_data = rqrr::PreparedImage::prepare(gray_img.clone());
-        let grids = img_data.detect_grids();
-
-        debug!("rqrr detected {} grids", grids.len());
-
-        let mut results = Vec::new();
-        for (i, grid) in grids.iter().enumerate() {
-            match grid.decode() {
-                Ok((_meta, content)) => {
-                    debug!("rqrr grid {} decoded successfully", i);
-                    results.push(content);
-                }
-                Err(e) => {
-                    debug!("rqrr grid {} decode failed: {:?}", i, e);
-                }
+    /// Scan a file, turning failures into a failed result
+    fn scan_path_or_failure(&self, path: &Path) -> ScanResult {
+        self.scan_path(path).unwrap_or_else(|e| {
+            error!("Failed to scan file {}: {}", path.display(), e);
+            ScanResult {
+                file_path: path.to_path_buf(),
+                qr_codes: Vec::new(),
+                structured_append: Vec::new(),
+                accuracy: None,
+                engine_results: Vec::new(),
+                timing: ScanTiming::new(),
+                success: false,
+                error: Some(e.to_string()),
             }
-        }
-
-        if !results.is_empty() {
-            debug!(
-                "rqrr successfully decoded {}/{} grids",
-                results.len(),
-                grids.len()
-            );
-        }
-
-        Ok(results)
+        })
     }
 
-    /// Detect QR codes using rxing (robust, handles deformed/multiple QR codes)
-    fn detect_with_rxing(&self, gray_img: &GrayImage, _img: &DynamicImage) -> Result<Vec<String>> {
-        let width = gray_img.width();
-        let height = gray_img.height();
-
-        // Convert to rxing format
-        let luminance_source = Luma8LuminanceSource::new(gray_img.as_raw().clone(), width, height);
-
-        let mut bitmap = BinaryBitmap::new(HybridBinarizer::new(luminance_source));
-
-        // Configure hints
-        let hints = DecodeHints::default();
-
-        let mut reader = QRCodeReader::new();
+    /// Scan all image files in a directory, handing each result to
+    /// `on_result` in path order as soon as it and all earlier ones are
+    /// final (with serial timing, as the re-timing pass reaches it). An
+    /// error from `on_result` stops the scan.
+    pub fn scan_directory(
+        &mut self,
+        dir: &Path,
+        mut on_result: impl FnMut(&ScanResult) -> Result<()>,
+    ) -> Result<Vec<ScanResult>> {
+        info!("Starting directory scan: {}", dir.display());
 
-        let mut results = Vec::new();
+        let dir_timer = Timer::start();
 
-        // Try to decode
-        match reader.decode_with_hints(&mut bitmap, &hints) {
-            Ok(result) => {
-                results.push(result.getText().to_string());
-            }
-            Err(e) => {
-                if !matches!(e, Exceptions::NotFoundException(_)) {
-                    debug!("rxing decode failed: {:?}", e);
+        let paths = collect_images(dir);
+        warn_unmatched(&self.ground_truth, &paths);
+
+        let jobs = self.jobs.min(paths.len()).max(1);
+        let results = if jobs > 1 && self.serial_timing {
+            info!("Scanning {} files with {} threads", paths.len(), jobs);
+            let mut results = self.scan_parallel(&paths, jobs, &mut |_| Ok(()))?;
+            info!("Re-timing {} files on a single thread", results.len());
+            for result in &mut results {
+                if result.success {
+                    self.retime(result);
                 }
+                on_result(result)?;
+            }
+            results
+        } else if jobs > 1 {
+            info!("Scanning {} files with {} threads", paths.len(), jobs);
+            self.scan_parallel(&paths, jobs, &mut on_result)?
+        } else {
+            let mut results = Vec::with_capacity(paths.len());
+            for path in &paths {
+                let result = self.scan_path_or_failure(path);
+                on_result(&result)?;
+                results.push(result);
             }
+            results
+        };
+
+        for result in &results {
+            self.record(result);
         }
+        self.stats.threads = jobs;
+
+        let dir_elapsed = dir_timer.elapsed();
+        info!(
+            "Directory scan completed in {:.2}ms",
+            dir_elapsed.as_secs_f64() * 1000.0
+        );
 
         Ok(results)
     }
 
-    /// Detect QR codes using quircs (pure Rust, based on quirc library)
-    fn detect_with_quircs(&self, gray_img: &GrayImage) -> Result<Vec<String>> {
-        let width = gray_img.width() as usize;
-        let height = gray_img.height() as usize;
-
-        // Create quircs decoder
-        let mut decoder = quircs::Quirc::new();
-
-        // Identify QR codes in the image
-        let codes = decoder.identify(width, height, gray_img.as_raw());
-
-        let mut results = Vec::new();
-        let mut count = 0;
-
-        for code in codes {
-            count += 1;
-            match code {
-                Ok(code) => match code.decode() {
-                    Ok(decoded) => {
-                        if let Ok(text) = std::str::from_utf8(&decoded.payload) {
-                            debug!("quircs decoded QR code successfully");
-                            results.push(text.to_string());
+    /// Scan `paths` on `jobs` worker threads, passing results to `on_result`
+    /// and returning them in path order
+    ///
+    /// Results that finish before those of earlier paths wait in a reorder
+    /// buffer keyed by path index, so a slow file holds back the output of
+    /// later ones, but not their scanning.
+    fn scan_parallel(
+        &self,
+        paths: &[PathBuf],
+        jobs: usize,
+        on_result: &mut dyn FnMut(&ScanResult) -> Result<()>,
+    ) -> Result<Vec<ScanResult>> {
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        let mut results: Vec<ScanResult> = Vec::with_capacity(paths.len());
+        let mut pending: BTreeMap<usize, ScanResult> = BTreeMap::new();
+        std::thread::scope(|scope| {
+            for _ in 0..jobs {
+                let sender = sender.clone();
+                let next = &next;
+                scope.spawn(move || {
+                    loop {
+                        let idx = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(path) = paths.get(idx) else {
+                            break;
+                        };
+                        if sender.send((idx, self.scan_path_or_failure(path))).is_err() {
+                            break;
                         }
                     }
-                    Err(e) => {
-                        debug!("quircs decode failed: {:?}", e);
+                });
+            }
+            drop(sender);
+
+            for (idx, result) in receiver {
+                pending.insert(idx, result);
+                while let Some(result) = pending.remove(&results.len()) {
+                    if let Err(e) = on_result(&result) {
+                        // Workers stop at their next file; dropping the
+                        // receiver discards results still in flight
+                        next.store(paths.len(), Ordering::Relaxed);
+                        return Err(e);
                     }
-                },
-                Err(e) => {
-                    debug!("quircs extract failed: {:?}", e);
+                    results.push(result);
                 }
             }
-        }
-
-        if count > 0 {
-            debug!("quircs identified {} codes", count);
-        }
+            Ok(())
+        })?;
 
         Ok(results)
     }
 
-    /// Detect QR codes using bardecoder (image-based decoder)
-    fn detect_with_bardecoder(&self, gray_img: &GrayImage) -> Result<Vec<String>> {
-        // bardecoder uses image 0.24, we use 0.25
-        // Convert via raw pixels to avoid slow PNG encode/decode
-        let width = gray_img.width();
-        let height = gray_img.height();
-        let pixels = gray_img.as_raw().clone();
-
-        // Create image 0.24 GrayImage from raw pixels
-        let gray_v24 = image_v24::GrayImage::from_raw(width, height, pixels)
-            .context("Failed to create image_v24::GrayImage")?;
-
-        // Convert to DynamicImage for bardecoder
-        let img_v24 = image_v24::DynamicImage::ImageLuma8(gray_v24);
-
-        let decoder = default_decoder();
-        let decoded_results = decoder.decode(&img_v24);
-
-        debug!("bardecoder found {} results", decoded_results.len());
+    /// Replace the timings and heap figures of `result` with those of a
+    /// fresh scan of the same file; codes, scores and errors stay as found
+    fn retime(&self, result: &mut ScanResult) {
+        let Ok(fresh) = self.scan_path(&result.file_path) else {
+            return;
+        };
 
-        let mut results = Vec::new();
-        for result in decoded_results {
-            match result {
-                Ok(text) => {
-                    debug!("bardecoder decoded QR code successfully");
-                    results.push(text);
-                }
-                Err(e) => {
-                    debug!("bardecoder decode failed: {:?}", e);
-                }
+        result.timing = fresh.timing;
+        for (engine, fresh_engine) in result.engine_results.iter_mut().zip(fresh.engine_results) {
+            engine.duration_ms = fresh_engine.duration_ms;
+            engine.detect_ms = fresh_engine.detect_ms;
+            engine.decode_ms = fresh_engine.decode_ms;
+            engine.memory = fresh_engine.memory;
+            for (variant, fresh_variant) in engine.variants.iter_mut().zip(fresh_engine.variants) {
+                variant.duration_ms = fresh_variant.duration_ms;
+                variant.detect_ms = fresh_variant.detect_ms;
+                variant.decode_ms = fresh_variant.decode_ms;
+                variant.memory = fresh_variant.memory;
             }
         }
-
-        Ok(results)
     }
 
-    /// Detect QR codes using zbar-pack (safe vendored ZBar bindings)
-    fn detect_with_zbar_pack(&self, gray_img: &GrayImage) -> Result<Vec<String>> {
-        // Ensure verbosity is set to 0 (ZBar might reset it internally)
-        zbar_pack::set_verbosity(0);
-
-        let width = gray_img.width();
-        let height = gray_img.height();
-
-        // Create zbar-pack image and scanner
-        let image = ZBarPackImage::from_gray(gray_img.as_raw(), width, height)
-            .map_err(|e| anyhow::anyhow!("zbar-pack image creation failed: {:?}", e))?;
-
-        let mut scanner = ZBarPackScanner::new()
-            .map_err(|e| anyhow::anyhow!("zbar-pack scanner creation failed: {:?}", e))?;
-
-        // Scan for barcodes
-        let symbols = scanner
-            .scan_image(&image)
-            .map_err(|e| anyhow::anyhow!("zbar-pack scan failed: {:?}", e))?;
+    /// Get statistics
+    pub fn stats(&mut self) -> &ScanStats {
+        self.stats.finalize();
+        &self.stats
+    }
+}
 
-        let mut results = Vec::new();
-        for symbol in symbols {
-            // Only include QR codes
-            if symbol.symbol_type() == zbar_pack::SymbolType::QRCODE {
-                debug!("zbar-pack decoded QR code successfully");
-                results.push(symbol.data().to_string());
+/// Append the symbols of `new` that are not in `into` yet, keeping order
+fn merge_symbols(into: &mut Vec<DecodedSymbol>, new: &[DecodedSymbol]) {
+    for symbol in new {
+        match into.iter_mut().find(|s| s.same_symbol(symbol)) {
+            // Keep the first sighting, but fill in what it did not report,
+            // including the raw bytes behind text an engine transcoded
+            Some(known) => {
+                if !known.raw_bytes && symbol.raw_bytes {
+                    known.payload = symbol.payload.clone();
+                    known.raw_bytes = true;
+                }
+                if known.corners.is_none() {
+                    known.corners = symbol.corners;
+                }
+                known.metadata.fill_missing(&symbol.metadata);
             }
+            None => into.push(symbol.clone()),
         }
-
-        debug!("zbar-pack found {} QR codes", results.len());
-        Ok(results)
     }
+}
 
-    /// Scan all image files in a directory
-    pub fn scan_directory(&mut self, dir: &Path) -> Result<Vec<ScanResult>> {
-        info!("Starting directory scan: {}", dir.display());
-
-        let dir_timer = Timer::start();
-        let mut results = Vec::new();
-
-        // Supported image extensions
-        let image_extensions = ["png", "jpg", "jpeg", "bmp", "gif", "webp", "tiff", "tif"];
+/// Image files under `dir`, sorted so output order does not depend on the
+/// filesystem or on which worker finishes first
+pub fn collect_images(dir: &Path) -> Vec<PathBuf> {
+    // Supported image extensions
+    let image_extensions = ["png", "jpg", "jpeg", "bmp", "gif", "webp", "tiff", "tif"];
+
+    let mut paths = Vec::new();
+    for entry in WalkDir::new(dir)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
 
-        for entry in WalkDir::new(dir)
-            .follow_links(true)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
+        // Check if it's an image file
+        if !path.is_file() {
+            continue;
+        }
 
-            // Check if it's an image file
-            if !path.is_file() {
+        if let Some(ext) = path.extension() {
+            let ext_str = ext.to_string_lossy().to_lowercase();
+            if !image_extensions.contains(&ext_str.as_str()) {
                 continue;
             }
+        } else {
+            continue;
+        }
 
-            if let Some(ext) = path.extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if !image_extensions.contains(&ext_str.as_str()) {
-                    continue;
-                }
-            } else {
-                continue;
-            }
+        paths.push(path.to_path_buf());
+    }
+    paths
+}
 
-            // Scan file
-            match self.scan_file(path) {
-                Ok(result) => {
-                    results.push(result);
-                }
-                Err(e) => {
-                    error!("Failed to scan file {}: {}", path.display(), e);
-                    self.stats.total_files += 1;
-                    self.stats.failed_scans += 1;
-
-                    results.push(ScanResult {
-                        file_path: path.to_path_buf(),
-                        qr_codes: Vec::new(),
-                        engine_results: Vec::new(),
-                        timing: ScanTiming::new(),
-                        success: false,
-                        error: Some(e.to_string()),
-                    });
-                }
-            }
-        }
+/// Decode the text of `symbols` with the merged metadata of the matching
+/// symbol in `merged`
+fn decode_texts(
+    symbols: &mut [DecodedSymbol],
+    merged: &[DecodedSymbol],
+    fallback: Option<Charset>,
+) {
+    for symbol in symbols {
+        let metadata = merged
+            .iter()
+            .find(|known| known.same_symbol(symbol))
+            .map_or(&symbol.metadata, |known| &known.metadata)
+            .clone();
+        symbol.decode_text(&metadata, fallback);
+    }
+}
 
-        let dir_elapsed = dir_timer.elapsed();
-        info!(
-            "Directory scan completed in {:.2}ms",
-            dir_elapsed.as_secs_f64() * 1000.0
-        );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::SymbolMetadata;
+    use crate::payload::Payload;
+
+    #[test]
+    fn merge_counts_a_transcoded_symbol_once() {
+        // ZBar's UTF-8 rendering of a Shift JIS symbol with an ECI, then
+        // rqrr's raw bytes of it
+        let transcoded = DecodedSymbol {
+            raw_bytes: false,
+            ..DecodedSymbol::new(Payload::from_bytes("テスト".as_bytes().to_vec()))
+        };
+        let raw = DecodedSymbol::new(Payload::from_bytes(vec![
+            0x83, 0x65, 0x83, 0x58, 0x83, 0x67,
+        ]))
+        .with_metadata(SymbolMetadata {
+            eci: Some(20),
+            ..SymbolMetadata::default()
+        });
 
-        Ok(results)
+        let mut merged = Vec::new();
+        merge_symbols(&mut merged, std::slice::from_ref(&transcoded));
+        merge_symbols(&mut merged, std::slice::from_ref(&raw));
+        assert_eq!(merged.len(), 1);
+        // The raw bytes replace the transcoded ones
+        assert_eq!(merged[0].payload.bytes, raw.payload.bytes);
+        assert!(merged[0].raw_bytes);
+
+        let metadata = merged[0].metadata.clone();
+        merged[0].decode_text(&metadata, None);
+        assert_eq!(merged[0].payload.text.as_deref(), Some("テスト"));
     }
 
-    /// Get statistics
-    pub fn stats(&mut self) -> &ScanStats {
-        self.stats.finalize();
-        &self.stats
+    #[test]
+    fn parallel_results_keep_path_order() {
+        let dir = std::env::temp_dir().join(format!("qr-tester-order-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let qr = qrcodegen::QrCode::encode_text("slow", qrcodegen::QrCodeEcc::Medium).unwrap();
+        let image = dir.join("code.png");
+        crate::generator::render(&qr, 8, 4).save(&image).unwrap();
+
+        // The image takes far longer than the missing files after it
+        let mut paths = vec![image];
+        paths.extend((0..8).map(|i| dir.join(format!("missing-{}.png", i))));
+        let registry = EngineRegistry::with_default_engines()
+            .select(&["rqrr".to_string()], &[])
+            .unwrap();
+        let scanner = QrScanner::new(registry, false);
+
+        let mut seen = Vec::new();
+        let results = scanner
+            .scan_parallel(&paths, 4, &mut |result| {
+                seen.push(result.file_path.clone());
+                Ok(())
+            })
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(seen, paths);
+        let returned: Vec<PathBuf> = results.iter().map(|r| r.file_path.clone()).collect();
+        assert_eq!(returned, paths);
+        assert!(results[0].success && results[0].qr_codes.len() == 1);
+        assert!(results[1..].iter().all(|r| !r.success));
     }
 }
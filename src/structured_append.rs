@@ -0,0 +1,277 @@
+//! Structured Append reassembly
+//!
+//! A payload split with Structured Append is spread over up to 16 QR
+//! symbols. Each symbol carries its position in the set, the set size and a
+//! parity byte (the XOR of every payload byte of the whole set) that is
+//! shared by all symbols of the set and tells sets apart.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::engine::DecodedSymbol;
+use crate::metadata::SymbolMetadata;
+use crate::payload::{Charset, Payload};
+
+/// The symbols of one Structured Append set found in an image
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct AppendedSet {
+    /// Parity byte shared by the set
+    pub parity: u8,
+    /// Number of symbols in the set
+    pub total: u8,
+    /// Sequence indices found, in the order the symbols were decoded
+    pub found: Vec<u8>,
+    /// Sequence indices not found
+    pub missing: Vec<u8>,
+    /// Whether the symbols were decoded in sequence order
+    pub in_order: bool,
+    /// Sequence indices seen with more than one payload (the first is used)
+    pub conflicting: Vec<u8>,
+    /// Reassembled payload; `None` while the set is incomplete
+    pub payload: Option<Payload>,
+    /// Whether the reassembled payload matches the parity byte; `None`
+    /// while the set is incomplete
+    pub parity_ok: Option<bool>,
+}
+
+impl AppendedSet {
+    pub fn is_complete(&self) -> bool {
+        self.missing.is_empty()
+    }
+}
+
+/// Group the Structured Append symbols among `symbols` into sets and
+/// reassemble the complete ones
+///
+/// The reassembled text is decoded like a single symbol's, with the first
+/// ECI of the set and `fallback`. Sets are returned in the order their
+/// first symbol appears.
+pub fn reassemble(symbols: &[DecodedSymbol], fallback: Option<Charset>) -> Vec<AppendedSet> {
+    let mut sets: Vec<(AppendedSet, Vec<(u8, &DecodedSymbol)>)> = Vec::new();
+
+    for symbol in symbols {
+        let Some(header) = symbol.metadata.structured_append else {
+            continue;
+        };
+
+        let pos = match sets
+            .iter()
+            .position(|(set, _)| set.parity == header.parity && set.total == header.total)
+        {
+            Some(pos) => pos,
+            None => {
+                sets.push((
+                    AppendedSet {
+                        parity: header.parity,
+                        total: header.total,
+                        found: Vec::new(),
+                        missing: Vec::new(),
+                        in_order: true,
+                        conflicting: Vec::new(),
+                        payload: None,
+                        parity_ok: None,
+                    },
+                    Vec::new(),
+                ));
+                sets.len() - 1
+            }
+        };
+        let (set, parts) = &mut sets[pos];
+
+        match parts.iter().find(|(index, _)| *index == header.index) {
+            Some((_, part)) => {
+                if part.payload.bytes != symbol.payload.bytes
+                    && !set.conflicting.contains(&header.index)
+                {
+                    set.conflicting.push(header.index);
+                }
+            }
+            None => {
+                if set.found.last().is_some_and(|&last| last > header.index) {
+                    set.in_order = false;
+                }
+                set.found.push(header.index);
+                parts.push((header.index, symbol));
+            }
+        }
+    }
+
+    sets.into_iter()
+        .map(|(mut set, mut parts)| {
+            set.missing = (0..set.total)
+                .filter(|index| !set.found.contains(index))
+                .collect();
+
+            if set.is_complete() {
+                parts.sort_by_key(|(index, _)| *index);
+                let bytes: Vec<u8> = parts
+                    .iter()
+                    .flat_map(|(_, part)| part.payload.bytes.iter().copied())
+                    .collect();
+                set.parity_ok = Some(bytes.iter().fold(0, |acc, b| acc ^ b) == set.parity);
+
+                let metadata = SymbolMetadata {
+                    eci: parts.iter().find_map(|(_, part)| part.metadata.eci),
+                    modes: parts
+                        .iter()
+                        .map(|(_, part)| part.metadata.modes.clone())
+                        .collect::<Option<Vec<_>>>()
+                        .map(|modes| modes.concat()),
+                    ..SymbolMetadata::default()
+                };
+                let mut payload = Payload::from_bytes(bytes);
+                payload.decode_text(&metadata, fallback);
+                set.payload = Some(payload);
+            }
+            set
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::StructuredAppend;
+
+    fn parity(parts: &[&[u8]]) -> u8 {
+        parts
+            .iter()
+            .flat_map(|part| part.iter())
+            .fold(0, |acc, b| acc ^ b)
+    }
+
+    fn part(bytes: &[u8], index: u8, total: u8, parity: u8) -> DecodedSymbol {
+        DecodedSymbol::new(Payload::from_bytes(bytes.to_vec())).with_metadata(SymbolMetadata {
+            structured_append: Some(StructuredAppend {
+                index,
+                total,
+                parity,
+            }),
+            ..SymbolMetadata::default()
+        })
+    }
+
+    fn split(text: &str, total: u8) -> Vec<DecodedSymbol> {
+        let chunks: Vec<&[u8]> = text
+            .as_bytes()
+            .chunks(text.len().div_ceil(total as usize))
+            .collect();
+        let parity = parity(&chunks);
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| part(chunk, index as u8, total, parity))
+            .collect()
+    }
+
+    #[test]
+    fn complete_in_order() {
+        let sets = reassemble(&split("Hello, appended world", 3), None);
+        assert_eq!(sets.len(), 1);
+        let set = &sets[0];
+        assert_eq!((set.total, set.found.as_slice()), (3, [0, 1, 2].as_slice()));
+        assert!(set.is_complete() && set.in_order && set.conflicting.is_empty());
+        assert_eq!(set.parity_ok, Some(true));
+        assert_eq!(
+            set.payload.as_ref().unwrap().text.as_deref(),
+            Some("Hello, appended world")
+        );
+    }
+
+    #[test]
+    fn complete_out_of_order() {
+        let mut symbols = split("Hello, appended world", 3);
+        symbols.swap(0, 2);
+        let set = &reassemble(&symbols, None)[0];
+        assert_eq!(set.found, [2, 1, 0]);
+        assert!(set.is_complete() && !set.in_order);
+        assert_eq!(set.parity_ok, Some(true));
+        assert_eq!(
+            set.payload.as_ref().unwrap().text.as_deref(),
+            Some("Hello, appended world")
+        );
+    }
+
+    #[test]
+    fn missing_part() {
+        let mut symbols = split("Hello, appended world", 3);
+        symbols.remove(1);
+        let set = &reassemble(&symbols, None)[0];
+        assert_eq!(
+            (set.found.as_slice(), set.missing.as_slice()),
+            ([0, 2].as_slice(), [1].as_slice())
+        );
+        assert!(!set.is_complete());
+        assert!(set.payload.is_none() && set.parity_ok.is_none());
+    }
+
+    #[test]
+    fn conflicting_duplicate() {
+        let mut symbols = split("Hello, appended world", 3);
+        let parity = symbols[0].metadata.structured_append.unwrap().parity;
+        // The same copy again is not a conflict, different data is
+        symbols.push(symbols[1].clone());
+        symbols.push(part(b"other", 1, 3, parity));
+        symbols.push(part(b"more", 1, 3, parity));
+        let set = &reassemble(&symbols, None)[0];
+        assert_eq!(set.found, [0, 1, 2]);
+        assert_eq!(set.conflicting, [1]);
+        // The first sighting is used
+        assert_eq!(
+            set.payload.as_ref().unwrap().text.as_deref(),
+            Some("Hello, appended world")
+        );
+    }
+
+    #[test]
+    fn parity_mismatch() {
+        let wrong = parity(&[b"Hello, ", b"world"]) ^ 0xff;
+        let symbols = [part(b"Hello, ", 0, 2, wrong), part(b"world", 1, 2, wrong)];
+        let set = &reassemble(&symbols, None)[0];
+        assert!(set.is_complete());
+        assert_eq!(set.parity_ok, Some(false));
+        assert_eq!(
+            set.payload.as_ref().unwrap().text.as_deref(),
+            Some("Hello, world")
+        );
+    }
+
+    #[test]
+    fn sets_are_told_apart_by_parity() {
+        let mut symbols = split("first set", 2);
+        symbols.insert(1, split("second set", 2).remove(1));
+        let sets = reassemble(&symbols, None);
+        assert_eq!(sets.len(), 2);
+        assert!(sets[0].is_complete());
+        assert_eq!(
+            (sets[1].found.as_slice(), sets[1].missing.as_slice()),
+            ([1].as_slice(), [0].as_slice())
+        );
+    }
+
+    #[test]
+    fn mixed_eci() {
+        // "テスト" in Shift JIS, split inside the text, with the ECI only in
+        // the second symbol; the set is decoded with the first ECI found
+        let (head, tail): (&[u8], &[u8]) = (&[0x83, 0x65], &[0x83, 0x58, 0x83, 0x67]);
+        let parity = parity(&[head, tail]);
+        let mut second = part(tail, 1, 2, parity);
+        second.metadata.eci = Some(20);
+        let symbols = [part(head, 0, 2, parity), second];
+
+        let payload = reassemble(&symbols, None)[0].payload.clone().unwrap();
+        assert_eq!(payload.text.as_deref(), Some("テスト"));
+        assert_eq!(
+            payload.encoding.as_deref(),
+            Some(Charset::from_name("Shift_JIS").unwrap().name())
+        );
+
+        // An earlier ECI takes precedence over a later one, and UTF-8
+        // cannot decode these bytes
+        let mut first = part(head, 0, 2, parity);
+        first.metadata.eci = Some(26);
+        let symbols = [first, symbols[1].clone()];
+        let payload = reassemble(&symbols, None)[0].payload.clone().unwrap();
+        assert_eq!(payload.text, None);
+    }
+}
@@ -0,0 +1,255 @@
+//! Robustness sweeps
+//!
+//! A sweep degrades one image along a single axis, in steps of increasing
+//! strength, and scans every step with every engine. For each engine and
+//! variant it reports the last value that still passed before the first
+//! failure, and that first failing value: the engine's breaking point on
+//! that axis.
+//!
+//! A step passes if exactly the expected payloads were decoded. They come
+//! from the ground truth if the image has any, else from what all engines
+//! decode in the undegraded image.
+
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use image::DynamicImage;
+use log::debug;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::degradation::{Degradation, DegradationChain};
+use crate::ground_truth::{Accuracy, Expected};
+use crate::scanner::QrScanner;
+
+/// Name of the per-engine row that takes the codes of all variants together
+pub const ALL_VARIANTS: &str = "all";
+
+/// Degradation a sweep varies
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Axis {
+    /// Gaussian blur sigma, in pixels
+    Blur,
+    /// Clockwise rotation, in degrees
+    Rotate,
+    /// Standard deviation of Gaussian noise, in gray levels
+    Noise,
+    /// Downscaling factor
+    Downscale,
+    /// Share of the image covered by an occluder, in percent
+    Occlude,
+}
+
+impl Axis {
+    /// Default (from, to, step)
+    pub fn default_range(self) -> (f64, f64, f64) {
+        match self {
+            Axis::Blur => (0.0, 8.0, 0.5),
+            Axis::Rotate => (0.0, 90.0, 5.0),
+            Axis::Noise => (0.0, 128.0, 8.0),
+            Axis::Downscale => (1.0, 16.0, 1.0),
+            Axis::Occlude => (0.0, 50.0, 2.5),
+        }
+    }
+
+    /// Name of the swept value with its unit, e.g. for a CSV header
+    pub fn label(self) -> &'static str {
+        match self {
+            Axis::Blur => "blur_sigma",
+            Axis::Rotate => "rotation_deg",
+            Axis::Noise => "noise_sigma",
+            Axis::Downscale => "downscale_factor",
+            Axis::Occlude => "occlusion_percent",
+        }
+    }
+
+    /// Degradation at strength `value`; `None` where it leaves the image as is
+    fn degradation(self, value: f64) -> Result<Option<Degradation>> {
+        let (name, neutral) = match self {
+            Axis::Blur => ("blur", 0.0),
+            Axis::Rotate => ("rotate", 0.0),
+            Axis::Noise => ("noise", 0.0),
+            Axis::Downscale => ("downscale", 1.0),
+            Axis::Occlude => ("occlude", 0.0),
+        };
+        if value == neutral {
+            return Ok(None);
+        }
+        format!("{}:{}", name, value).parse().map(Some)
+    }
+}
+
+/// What to sweep
+#[derive(Debug, Clone)]
+pub struct SweepSettings {
+    pub axis: Axis,
+    pub from: f64,
+    pub to: f64,
+    pub step: f64,
+    /// Seed of the random parts of the degradation (noise, occluder)
+    pub seed: u64,
+}
+
+impl SweepSettings {
+    /// Values from `from` to `to` (inclusive if a step lands on it)
+    pub fn values(&self) -> Result<Vec<f64>> {
+        if self.step.is_nan() || self.step <= 0.0 {
+            bail!("The step must be above 0");
+        }
+        if self.to < self.from {
+            bail!("The range must not end before it starts");
+        }
+        let steps = ((self.to - self.from) / self.step + 1e-9).floor() as usize;
+        if steps >= 10_000 {
+            bail!("The range has more than 10000 steps; pick a larger step");
+        }
+        // Rounded so steps like 0.1 print as 0.3 rather than 0.30000000000000004
+        Ok((0..=steps)
+            .map(|i| ((self.from + i as f64 * self.step) * 1e6).round() / 1e6)
+            .collect())
+    }
+}
+
+/// Results of a sweep
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SweepReport {
+    pub file_path: String,
+    pub axis: Axis,
+    /// Name of the swept value with its unit
+    pub label: String,
+    pub values: Vec<f64>,
+    pub seed: u64,
+    /// Whether the expected payloads came from the ground truth rather than
+    /// the undegraded image
+    pub ground_truth: bool,
+    pub expected_payloads: usize,
+    /// One per engine and variant, plus each engine's `all` row, in run order
+    pub series: Vec<SweepSeries>,
+}
+
+/// One engine and variant over the whole sweep
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SweepSeries {
+    pub engine_name: String,
+    /// Variant name, or `all` for the codes of all variants together
+    pub variant_name: String,
+    /// Last value that passed before the first failure; `None` if the first
+    /// value already failed
+    pub last_pass: Option<f64>,
+    /// First value that failed, or at which the variant was not generated;
+    /// `None` if all passed
+    pub first_fail: Option<f64>,
+    /// Values that passed, including any after the first failure
+    pub passes: usize,
+    /// One per value the variant was scanned at; variants only generated
+    /// for larger images (adaptive) may miss some
+    pub points: Vec<SweepPoint>,
+}
+
+/// Outcome of one engine and variant at one value
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct SweepPoint {
+    pub value: f64,
+    pub passed: bool,
+    /// Distinct codes decoded
+    pub qr_codes: usize,
+    pub duration_ms: f64,
+}
+
+/// Sweep `img` along `settings.axis`, scanning each step with `scanner`;
+/// without `expected` payloads, the undegraded image's codes are expected
+pub fn run(
+    scanner: &QrScanner,
+    path: &Path,
+    img: &DynamicImage,
+    expected: Option<Vec<Expected>>,
+    settings: &SweepSettings,
+) -> Result<SweepReport> {
+    let values = settings.values()?;
+    let steps = values
+        .iter()
+        .map(|&value| settings.axis.degradation(value))
+        .collect::<Result<Vec<_>>>()?;
+    let ground_truth = expected.is_some();
+    let expected = match expected {
+        Some(expected) => expected,
+        None => {
+            let (codes, _) = scanner.scan_image(img)?;
+            if codes.is_empty() {
+                bail!(
+                    "No engine decodes the undegraded image; pass --manifest with its expected payloads"
+                );
+            }
+            codes
+                .iter()
+                .map(|symbol| Expected::Bytes(symbol.payload.bytes.clone()))
+                .collect()
+        }
+    };
+
+    let mut series: Vec<SweepSeries> = Vec::new();
+    for (&value, step) in values.iter().zip(steps) {
+        let degraded = match step {
+            Some(step) => DegradationChain {
+                steps: vec![step],
+                seed: settings.seed,
+            }
+            .apply(img)
+            .with_context(|| format!("Failed to degrade at {}", value))?,
+            None => img.clone(),
+        };
+        let (_, engine_results) = scanner.scan_image(&degraded)?;
+        debug!("Scanned {} = {}", settings.axis.label(), value);
+
+        for engine in &engine_results {
+            let rows = std::iter::once((ALL_VARIANTS, &engine.qr_codes, engine.duration_ms)).chain(
+                engine
+                    .variants
+                    .iter()
+                    .map(|v| (v.variant_name.as_str(), &v.qr_codes, v.duration_ms)),
+            );
+            for (variant_name, codes, duration_ms) in rows {
+                let point = SweepPoint {
+                    value,
+                    passed: Accuracy::score(&expected, codes).exact(),
+                    qr_codes: codes.len(),
+                    duration_ms,
+                };
+                let position = series.iter().position(|s| {
+                    s.engine_name == engine.engine_name && s.variant_name == variant_name
+                });
+                match position {
+                    Some(i) => series[i].points.push(point),
+                    None => series.push(SweepSeries {
+                        engine_name: engine.engine_name.clone(),
+                        variant_name: variant_name.to_string(),
+                        last_pass: None,
+                        first_fail: None,
+                        passes: 0,
+                        points: vec![point],
+                    }),
+                }
+            }
+        }
+    }
+
+    // A variant that was not generated at a value counts as failing there
+    for s in &mut series {
+        let passed = |value: f64| s.points.iter().any(|p| p.value == value && p.passed);
+        s.passes = values.iter().filter(|&&v| passed(v)).count();
+        s.first_fail = values.iter().copied().find(|&v| !passed(v));
+        s.last_pass = values.iter().copied().take_while(|&v| passed(v)).last();
+    }
+
+    Ok(SweepReport {
+        file_path: path.display().to_string(),
+        axis: settings.axis,
+        label: settings.axis.label().to_string(),
+        values,
+        seed: settings.seed,
+        ground_truth,
+        expected_payloads: expected.len(),
+        series,
+    })
+}
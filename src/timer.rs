@@ -1,32 +1,72 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
+use crate::ground_truth::AccuracyReport;
+use crate::memory::{EngineMemory, MemoryUsage};
+
+/// Serde format of durations in reports: float milliseconds
+pub mod duration_ms {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(duration.as_secs_f64() * 1000.0)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let ms = f64::deserialize(deserializer)?;
+        Duration::try_from_secs_f64(ms / 1000.0).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Time and heap spent generating one preprocessed variant from its input
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VariantTiming {
+    pub variant_name: String,
+    #[serde(rename = "duration_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub duration: Duration,
+    /// Includes the variant image itself, which stays allocated; `None`
+    /// without `--memory`
+    pub memory: Option<MemoryUsage>,
+}
+
 /// Detailed timing for QR detection stages
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct QrDetectionTiming {
-    /// Time spent converting to grayscale
-    pub to_grayscale: Duration,
-    /// Time spent preparing image for detection
-    pub prepare_image: Duration,
-    /// Time spent detecting QR code grids
-    pub detect_grids: Duration,
-    /// Time spent decoding QR codes
-    pub decode_qr: Duration,
+    /// Time spent scaling large images down (copying smaller ones)
+    #[serde(rename = "resize_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub resize: Duration,
+    /// Time spent generating each variant, in variant order; `original` is
+    /// the grayscale conversion
+    pub variants: Vec<VariantTiming>,
+    /// Time spent running all engines over all variants
+    #[serde(rename = "engines_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub engines: Duration,
     /// Total detection time
+    #[serde(rename = "total_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
     pub total: Duration,
 }
 
 impl QrDetectionTiming {
     pub fn new() -> Self {
         Self {
-            to_grayscale: Duration::ZERO,
-            prepare_image: Duration::ZERO,
-            detect_grids: Duration::ZERO,
-            decode_qr: Duration::ZERO,
+            resize: Duration::ZERO,
+            variants: Vec::new(),
+            engines: Duration::ZERO,
             total: Duration::ZERO,
         }
     }
 
+    /// Time spent resizing and generating variants
+    pub fn preprocess(&self) -> Duration {
+        self.resize + self.variants.iter().map(|v| v.duration).sum::<Duration>()
+    }
+
     /// Convert duration to milliseconds
     pub fn to_ms(&self, duration: Duration) -> f64 {
         duration.as_secs_f64() * 1000.0
@@ -34,17 +74,34 @@ impl QrDetectionTiming {
 }
 
 /// Timing information for scanning a single file
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ScanTiming {
+    /// Time spent reading the file
+    #[serde(rename = "read_file_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub read_file: Duration,
+    /// Time spent decoding the image format
+    #[serde(rename = "decode_image_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub decode_image: Duration,
+    /// Time spent applying `--degrade` (zero without it)
+    #[serde(rename = "degrade_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
+    pub degrade: Duration,
     /// Detailed QR detection timing
     pub qr_detection: QrDetectionTiming,
     /// Total processing time
+    #[serde(rename = "total_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
     pub total: Duration,
 }
 
 impl ScanTiming {
     pub fn new() -> Self {
         Self {
+            read_file: Duration::ZERO,
+            decode_image: Duration::ZERO,
+            degrade: Duration::ZERO,
             qr_detection: QrDetectionTiming::new(),
             total: Duration::ZERO,
         }
@@ -66,10 +123,17 @@ impl Timer {
     pub fn elapsed(&self) -> Duration {
         self.start.elapsed()
     }
+
+    /// Run `f` and measure how long it took
+    pub fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let timer = Self::start();
+        let value = f();
+        (value, timer.elapsed())
+    }
 }
 
 /// Overall scan statistics
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct ScanStats {
     /// Total number of files
     pub total_files: usize,
@@ -82,9 +146,20 @@ pub struct ScanStats {
     /// Total number of QR codes found
     pub total_qr_codes: usize,
     /// Total time spent scanning
+    #[serde(rename = "total_duration_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
     pub total_duration: Duration,
     /// Average time per file
+    #[serde(rename = "avg_duration_per_file_ms", with = "duration_ms")]
+    #[schemars(with = "f64")]
     pub avg_duration_per_file: Duration,
+    /// Number of threads files were scanned on
+    pub threads: usize,
+    /// Scores against the ground truth, if any scanned file has some
+    pub accuracy: Option<AccuracyReport>,
+    /// Heap use per engine over all calls, in run order; empty without
+    /// `--memory`
+    pub memory: Vec<EngineMemory>,
 }
 
 impl ScanStats {
@@ -97,6 +172,9 @@ impl ScanStats {
             total_qr_codes: 0,
             total_duration: Duration::ZERO,
             avg_duration_per_file: Duration::ZERO,
+            threads: 1,
+            accuracy: None,
+            memory: Vec::new(),
         }
     }
 
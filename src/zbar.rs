@@ -0,0 +1,137 @@
+//! Minimal safe wrapper around ZBar's C API
+//!
+//! zbar-pack returns symbol data as `&str`, which is unsound in binary mode
+//! where ZBar hands back undecoded (e.g. Shift JIS) bytes, and it does not
+//! expose symbol locations. The `zbar-pack` engine therefore drives the same
+//! vendored ZBar through zbar-sys and copies out raw bytes and points.
+
+use anyhow::{Result, bail};
+use std::os::raw::c_ulong;
+use std::ptr::NonNull;
+use zbar_sys as ffi;
+
+pub use ffi::{ZBAR_CFG_BINARY, ZBAR_QRCODE};
+
+/// A symbol ZBar decoded, copied out of ZBar's memory
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub symbol_type: ffi::zbar_symbol_type_t,
+    /// Payload as ZBar returned it, not necessarily UTF-8
+    pub data: Vec<u8>,
+    /// Points ZBar located the symbol by, in image pixels
+    pub location: Vec<(i32, i32)>,
+}
+
+/// ZBar image scanner
+pub struct Scanner {
+    raw: NonNull<ffi::zbar_image_scanner_t>,
+}
+
+impl Scanner {
+    pub fn new() -> Result<Self> {
+        // SAFETY: no preconditions; a null return is handled
+        let raw = unsafe { ffi::zbar_image_scanner_create() };
+        match NonNull::new(raw) {
+            Some(raw) => Ok(Self { raw }),
+            None => bail!("ZBar scanner creation failed"),
+        }
+    }
+
+    /// Set a `ZBAR_CFG_*` option for one symbol type
+    pub fn set_config(
+        &mut self,
+        symbol_type: ffi::zbar_symbol_type_t,
+        config: ffi::zbar_config_t,
+        value: i32,
+    ) -> Result<()> {
+        // SAFETY: `raw` is a live scanner
+        let status = unsafe {
+            ffi::zbar_image_scanner_set_config(self.raw.as_ptr(), symbol_type, config, value)
+        };
+        if status != 0 {
+            bail!("ZBar rejected config {} = {}", config, value);
+        }
+        Ok(())
+    }
+
+    /// Scan an 8-bit grayscale image stored row by row
+    pub fn scan_gray(&mut self, pixels: &[u8], width: u32, height: u32) -> Result<Vec<Symbol>> {
+        if pixels.len() != width as usize * height as usize {
+            bail!(
+                "{} bytes do not make a {}x{} grayscale image",
+                pixels.len(),
+                width,
+                height
+            );
+        }
+
+        // SAFETY: no preconditions; a null return is handled
+        let image = NonNull::new(unsafe { ffi::zbar_image_create() })
+            .map(Image)
+            .ok_or_else(|| anyhow::anyhow!("ZBar image creation failed"))?;
+        // Y800: 8-bit grayscale
+        let format = u32::from_le_bytes(*b"Y800") as c_ulong;
+
+        // SAFETY: `image` and `raw` are live; ZBar only reads `pixels`,
+        // which outlive the image, and frees nothing without a cleanup
+        // handler. Symbols belong to the image and are copied before it is
+        // destroyed.
+        unsafe {
+            ffi::zbar_image_set_format(image.0.as_ptr(), format);
+            ffi::zbar_image_set_size(image.0.as_ptr(), width, height);
+            ffi::zbar_image_set_data(
+                image.0.as_ptr(),
+                pixels.as_ptr().cast(),
+                pixels.len() as c_ulong,
+                None,
+            );
+            if ffi::zbar_scan_image(self.raw.as_ptr(), image.0.as_ptr()) < 0 {
+                bail!("ZBar scan failed");
+            }
+
+            let mut symbols = Vec::new();
+            let mut symbol = ffi::zbar_image_first_symbol(image.0.as_ptr());
+            while !symbol.is_null() {
+                let data = ffi::zbar_symbol_get_data(symbol);
+                let length = ffi::zbar_symbol_get_data_length(symbol) as usize;
+                let data = if data.is_null() {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(data.cast::<u8>(), length).to_vec()
+                };
+                let location = (0..ffi::zbar_symbol_get_loc_size(symbol))
+                    .map(|i| {
+                        (
+                            ffi::zbar_symbol_get_loc_x(symbol, i),
+                            ffi::zbar_symbol_get_loc_y(symbol, i),
+                        )
+                    })
+                    .collect();
+                symbols.push(Symbol {
+                    symbol_type: ffi::zbar_symbol_get_type(symbol),
+                    data,
+                    location,
+                });
+                symbol = ffi::zbar_symbol_next(symbol);
+            }
+            Ok(symbols)
+        }
+    }
+}
+
+impl Drop for Scanner {
+    fn drop(&mut self) {
+        // SAFETY: `raw` came from `zbar_image_scanner_create` and is dropped once
+        unsafe { ffi::zbar_image_scanner_destroy(self.raw.as_ptr()) }
+    }
+}
+
+/// Owned ZBar image, destroyed on drop
+struct Image(NonNull<ffi::zbar_image_t>);
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `zbar_image_create` and is dropped once
+        unsafe { ffi::zbar_image_destroy(self.0.as_ptr()) }
+    }
+}